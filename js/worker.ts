@@ -84,7 +84,7 @@ self.onmessage = async (event: MessageEvent<WorkerRequest>) => {
             self.postMessage(response, [croppedBuffer]);
         } else if (request.action === 'resize') {
             const { width, height, filter } = request.params;
-            const resizedData = resize_image(data, width, height, filter);
+            const resizedData = resize_image(data, width, height, filter, false, false);
             const metadata = read_image_metadata(resizedData) as WasmImageMetadata;
 
             const resizedBuffer = resizedData.buffer as ArrayBuffer;
@@ -123,7 +123,9 @@ self.onmessage = async (event: MessageEvent<WorkerRequest>) => {
                 gammaMapped,
                 shadows,
                 highlights,
-                vibrance
+                vibrance,
+                'hsl',
+                'additive'
             );
             const metadata = read_image_metadata(adjustedData) as WasmImageMetadata;
 
@@ -1,9 +1,13 @@
 use wasm_bindgen::prelude::*;
-use image::{DynamicImage, Rgba, RgbaImage};
-use image::imageops::{brighten, contrast, huerotate};
+use image::{DynamicImage, GrayAlphaImage, GrayImage, ImageFormat, Luma, LumaA, Rgba, RgbaImage};
+use image::imageops::{brighten, huerotate, FilterType};
 use std::io::Cursor;
 
-use crate::common::decode_image;
+use crate::common::{decode_image, linear_to_srgb, srgb_to_linear};
+use crate::filters::clarity;
+use crate::parallel::for_each_pixel;
+use crate::timing::StageTimer;
+use crate::transforms::parse_filter;
 
 /// Convert RGB (0-255) to HSL (h: 0-360, s: 0-1, l: 0-1)
 fn rgb_to_hsl(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
@@ -82,33 +86,170 @@ fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
     (r, g, b)
 }
 
+/// Convert RGB (0-255) to HSV (h: 0-360, s: 0-1, v: 0-1)
+fn rgb_to_hsv(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let r = r as f32 / 255.0;
+    let g = g as f32 / 255.0;
+    let b = b as f32 / 255.0;
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let d = max - min;
+
+    if d.abs() < f32::EPSILON {
+        return (0.0, 0.0, max);
+    }
+
+    let s = d / max;
+    let h = if (max - r).abs() < f32::EPSILON {
+        ((g - b) / d).rem_euclid(6.0)
+    } else if (max - g).abs() < f32::EPSILON {
+        (b - r) / d + 2.0
+    } else {
+        (r - g) / d + 4.0
+    };
+
+    (h * 60.0, s, max)
+}
+
+/// Convert HSV (h: 0-360, s: 0-1, v: 0-1) to RGB (0-255)
+fn hsv_to_rgb(h: f32, s: f32, v: f32) -> (u8, u8, u8) {
+    let c = v * s;
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let m = v - c;
+
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    let to_u8 = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    (to_u8(r), to_u8(g), to_u8(b))
+}
+
 /// Calculate luminance from RGB values (0-1 range)
-fn luminance(r: f32, g: f32, b: f32) -> f32 {
+pub(crate) fn luminance(r: f32, g: f32, b: f32) -> f32 {
     0.299 * r + 0.587 * g + 0.114 * b
 }
 
+/// Color model in which saturation is scaled.
+///
+/// HSL keeps lightness fixed, so boosting saturation pushes midtones towards pure
+/// hues and desaturating collapses them towards mid-gray. HSV keeps the brightest
+/// channel (value) fixed instead, which matches editors that use HSV sliders:
+/// desaturated colors move towards a light gray of the same value, and highlights
+/// keep their brightness. LCh scales chroma in CIELAB and keeps perceptual lightness,
+/// so saturated colors neither darken nor wash out; the Lab round trip costs a few
+/// cube roots per pixel, roughly 2-3x slower than HSL.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum SaturationModel {
+    Hsl,
+    Hsv,
+    Lch,
+}
+
+impl SaturationModel {
+    pub fn parse(model: &str) -> Result<Self, String> {
+        match model {
+            "" | "hsl" => Ok(SaturationModel::Hsl),
+            "hsv" => Ok(SaturationModel::Hsv),
+            "lch" => Ok(SaturationModel::Lch),
+            _ => Err(format!("Invalid saturation model: {}", model)),
+        }
+    }
+}
+
+/// How the brightness slider is applied.
+///
+/// Additive shifts every channel by the same amount, so at high values whole
+/// regions flatten to white. Multiplicative scales channels by `1 + brightness/100`,
+/// which keeps relative tones and only clips what was already bright. Unlike the
+/// `exposure` control (stops, applied before shadows/highlights/gamma), it acts as a
+/// linear factor at the brightness stage of the pipeline.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum BrightnessMode {
+    Additive,
+    Multiplicative,
+}
+
+impl BrightnessMode {
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode {
+            "" | "additive" => Ok(BrightnessMode::Additive),
+            "multiplicative" => Ok(BrightnessMode::Multiplicative),
+            _ => Err(format!("Invalid brightness mode: {}", mode)),
+        }
+    }
+}
+
+/// CIELAB companding function (D65 white, components relative to the white point)
+fn lab_f(t: f32) -> f32 {
+    const DELTA: f32 = 6.0 / 29.0;
+    if t > DELTA * DELTA * DELTA { t.cbrt() } else { t / (3.0 * DELTA * DELTA) + 4.0 / 29.0 }
+}
+
+fn lab_f_inverse(t: f32) -> f32 {
+    const DELTA: f32 = 6.0 / 29.0;
+    if t > DELTA { t * t * t } else { 3.0 * DELTA * DELTA * (t - 4.0 / 29.0) }
+}
+
+/// Scale CIELAB chroma by `factor` at constant lightness and hue. Works on the Lab
+/// companded values directly: L depends only on f(Y), and a/b are differences from
+/// it, so scaling those differences scales chroma. Out-of-gamut results are clipped.
+fn scale_lab_chroma(r: u8, g: u8, b: u8, factor: f32, linear: &[f32; 256]) -> (u8, u8, u8) {
+    const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+    let (r, g, b) = (linear[r as usize], linear[g as usize], linear[b as usize]);
+    let x = 0.4124564 * r + 0.3575761 * g + 0.1804375 * b;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.072175 * b;
+    let z = 0.0193339 * r + 0.119192 * g + 0.9503041 * b;
+
+    let fy = lab_f(y / WHITE[1]);
+    let fx = fy + (lab_f(x / WHITE[0]) - fy) * factor;
+    let fz = fy + (lab_f(z / WHITE[2]) - fy) * factor;
+    let (x, y, z) = (lab_f_inverse(fx) * WHITE[0], y, lab_f_inverse(fz) * WHITE[2]);
+
+    let to_u8 = |v: f32| (linear_to_srgb(v.clamp(0.0, 1.0)) * 255.0).round() as u8;
+    (
+        to_u8(3.240454 * x - 1.537139 * y - 0.4985314 * z),
+        to_u8(-0.969266 * x + 1.876011 * y + 0.041556 * z),
+        to_u8(0.0556434 * x - 0.2040259 * y + 1.057225 * z),
+    )
+}
+
 /// Apply saturation adjustment to an image
-fn apply_saturation(img: &RgbaImage, factor: f32) -> RgbaImage {
-    let (width, height) = img.dimensions();
-    let mut output = RgbaImage::new(width, height);
+fn apply_saturation(img: &RgbaImage, factor: f32, model: SaturationModel) -> RgbaImage {
+    let mut output = img.clone();
+    let linear: [f32; 256] = std::array::from_fn(|v| srgb_to_linear(v as f32 / 255.0));
 
-    for (x, y, pixel) in img.enumerate_pixels() {
+    for_each_pixel(&mut output, |pixel| {
         let [r, g, b, a] = pixel.0;
-        let (h, s, l) = rgb_to_hsl(r, g, b);
-        let new_s = (s * factor).clamp(0.0, 1.0);
-        let (new_r, new_g, new_b) = hsl_to_rgb(h, new_s, l);
-        output.put_pixel(x, y, Rgba([new_r, new_g, new_b, a]));
-    }
+        let (new_r, new_g, new_b) = match model {
+            SaturationModel::Hsl => {
+                let (h, s, l) = rgb_to_hsl(r, g, b);
+                hsl_to_rgb(h, (s * factor).clamp(0.0, 1.0), l)
+            }
+            SaturationModel::Hsv => {
+                let (h, s, v) = rgb_to_hsv(r, g, b);
+                hsv_to_rgb(h, (s * factor).clamp(0.0, 1.0), v)
+            }
+            SaturationModel::Lch => scale_lab_chroma(r, g, b, factor, &linear),
+        };
+        *pixel = Rgba([new_r, new_g, new_b, a]);
+    });
 
     output
 }
 
 /// Apply vibrance adjustment (affects less saturated colors more)
 fn apply_vibrance(img: &RgbaImage, amount: f32) -> RgbaImage {
-    let (width, height) = img.dimensions();
-    let mut output = RgbaImage::new(width, height);
+    let mut output = img.clone();
 
-    for (x, y, pixel) in img.enumerate_pixels() {
+    for_each_pixel(&mut output, |pixel| {
         let [r, g, b, a] = pixel.0;
         let (h, s, l) = rgb_to_hsl(r, g, b);
 
@@ -117,52 +258,80 @@ fn apply_vibrance(img: &RgbaImage, amount: f32) -> RgbaImage {
         let new_s = (s + adjustment).clamp(0.0, 1.0);
 
         let (new_r, new_g, new_b) = hsl_to_rgb(h, new_s, l);
-        output.put_pixel(x, y, Rgba([new_r, new_g, new_b, a]));
-    }
+        *pixel = Rgba([new_r, new_g, new_b, a]);
+    });
 
     output
 }
 
 /// Apply exposure adjustment (in stops, like a camera)
 fn apply_exposure(img: &RgbaImage, stops: f32) -> RgbaImage {
-    let (width, height) = img.dimensions();
-    let mut output = RgbaImage::new(width, height);
-    let multiplier = 2.0_f32.powf(stops);
+    scale_channels(img, 2.0_f32.powf(stops))
+}
+
+/// Multiply the RGB channels by a constant factor, clipping at 255
+fn scale_channels(img: &RgbaImage, multiplier: f32) -> RgbaImage {
+    let mut output = img.clone();
 
-    for (x, y, pixel) in img.enumerate_pixels() {
+    for_each_pixel(&mut output, |pixel| {
         let [r, g, b, a] = pixel.0;
         let new_r = ((r as f32 * multiplier).round() as u16).min(255) as u8;
         let new_g = ((g as f32 * multiplier).round() as u16).min(255) as u8;
         let new_b = ((b as f32 * multiplier).round() as u16).min(255) as u8;
-        output.put_pixel(x, y, Rgba([new_r, new_g, new_b, a]));
-    }
+        *pixel = Rgba([new_r, new_g, new_b, a]);
+    });
+
+    output
+}
+
+/// Apply contrast around mid-gray with the same curve as `imageops::contrast`, which
+/// would also stretch the alpha channel (making transparent pixels visible)
+fn apply_contrast(img: &RgbaImage, amount: f32) -> RgbaImage {
+    let mut output = img.clone();
+    let percent = ((100.0 + amount) / 100.0).powi(2);
+
+    for_each_pixel(&mut output, |pixel| {
+        let [r, g, b, a] = pixel.0;
+        let stretch = |c: u8| (((c as f32 / 255.0 - 0.5) * percent + 0.5) * 255.0).clamp(0.0, 255.0) as u8;
+        *pixel = Rgba([stretch(r), stretch(g), stretch(b), a]);
+    });
 
     output
 }
 
 /// Apply gamma correction
 fn apply_gamma(img: &RgbaImage, gamma: f32) -> RgbaImage {
-    let (width, height) = img.dimensions();
-    let mut output = RgbaImage::new(width, height);
+    let mut output = img.clone();
     let inv_gamma = 1.0 / gamma;
 
-    for (x, y, pixel) in img.enumerate_pixels() {
+    for_each_pixel(&mut output, |pixel| {
         let [r, g, b, a] = pixel.0;
         let new_r = ((r as f32 / 255.0).powf(inv_gamma) * 255.0).round() as u8;
         let new_g = ((g as f32 / 255.0).powf(inv_gamma) * 255.0).round() as u8;
         let new_b = ((b as f32 / 255.0).powf(inv_gamma) * 255.0).round() as u8;
-        output.put_pixel(x, y, Rgba([new_r, new_g, new_b, a]));
-    }
+        *pixel = Rgba([new_r, new_g, new_b, a]);
+    });
 
     output
 }
 
+/// How strongly the shadows control affects a pixel of luminance `lum` (0-1): full
+/// at black, fading linearly to nothing at mid-gray
+fn shadow_weight(lum: f32) -> f32 {
+    (1.0 - lum * 2.0).max(0.0)
+}
+
+/// How strongly the highlights control affects a pixel: nothing up to mid-gray,
+/// rising linearly to full at white
+fn highlight_weight(lum: f32) -> f32 {
+    ((lum - 0.5) * 2.0).max(0.0)
+}
+
 /// Apply shadows adjustment (affects dark areas)
 fn apply_shadows(img: &RgbaImage, amount: f32) -> RgbaImage {
-    let (width, height) = img.dimensions();
-    let mut output = RgbaImage::new(width, height);
+    let mut output = img.clone();
 
-    for (x, y, pixel) in img.enumerate_pixels() {
+    for_each_pixel(&mut output, |pixel| {
         let [r, g, b, a] = pixel.0;
         let rf = r as f32 / 255.0;
         let gf = g as f32 / 255.0;
@@ -170,26 +339,23 @@ fn apply_shadows(img: &RgbaImage, amount: f32) -> RgbaImage {
 
         let lum = luminance(rf, gf, bf);
 
-        // Apply adjustment only to dark areas (shadows), with smooth falloff
-        let shadow_weight = (1.0 - lum * 2.0).max(0.0);
-        let adjustment = 1.0 + (amount / 100.0) * shadow_weight;
+        let adjustment = 1.0 + (amount / 100.0) * shadow_weight(lum);
 
         let new_r = ((rf * adjustment) * 255.0).round().clamp(0.0, 255.0) as u8;
         let new_g = ((gf * adjustment) * 255.0).round().clamp(0.0, 255.0) as u8;
         let new_b = ((bf * adjustment) * 255.0).round().clamp(0.0, 255.0) as u8;
 
-        output.put_pixel(x, y, Rgba([new_r, new_g, new_b, a]));
-    }
+        *pixel = Rgba([new_r, new_g, new_b, a]);
+    });
 
     output
 }
 
 /// Apply highlights adjustment (affects bright areas)
 fn apply_highlights(img: &RgbaImage, amount: f32) -> RgbaImage {
-    let (width, height) = img.dimensions();
-    let mut output = RgbaImage::new(width, height);
+    let mut output = img.clone();
 
-    for (x, y, pixel) in img.enumerate_pixels() {
+    for_each_pixel(&mut output, |pixel| {
         let [r, g, b, a] = pixel.0;
         let rf = r as f32 / 255.0;
         let gf = g as f32 / 255.0;
@@ -197,21 +363,122 @@ fn apply_highlights(img: &RgbaImage, amount: f32) -> RgbaImage {
 
         let lum = luminance(rf, gf, bf);
 
-        // Apply adjustment only to bright areas (highlights), with smooth falloff
-        let highlight_weight = ((lum - 0.5) * 2.0).max(0.0);
-        let adjustment = 1.0 + (amount / 100.0) * highlight_weight;
+        let adjustment = 1.0 + (amount / 100.0) * highlight_weight(lum);
 
         let new_r = ((rf * adjustment) * 255.0).round().clamp(0.0, 255.0) as u8;
         let new_g = ((gf * adjustment) * 255.0).round().clamp(0.0, 255.0) as u8;
         let new_b = ((bf * adjustment) * 255.0).round().clamp(0.0, 255.0) as u8;
 
-        output.put_pixel(x, y, Rgba([new_r, new_g, new_b, a]));
-    }
+        *pixel = Rgba([new_r, new_g, new_b, a]);
+    });
 
     output
 }
 
+/// Parameters of an adjustment pass, in the ranges documented on `adjust_image`
+pub(crate) struct Adjustments {
+    pub brightness: i32,
+    pub brightness_mode: BrightnessMode,
+    pub contrast: f32,
+    pub saturation: f32,
+    pub saturation_model: SaturationModel,
+    pub hue: i32,
+    pub exposure: f32,
+    pub gamma: f32,
+    pub shadows: f32,
+    pub highlights: f32,
+    pub vibrance: f32,
+}
+
+/// Append a description to `errors` if `value` is not finite or outside `min..=max`
+fn check_range(errors: &mut Vec<String>, name: &str, value: f32, min: f32, max: f32) {
+    if !value.is_finite() || value < min || value > max {
+        errors.push(format!("{} must be between {} and {} (got {})", name, min, max, value));
+    }
+}
+
+impl Adjustments {
+    /// Reject out-of-range parameters up front (e.g. gamma 0 would divide by zero),
+    /// listing every offending parameter in the error
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        check_range(&mut errors, "brightness", self.brightness as f32, -100.0, 100.0);
+        check_range(&mut errors, "contrast", self.contrast, -100.0, 100.0);
+        check_range(&mut errors, "saturation", self.saturation, 0.0, 2.0);
+        check_range(&mut errors, "hue", self.hue as f32, -180.0, 180.0);
+        check_range(&mut errors, "exposure", self.exposure, -2.0, 2.0);
+        check_range(&mut errors, "gamma", self.gamma, 0.1, 3.0);
+        check_range(&mut errors, "shadows", self.shadows, -100.0, 100.0);
+        check_range(&mut errors, "highlights", self.highlights, -100.0, 100.0);
+        check_range(&mut errors, "vibrance", self.vibrance, -100.0, 100.0);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Invalid adjustment parameters: {}", errors.join("; ")))
+        }
+    }
+
+    /// Apply all adjustments in a logical order
+    pub fn apply(&self, mut rgba: RgbaImage) -> RgbaImage {
+        // 1. Exposure (multiplicative, apply early)
+        if self.exposure.abs() > 0.001 {
+            rgba = apply_exposure(&rgba, self.exposure);
+        }
+
+        // 2. Shadows and Highlights
+        if self.shadows.abs() > 0.001 {
+            rgba = apply_shadows(&rgba, self.shadows);
+        }
+        if self.highlights.abs() > 0.001 {
+            rgba = apply_highlights(&rgba, self.highlights);
+        }
+
+        // 3. Gamma correction
+        if (self.gamma - 1.0).abs() > 0.001 {
+            rgba = apply_gamma(&rgba, self.gamma);
+        }
+
+        // 4. Brightness: additive uses image crate's brighten, scaled from -100..+100 to
+        //    approx -128..+128; multiplicative scales channels by 0..2
+        if self.brightness != 0 {
+            rgba = match self.brightness_mode {
+                BrightnessMode::Additive => {
+                    let brightness_scaled = (self.brightness as f32 * 1.28).round() as i32;
+                    brighten(&rgba, brightness_scaled)
+                }
+                BrightnessMode::Multiplicative => {
+                    scale_channels(&rgba, 1.0 + self.brightness as f32 / 100.0)
+                }
+            };
+        }
+
+        // 5. Contrast
+        if self.contrast.abs() > 0.001 {
+            rgba = apply_contrast(&rgba, self.contrast);
+        }
+
+        // 6. Color adjustments: Saturation, Vibrance, Hue
+        if (self.saturation - 1.0).abs() > 0.001 {
+            rgba = apply_saturation(&rgba, self.saturation, self.saturation_model);
+        }
+
+        if self.vibrance.abs() > 0.001 {
+            // Convert -100..+100 to -1..+1
+            let vibrance_normalized = self.vibrance / 100.0;
+            rgba = apply_vibrance(&rgba, vibrance_normalized);
+        }
+
+        if self.hue != 0 {
+            rgba = huerotate(&rgba, self.hue);
+        }
+
+        rgba
+    }
+}
+
 #[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
 pub fn adjust_image(
     data: &[u8],
     brightness: i32,      // -100 to +100
@@ -223,62 +490,563 @@ pub fn adjust_image(
     shadows: f32,         // -100 to +100
     highlights: f32,      // -100 to +100
     vibrance: f32,        // -100 to +100 (maps to -1 to +1)
+    saturation_model: &str, // "hsl" (default), "hsv" or "lch"
+    brightness_mode: &str,  // "additive" (default) or "multiplicative"
 ) -> Result<Vec<u8>, String> {
+    let saturation_model = SaturationModel::parse(saturation_model)?;
+    let brightness_mode = BrightnessMode::parse(brightness_mode)?;
+
+    let adjustments = Adjustments {
+        brightness,
+        brightness_mode,
+        contrast: contrast_val,
+        saturation,
+        saturation_model,
+        hue,
+        exposure,
+        gamma,
+        shadows,
+        highlights,
+        vibrance,
+    };
+    adjustments.validate()?;
+
+    let mut timer = StageTimer::start("adjust");
     let decoded = decode_image(data)?;
-    let mut rgba = decoded.img.to_rgba8();
+    let rgba = decoded.img.to_rgba8();
+    timer.stage("decode");
+
+    let rgba = adjustments.apply(rgba);
+    timer.stage("process");
+
+    // Convert back to DynamicImage and encode
+    let adjusted = DynamicImage::ImageRgba8(rgba);
+
+    let mut output = Vec::new();
+    adjusted.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode adjusted image: {}", e))?;
+    timer.stage("encode");
+    timer.finish();
+
+    Ok(output)
+}
+
+/// Output of `adjust_preview`
+#[wasm_bindgen(getter_with_clone)]
+pub struct PreviewResult {
+    pub data: Vec<u8>,
+    /// Whether the preview was rendered below full resolution, i.e. it only
+    /// approximates `adjust_image` and the full render should follow
+    pub downscaled: bool,
+}
+
+/// Fast low-resolution version of `adjust_image` for interactive slider previews:
+/// the image is first downscaled so its longer edge is at most `preview_max_edge`,
+/// then the same adjustments are applied. Parameters match `adjust_image`.
+///
+/// Previews are approximate: the downscale with `filter` ("nearest", "triangle",
+/// "catmull_rom", "gaussian" or "lanczos3"; empty means triangle, a good speed/accuracy
+/// balance) softens or aliases fine detail compared to the final full-resolution render.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn adjust_preview(
+    data: &[u8],
+    brightness: i32,
+    contrast_val: f32,
+    saturation: f32,
+    hue: i32,
+    exposure: f32,
+    gamma: f32,
+    shadows: f32,
+    highlights: f32,
+    vibrance: f32,
+    saturation_model: &str,
+    brightness_mode: &str,
+    preview_max_edge: u32,
+    filter: &str,
+) -> Result<PreviewResult, String> {
+    if preview_max_edge == 0 {
+        return Err("Preview size must be greater than 0".to_string());
+    }
+
+    let adjustments = Adjustments {
+        brightness,
+        brightness_mode: BrightnessMode::parse(brightness_mode)?,
+        contrast: contrast_val,
+        saturation,
+        saturation_model: SaturationModel::parse(saturation_model)?,
+        hue,
+        exposure,
+        gamma,
+        shadows,
+        highlights,
+        vibrance,
+    };
+    adjustments.validate()?;
+
+    let mut timer = StageTimer::start("adjust_preview");
+    let decoded = decode_image(data)?;
+    timer.stage("decode");
+
+    let downscaled = decoded.img.width().max(decoded.img.height()) > preview_max_edge;
+    let img = if downscaled {
+        let filter = if filter.is_empty() { FilterType::Triangle } else { parse_filter(filter) };
+        decoded.img.resize(preview_max_edge, preview_max_edge, filter)
+    } else {
+        decoded.img
+    };
+    let rgba = adjustments.apply(img.to_rgba8());
+    timer.stage("process");
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode adjusted preview: {}", e))?;
+    timer.stage("encode");
+    timer.finish();
+
+    Ok(PreviewResult { data: output, downscaled })
+}
+
+/// Visualize the tonal bands used by the shadows and highlights controls: a grayscale
+/// PNG whose brightness is the weight each pixel gets in `band` ("shadows",
+/// "midtones" or "highlights"), from the same weighting functions the adjustments
+/// use. Midtones are what the other two leave, so the three masks add up to white.
+#[wasm_bindgen]
+pub fn tonal_mask(data: &[u8], band: &str) -> Result<Vec<u8>, String> {
+    let weight: fn(f32) -> f32 = match band {
+        "shadows" => shadow_weight,
+        "midtones" => |lum| 1.0 - shadow_weight(lum) - highlight_weight(lum),
+        "highlights" => highlight_weight,
+        _ => return Err(format!("Invalid tonal band: {} (expected shadows, midtones or highlights)", band)),
+    };
+
+    let decoded = decode_image(data)?;
+    let rgba = decoded.img.to_rgba8();
+    let mask = GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, _] = rgba.get_pixel(x, y).0;
+        let lum = luminance(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+        Luma([(weight(lum) * 255.0).round().clamp(0.0, 255.0) as u8])
+    });
 
-    // Apply adjustments in a logical order
+    let mut output = Vec::new();
+    DynamicImage::ImageLuma8(mask).write_to(&mut Cursor::new(&mut output), ImageFormat::Png)
+        .map_err(|e| format!("Failed to encode tonal mask: {}", e))?;
+
+    Ok(output)
+}
 
-    // 1. Exposure (multiplicative, apply early)
-    if exposure.abs() > 0.001 {
-        rgba = apply_exposure(&rgba, exposure);
+/// Fraction of pixels allowed to clip at each end when stretching levels
+const AUTO_LEVELS_CLIP: f64 = 0.005;
+
+/// Gray-world white balance: scale each channel so its mean matches the mean of all
+/// three, with gains limited to 0.5-2 and blended in by `strength`
+fn auto_white_balance(img: &mut RgbaImage, strength: f32) {
+    let mut sums = [0f64; 3];
+    for pixel in img.pixels() {
+        for (sum, &value) in sums.iter_mut().zip(&pixel.0[..3]) {
+            *sum += value as f64;
+        }
     }
+    let gray = (sums[0] + sums[1] + sums[2]) / 3.0;
+    let gains = sums.map(|sum| {
+        let gain = if sum > 0.0 { (gray / sum).clamp(0.5, 2.0) as f32 } else { 1.0 };
+        1.0 + (gain - 1.0) * strength
+    });
 
-    // 2. Shadows and Highlights
-    if shadows.abs() > 0.001 {
-        rgba = apply_shadows(&rgba, shadows);
+    for pixel in img.pixels_mut() {
+        for (value, gain) in pixel.0[..3].iter_mut().zip(gains) {
+            *value = (*value as f32 * gain).round().min(255.0) as u8;
+        }
     }
-    if highlights.abs() > 0.001 {
-        rgba = apply_highlights(&rgba, highlights);
+}
+
+/// Stretch luminance so the 0.5th/99.5th percentiles become black/white, applying
+/// the same linear map to all channels (so hues are kept), blended in by `strength`
+fn auto_levels(img: &mut RgbaImage, strength: f32) {
+    let mut histogram = [0u64; 256];
+    for pixel in img.pixels() {
+        let [r, g, b, _] = pixel.0;
+        histogram[(luminance(r as f32, g as f32, b as f32).round() as usize).min(255)] += 1;
     }
 
-    // 3. Gamma correction
-    if (gamma - 1.0).abs() > 0.001 {
-        rgba = apply_gamma(&rgba, gamma);
+    let clip = (img.pixels().len() as f64 * AUTO_LEVELS_CLIP) as u64;
+    let first_unclipped = |bins: Vec<usize>| {
+        let mut count = 0;
+        bins.into_iter().find(|&i| {
+            count += histogram[i];
+            count > clip
+        }).unwrap_or(0) as f32
+    };
+    let low = first_unclipped((0..256).collect());
+    let high = first_unclipped((0..256).rev().collect());
+    if high - low < 1.0 {
+        return;
     }
 
-    // 4. Brightness (using image crate's brighten, scale from -100..+100 to approx -128..+128)
-    if brightness != 0 {
-        let brightness_scaled = (brightness as f32 * 1.28).round() as i32;
-        rgba = brighten(&rgba, brightness_scaled);
+    let scale = 255.0 / (high - low);
+    for pixel in img.pixels_mut() {
+        for value in &mut pixel.0[..3] {
+            let v = *value as f32;
+            let stretched = (v - low) * scale;
+            *value = (v + (stretched - v) * strength).round().clamp(0.0, 255.0) as u8;
+        }
     }
+}
 
-    // 5. Contrast (using image crate's contrast)
-    if contrast_val.abs() > 0.001 {
-        rgba = contrast(&rgba, contrast_val);
+/// Fraction of the brightest pixels ignored when finding the white point, so
+/// specular highlights and clipped light sources do not set it
+const WHITE_POINT_CLIP: f64 = 0.01;
+
+/// Remove a color cast through the white point alone: the 99th percentile of each
+/// channel is taken as the image's white, and the weaker channels are scaled up to
+/// match the strongest (gains limited to 2) so that white becomes neutral. Black stays
+/// at 0 and, unlike `auto_enhance`, contrast is not stretched; unlike gray-world
+/// balance, midtone colors do not drive the correction. `strength` (0-1) blends the
+/// gains in, 0 returns the input unchanged. With `ignore_transparent` fully
+/// transparent pixels do not count towards the white point.
+#[wasm_bindgen]
+pub fn auto_color_cast_removal(data: &[u8], strength: f32, ignore_transparent: bool) -> Result<Vec<u8>, String> {
+    if !strength.is_finite() || !(0.0..=1.0).contains(&strength) {
+        return Err(format!("Color cast strength must be between 0 and 1 (got {})", strength));
+    }
+    if strength == 0.0 {
+        return Ok(data.to_vec());
     }
 
-    // 6. Color adjustments: Saturation, Vibrance, Hue
-    if (saturation - 1.0).abs() > 0.001 {
-        rgba = apply_saturation(&rgba, saturation);
+    let decoded = decode_image(data)?;
+    let mut rgba = decoded.img.to_rgba8();
+
+    let mut histograms = [[0u64; 256]; 3];
+    let mut counted = 0u64;
+    for pixel in rgba.pixels().filter(|p| !ignore_transparent || p.0[3] > 0) {
+        for (histogram, &value) in histograms.iter_mut().zip(&pixel.0[..3]) {
+            histogram[value as usize] += 1;
+        }
+        counted += 1;
     }
 
-    if vibrance.abs() > 0.001 {
-        // Convert -100..+100 to -1..+1
-        let vibrance_normalized = vibrance / 100.0;
-        rgba = apply_vibrance(&rgba, vibrance_normalized);
+    let clip = (counted as f64 * WHITE_POINT_CLIP) as u64;
+    let white = histograms.map(|histogram| {
+        let mut count = 0;
+        (0..256).rev().find(|&i| {
+            count += histogram[i];
+            count > clip
+        }).unwrap_or(0) as f32
+    });
+    let target = white[0].max(white[1]).max(white[2]);
+    if target < 1.0 {
+        return Ok(data.to_vec());
     }
 
-    if hue != 0 {
-        rgba = huerotate(&rgba, hue);
+    let gains = white.map(|w| {
+        let gain = (target / w.max(1.0)).min(2.0);
+        1.0 + (gain - 1.0) * strength
+    });
+    for pixel in rgba.pixels_mut() {
+        for (value, gain) in pixel.0[..3].iter_mut().zip(gains) {
+            *value = (*value as f32 * gain).round().min(255.0) as u8;
+        }
     }
 
-    // Convert back to DynamicImage and encode
-    let adjusted = DynamicImage::ImageRgba8(rgba);
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode corrected image: {}", e))?;
+
+    Ok(output)
+}
+
+/// One-click enhance. In order, each step scaled by `strength` (0-1):
+/// 1. gray-world white balance (channel gains limited to 0.5-2),
+/// 2. auto levels: luminance 0.5%/99.5% percentiles stretched to black/white,
+/// 3. clarity +20,
+/// 4. HSL saturation x1.1.
+///
+/// A strength of 0 returns the input unchanged.
+#[wasm_bindgen]
+pub fn auto_enhance(data: &[u8], strength: f32) -> Result<Vec<u8>, String> {
+    if !strength.is_finite() || !(0.0..=1.0).contains(&strength) {
+        return Err(format!("Enhance strength must be between 0 and 1 (got {})", strength));
+    }
+    if strength == 0.0 {
+        return Ok(data.to_vec());
+    }
+
+    let decoded = decode_image(data)?;
+    let mut rgba = decoded.img.to_rgba8();
+
+    auto_white_balance(&mut rgba, strength);
+    auto_levels(&mut rgba, strength);
+    clarity(&mut rgba, 20.0 * strength);
+    let rgba = apply_saturation(&rgba, 1.0 + 0.1 * strength, SaturationModel::Hsl);
 
     let mut output = Vec::new();
-    adjusted.write_to(&mut Cursor::new(&mut output), decoded.format)
-        .map_err(|e| format!("Failed to encode adjusted image: {}", e))?;
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode enhanced image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Channel mixer black-and-white conversion: gray = r * `r_weight` + g * `g_weight` +
+/// b * `b_weight`, clipped to 0-255. Weights summing to about 1 keep overall brightness;
+/// other sums are allowed for creative effect. Output is grayscale (alpha is kept).
+#[wasm_bindgen]
+pub fn black_and_white(data: &[u8], r_weight: f32, g_weight: f32, b_weight: f32) -> Result<Vec<u8>, String> {
+    if ![r_weight, g_weight, b_weight].iter().all(|w| w.is_finite()) {
+        return Err("Channel weights must be finite numbers".to_string());
+    }
+
+    let decoded = decode_image(data)?;
+    let rgba = decoded.img.to_rgba8();
+
+    let gray = GrayAlphaImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let value = r as f32 * r_weight + g as f32 * g_weight + b as f32 * b_weight;
+        LumaA([value.round().clamp(0.0, 255.0) as u8, a])
+    });
+
+    let output_img = if decoded.img.color().has_alpha() {
+        DynamicImage::ImageLumaA8(gray)
+    } else {
+        DynamicImage::ImageLuma8(DynamicImage::ImageLumaA8(gray).to_luma8())
+    };
+
+    let mut output = Vec::new();
+    output_img.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode black and white image: {}", e))?;
+
+    Ok(output)
+}
+
+/// `black_and_white` with named channel weights: "neutral" (luma), "red_filter"
+/// (darkens blue skies dramatically) or "orange_filter" (a milder sky and skin effect)
+#[wasm_bindgen]
+pub fn black_and_white_preset(data: &[u8], preset: &str) -> Result<Vec<u8>, String> {
+    let (r, g, b) = match preset {
+        "neutral" => (0.299, 0.587, 0.114),
+        "red_filter" => (0.8, 0.2, 0.0),
+        "orange_filter" => (0.6, 0.35, 0.05),
+        _ => return Err(format!("Unknown black and white preset: {}", preset)),
+    };
+    black_and_white(data, r, g, b)
+}
+
+/// Cumulative distribution (0..1) of a 256-bin histogram
+fn cumulative(histogram: &[u64; 256]) -> [f64; 256] {
+    let total = histogram.iter().sum::<u64>().max(1) as f64;
+    let mut running = 0;
+    histogram.map(|count| {
+        running += count;
+        running as f64 / total
+    })
+}
+
+/// Level mapping that gives the source histogram the shape of the reference one: each
+/// source level goes to the lowest reference level whose cumulative share reaches it
+fn match_levels(source: &[u64; 256], reference: &[u64; 256]) -> [u8; 256] {
+    let (source, reference) = (cumulative(source), cumulative(reference));
+    let mut level = 0;
+    std::array::from_fn(|v| {
+        // Both distributions are non-decreasing, so the search resumes where it stopped
+        while level < 255 && reference[level] < source[v] - 1e-9 {
+            level += 1;
+        }
+        level as u8
+    })
+}
+
+/// Luminance (or, with `per_channel`, R, G and B) histograms of the pixels, leaving
+/// out fully transparent ones with `ignore_transparent`
+fn tone_histograms(img: &RgbaImage, per_channel: bool, ignore_transparent: bool) -> [[u64; 256]; 3] {
+    let mut histograms = [[0u64; 256]; 3];
+    for pixel in img.pixels().filter(|p| !ignore_transparent || p.0[3] > 0) {
+        let [r, g, b, _] = pixel.0;
+        if per_channel {
+            for (histogram, value) in histograms.iter_mut().zip([r, g, b]) {
+                histogram[value as usize] += 1;
+            }
+        } else {
+            histograms[0][(luminance(r as f32, g as f32, b as f32).round() as usize).min(255)] += 1;
+        }
+    }
+    histograms
+}
+
+/// Match the tone distribution of the image to `reference`, e.g. to make a photo set
+/// consistent. By default only luminance is matched and every channel is shifted by
+/// the same amount, which keeps colors; `per_channel` matches R, G and B separately,
+/// which also transfers the reference's color balance. With `ignore_transparent` fully
+/// transparent pixels of either image are left out of the distributions. Matching an
+/// image to itself leaves it unchanged.
+#[wasm_bindgen]
+pub fn match_histogram(data: &[u8], reference: &[u8], per_channel: bool, ignore_transparent: bool) -> Result<Vec<u8>, String> {
+    let decoded = decode_image(data)?;
+    let target = decode_image(reference).map_err(|e| format!("Reference image: {}", e))?;
+    let mut rgba = decoded.img.to_rgba8();
+
+    let source = tone_histograms(&rgba, per_channel, ignore_transparent);
+    let wanted = tone_histograms(&target.img.to_rgba8(), per_channel, ignore_transparent);
+    let maps: [[u8; 256]; 3] = std::array::from_fn(|c| match_levels(&source[c], &wanted[c]));
+
+    for pixel in rgba.pixels_mut() {
+        if per_channel {
+            for (value, map) in pixel.0[..3].iter_mut().zip(&maps) {
+                *value = map[*value as usize];
+            }
+        } else {
+            let [r, g, b, _] = pixel.0;
+            let lum = (luminance(r as f32, g as f32, b as f32).round() as usize).min(255);
+            let shift = maps[0][lum] as f32 - lum as f32;
+            for value in &mut pixel.0[..3] {
+                *value = (*value as f32 + shift).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode matched image: {}", e))?;
 
     Ok(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+
+    fn neutral() -> Adjustments {
+        Adjustments {
+            brightness: 0,
+            brightness_mode: BrightnessMode::Additive,
+            contrast: 0.0,
+            saturation: 1.0,
+            saturation_model: SaturationModel::Hsl,
+            hue: 0,
+            exposure: 0.0,
+            gamma: 1.0,
+            shadows: 0.0,
+            highlights: 0.0,
+            vibrance: 0.0,
+        }
+    }
+
+    #[test]
+    fn validate_accepts_neutral_parameters() {
+        assert!(neutral().validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_zero_gamma() {
+        let err = Adjustments { gamma: 0.0, ..neutral() }.validate().unwrap_err();
+        assert!(err.contains("gamma"), "{}", err);
+    }
+
+    #[test]
+    fn validate_lists_every_out_of_range_parameter() {
+        let err = Adjustments { saturation: -0.5, exposure: f32::NAN, ..neutral() }.validate().unwrap_err();
+        assert!(err.contains("saturation"), "{}", err);
+        assert!(err.contains("exposure"), "{}", err);
+        assert!(!err.contains("gamma"), "{}", err);
+    }
+
+    #[test]
+    fn full_adjustment_pass_preserves_alpha() {
+        let src = RgbaImage::from_fn(4, 4, |x, y| Rgba([(x * 60) as u8, (y * 60) as u8, 90, ((x + y) * 36) as u8]));
+        let adjustments = Adjustments {
+            brightness: 30,
+            contrast: 40.0,
+            saturation: 1.5,
+            hue: 45,
+            exposure: 1.0,
+            gamma: 1.8,
+            shadows: 50.0,
+            highlights: -50.0,
+            vibrance: 30.0,
+            ..neutral()
+        };
+
+        let adjusted = adjustments.apply(src.clone());
+        for (before, after) in src.pixels().zip(adjusted.pixels()) {
+            assert_eq!(before.0[3], after.0[3]);
+        }
+        assert_eq!(adjusted.get_pixel(0, 0).0[3], 0);
+    }
+
+    #[test]
+    fn contrast_does_not_stretch_alpha() {
+        let src = RgbaImage::from_pixel(2, 2, Rgba([200, 100, 50, 64]));
+        let contrasted = apply_contrast(&src, 80.0);
+        assert!(contrasted.pixels().all(|p| p.0[3] == 64));
+        assert_ne!(contrasted.get_pixel(0, 0).0[..3], [200, 100, 50]);
+    }
+
+    fn lab_lightness(rgb: [u8; 3]) -> f32 {
+        let [r, g, b] = rgb.map(|v| srgb_to_linear(v as f32 / 255.0));
+        116.0 * lab_f(0.2126729 * r + 0.7151522 * g + 0.072175 * b) - 16.0
+    }
+
+    fn lch_saturate(rgb: [u8; 3], factor: f32) -> [u8; 3] {
+        let img = RgbaImage::from_pixel(1, 1, Rgba([rgb[0], rgb[1], rgb[2], 255]));
+        let [r, g, b, _] = apply_saturation(&img, factor, SaturationModel::Lch).get_pixel(0, 0).0;
+        [r, g, b]
+    }
+
+    const PRIMARIES: [[u8; 3]; 7] = [
+        [255, 0, 0], [0, 255, 0], [0, 0, 255],
+        [255, 255, 0], [0, 255, 255], [255, 0, 255],
+        [180, 90, 40],
+    ];
+
+    #[test]
+    fn lch_saturation_of_one_is_identity() {
+        for rgb in PRIMARIES {
+            let result = lch_saturate(rgb, 1.0);
+            assert!(result.iter().zip(rgb).all(|(a, b)| a.abs_diff(b) <= 1), "{:?} became {:?}", rgb, result);
+        }
+    }
+
+    #[test]
+    fn lch_desaturation_keeps_lightness() {
+        for rgb in PRIMARIES {
+            let gray = lch_saturate(rgb, 0.0);
+            assert!(gray.iter().all(|&v| v.abs_diff(gray[0]) <= 1), "{:?} became {:?}", rgb, gray);
+
+            for factor in [0.0, 0.5] {
+                let result = lch_saturate(rgb, factor);
+                let (before, after) = (lab_lightness(rgb), lab_lightness(result));
+                assert!((before - after).abs() < 1.0, "{:?} x{}: L {} became {}", rgb, factor, before, after);
+            }
+        }
+    }
+
+    fn photo() -> RgbaImage {
+        RgbaImage::from_fn(40, 30, |x, y| Rgba([(x * 6) as u8, (y * 8) as u8, ((x * y) % 200) as u8 + 30, 255]))
+    }
+
+    #[test]
+    fn match_histogram_to_itself_is_a_no_op() {
+        let data = encode_png_fixture(photo());
+        for per_channel in [false, true] {
+            let matched = image::load_from_memory(&match_histogram(&data, &data, per_channel, false).unwrap())
+                .unwrap().to_rgba8();
+            let max = matched.as_raw().iter().zip(photo().as_raw()).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+            assert!(max <= 1, "per_channel {}: max difference {}", per_channel, max);
+        }
+    }
+
+    #[test]
+    fn match_histogram_moves_tones_towards_the_reference() {
+        let data = encode_png_fixture(photo());
+        let bright = encode_png_fixture(RgbaImage::from_fn(40, 30, |x, y| {
+            let [r, g, b, a] = photo().get_pixel(x, y).0;
+            Rgba([r / 2 + 120, g / 2 + 120, b / 2 + 120, a])
+        }));
+        let mean = |img: &RgbaImage| img.as_raw().iter().map(|&v| v as f64).sum::<f64>() / img.as_raw().len() as f64;
+
+        for per_channel in [false, true] {
+            let matched = image::load_from_memory(&match_histogram(&data, &bright, per_channel, false).unwrap())
+                .unwrap().to_rgba8();
+            assert!(mean(&matched) > mean(&photo()) + 20.0, "per_channel {}", per_channel);
+        }
+    }
+}
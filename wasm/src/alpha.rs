@@ -0,0 +1,338 @@
+use wasm_bindgen::prelude::*;
+use image::{ColorType, DynamicImage, GrayImage, ImageFormat, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+use image::imageops::FilterType;
+use std::io::Cursor;
+
+use crate::common::{check_dimensions, convert_color, decode_image, linear_to_srgb, parse_color, srgb_to_linear, supports_alpha};
+use crate::filters::{gaussian_separable, MAX_BLUR_SIGMA};
+use crate::metadata::read_premultiplied_alpha;
+
+/// Maximum relative aspect-ratio difference at which a mask is still stretched to fit
+const MASK_ASPECT_TOLERANCE: f64 = 0.01;
+
+/// Porter-Duff "over" of `fg` onto `bg` with straight alpha. With `gamma_correct` the
+/// colors are mixed in linear light, which avoids dark fringes on soft edges; otherwise
+/// the sRGB values are mixed directly.
+pub(crate) fn composite_over(fg: Rgba<u8>, bg: Rgba<u8>, gamma_correct: bool) -> Rgba<u8> {
+    let fa = fg.0[3] as f32 / 255.0;
+    let ba = bg.0[3] as f32 / 255.0;
+    let out_a = fa + ba * (1.0 - fa);
+    if out_a <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+
+    let mut out = [0u8; 4];
+    for (c, value) in out.iter_mut().take(3).enumerate() {
+        let (mut f, mut b) = (fg.0[c] as f32 / 255.0, bg.0[c] as f32 / 255.0);
+        if gamma_correct {
+            f = srgb_to_linear(f);
+            b = srgb_to_linear(b);
+        }
+        let mut mixed = (f * fa + b * ba * (1.0 - fa)) / out_a;
+        if gamma_correct {
+            mixed = linear_to_srgb(mixed);
+        }
+        *value = (mixed * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    out[3] = (out_a * 255.0).round() as u8;
+    Rgba(out)
+}
+
+/// Replace the alpha channel with the grayscale of `mask`. The mask is resized to the
+/// image dimensions when the aspect ratios match. Formats without alpha are written as PNG.
+#[wasm_bindgen]
+pub fn set_alpha_from_mask(data: &[u8], mask: &[u8]) -> Result<Vec<u8>, String> {
+    let decoded = decode_image(data)?;
+    let mask_decoded = decode_image(mask)?;
+
+    let (width, height) = (decoded.img.width(), decoded.img.height());
+    let (mask_width, mask_height) = (mask_decoded.img.width(), mask_decoded.img.height());
+
+    let mut mask_img = mask_decoded.img;
+    if (mask_width, mask_height) != (width, height) {
+        let aspect = width as f64 / height as f64;
+        let mask_aspect = mask_width as f64 / mask_height as f64;
+        if ((aspect - mask_aspect) / aspect).abs() > MASK_ASPECT_TOLERANCE {
+            return Err(format!(
+                "Mask dimensions ({}x{}) cannot be matched to image dimensions ({}x{})",
+                mask_width, mask_height, width, height
+            ));
+        }
+        mask_img = mask_img.resize_exact(width, height, FilterType::Triangle);
+    }
+
+    let mask_luma = mask_img.to_luma8();
+    let mut rgba = decoded.img.to_rgba8();
+    for (pixel, m) in rgba.pixels_mut().zip(mask_luma.pixels()) {
+        pixel.0[3] = m.0[0];
+    }
+
+    let format = if supports_alpha(decoded.format) { decoded.format } else { ImageFormat::Png };
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), format)
+        .map_err(|e| format!("Failed to encode masked image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Drop the alpha channel, producing an RGB image (16-bit sources stay 16-bit)
+#[wasm_bindgen]
+pub fn remove_alpha(data: &[u8]) -> Result<Vec<u8>, String> {
+    let decoded = decode_image(data)?;
+
+    let opaque = match decoded.img.color() {
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+            DynamicImage::ImageRgb16(decoded.img.to_rgb16())
+        }
+        _ => DynamicImage::ImageRgb8(decoded.img.to_rgb8()),
+    };
+
+    let mut output = Vec::new();
+    opaque.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode image without alpha: {}", e))?;
+
+    Ok(output)
+}
+
+/// Convert premultiplied (associated) alpha to the straight alpha the rest of the
+/// crate expects, dividing each color by its alpha; otherwise such images composite
+/// too dark. Only inputs detected as premultiplied are converted (TIFF with
+/// ExtraSamples = 1, and OpenEXR, see `ImageMetadata::premultiplied_alpha`); anything
+/// else is returned unchanged. Fully transparent pixels become transparent black.
+#[wasm_bindgen]
+pub fn to_straight_alpha(data: &[u8]) -> Result<Vec<u8>, String> {
+    let decoded = decode_image(data)?;
+    if !decoded.img.color().has_alpha() || !read_premultiplied_alpha(data, decoded.format) {
+        return Ok(data.to_vec());
+    }
+
+    let mut rgba = decoded.img.to_rgba32f();
+    for pixel in rgba.pixels_mut() {
+        let alpha = pixel.0[3];
+        for c in 0..3 {
+            pixel.0[c] = if alpha > 0.0 { pixel.0[c] / alpha } else { 0.0 };
+        }
+    }
+
+    let straight = convert_color(&DynamicImage::ImageRgba32F(rgba), decoded.img.color());
+    let mut output = Vec::new();
+    straight.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode image with straight alpha: {}", e))?;
+
+    Ok(output)
+}
+
+/// Alpha-composite the image over a checkerboard of `cell`-pixel squares, producing an
+/// opaque RGB preview of its transparency that can be saved in any format (e.g. JPEG)
+#[wasm_bindgen]
+pub fn composite_on_checkerboard(data: &[u8], cell: u32, light: &str, dark: &str) -> Result<Vec<u8>, String> {
+    if cell == 0 {
+        return Err("Checkerboard cell size must be greater than 0".to_string());
+    }
+    let light = parse_color(light)?;
+    let dark = parse_color(dark)?;
+
+    let decoded = decode_image(data)?;
+    let rgba = decoded.img.to_rgba8();
+
+    let composited = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let backdrop = if (x / cell + y / cell).is_multiple_of(2) { light } else { dark };
+        let [r, g, b, _] = composite_over(*rgba.get_pixel(x, y), Rgba([backdrop.0[0], backdrop.0[1], backdrop.0[2], 255]), false).0;
+        Rgb([r, g, b])
+    });
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgb8(composited).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode composited image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Composite the image over a solid `background` color, producing an opaque RGB image
+/// in the source format. `gamma_correct` blends in linear light instead of sRGB.
+#[wasm_bindgen]
+pub fn flatten_alpha(data: &[u8], background: &str, gamma_correct: bool) -> Result<Vec<u8>, String> {
+    let background = parse_color(background)?;
+    let backdrop = Rgba([background.0[0], background.0[1], background.0[2], 255]);
+
+    let decoded = decode_image(data)?;
+    let rgba = decoded.img.to_rgba8();
+
+    let flattened = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, _] = composite_over(*rgba.get_pixel(x, y), backdrop, gamma_correct).0;
+        Rgb([r, g, b])
+    });
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgb8(flattened).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode flattened image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Draw `watermark` over the image with its top-left corner at (`x`, `y`) (may be
+/// negative or partly outside) and its alpha scaled by `opacity` (0-1).
+/// `gamma_correct` blends in linear light instead of sRGB.
+#[wasm_bindgen]
+pub fn overlay_watermark(
+    data: &[u8],
+    watermark: &[u8],
+    x: i32,
+    y: i32,
+    opacity: f32,
+    gamma_correct: bool,
+) -> Result<Vec<u8>, String> {
+    if !opacity.is_finite() || !(0.0..=1.0).contains(&opacity) {
+        return Err(format!("Opacity must be between 0 and 1 (got {})", opacity));
+    }
+
+    let decoded = decode_image(data)?;
+    let mark = decode_image(watermark)?.img.to_rgba8();
+    let mut rgba = decoded.img.to_rgba8();
+
+    for (mx, my, pixel) in mark.enumerate_pixels() {
+        let (tx, ty) = (x as i64 + mx as i64, y as i64 + my as i64);
+        if tx < 0 || ty < 0 || tx >= rgba.width() as i64 || ty >= rgba.height() as i64 {
+            continue;
+        }
+        let mut fg = *pixel;
+        fg.0[3] = (fg.0[3] as f32 * opacity).round() as u8;
+        let target = rgba.get_pixel_mut(tx as u32, ty as u32);
+        *target = composite_over(fg, *target, gamma_correct);
+    }
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode watermarked image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Replace every pixel within `tolerance` (Euclidean RGB distance) of `target` with
+/// `replacement`, e.g. for simple green-screen removal with "transparent". Formats
+/// without alpha are written as PNG when the replacement is not fully opaque.
+#[wasm_bindgen]
+pub fn replace_color(data: &[u8], target: &str, tolerance: u8, replacement: &str) -> Result<Vec<u8>, String> {
+    let target = parse_color(target)?;
+    let replacement = parse_color(replacement)?;
+
+    let decoded = decode_image(data)?;
+    let mut rgba = decoded.img.to_rgba8();
+
+    let max_distance_sq = tolerance as i32 * tolerance as i32;
+    for pixel in rgba.pixels_mut() {
+        let distance_sq: i32 = (0..3)
+            .map(|c| {
+                let d = pixel.0[c] as i32 - target.0[c] as i32;
+                d * d
+            })
+            .sum();
+        if distance_sq <= max_distance_sq {
+            *pixel = replacement;
+        }
+    }
+
+    let format = if replacement.0[3] == 255 || supports_alpha(decoded.format) {
+        decoded.format
+    } else {
+        ImageFormat::Png
+    };
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), format)
+        .map_err(|e| format!("Failed to encode recolored image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Add a soft drop shadow: the alpha silhouette is offset by (`dx`, `dy`), blurred with
+/// `blur` sigma, tinted with `color` at `opacity` (0-1) and placed under the image. The
+/// canvas grows to fit the shadow; formats without alpha are written as PNG.
+#[wasm_bindgen]
+pub fn add_drop_shadow(data: &[u8], dx: i32, dy: i32, blur: f32, color: &str, opacity: f32) -> Result<Vec<u8>, String> {
+    if !blur.is_finite() || !(0.0..=MAX_BLUR_SIGMA).contains(&blur) {
+        return Err(format!("Shadow blur must be between 0 and {} (got {})", MAX_BLUR_SIGMA, blur));
+    }
+    if !opacity.is_finite() || !(0.0..=1.0).contains(&opacity) {
+        return Err(format!("Opacity must be between 0 and 1 (got {})", opacity));
+    }
+    let color = parse_color(color)?;
+
+    let decoded = decode_image(data)?;
+    let rgba = decoded.img.to_rgba8();
+    let (width, height) = (rgba.width() as i64, rgba.height() as i64);
+
+    let margin = (blur * 3.0).ceil() as i64;
+    let (dx, dy) = (dx as i64, dy as i64);
+    let left = (dx - margin).min(0);
+    let top = (dy - margin).min(0);
+    let right = (width + dx + margin).max(width);
+    let bottom = (height + dy + margin).max(height);
+
+    let canvas_w = u32::try_from(right - left).map_err(|_| "Shadow canvas is too large".to_string())?;
+    let canvas_h = u32::try_from(bottom - top).map_err(|_| "Shadow canvas is too large".to_string())?;
+    check_dimensions(canvas_w, canvas_h)?;
+
+    let (image_x, image_y) = (-left, -top);
+    let (shadow_x, shadow_y) = (image_x + dx, image_y + dy);
+
+    let mut silhouette = GrayImage::from_fn(canvas_w, canvas_h, |x, y| {
+        let (sx, sy) = (x as i64 - shadow_x, y as i64 - shadow_y);
+        if sx < 0 || sy < 0 || sx >= width || sy >= height {
+            return Luma([0]);
+        }
+        Luma([rgba.get_pixel(sx as u32, sy as u32).0[3]])
+    });
+    if blur > 0.0 {
+        silhouette = gaussian_separable(&silhouette, blur);
+    }
+
+    let shadow_alpha = opacity * color.0[3] as f32 / 255.0;
+    let canvas = RgbaImage::from_fn(canvas_w, canvas_h, |x, y| {
+        let a = silhouette.get_pixel(x, y).0[0] as f32 * shadow_alpha;
+        let shadow = Rgba([color.0[0], color.0[1], color.0[2], a.round() as u8]);
+
+        let (ix, iy) = (x as i64 - image_x, y as i64 - image_y);
+        if ix < 0 || iy < 0 || ix >= width || iy >= height {
+            return shadow;
+        }
+        composite_over(*rgba.get_pixel(ix as u32, iy as u32), shadow, false)
+    });
+
+    let format = if supports_alpha(decoded.format) { decoded.format } else { ImageFormat::Png };
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(canvas).write_to(&mut Cursor::new(&mut output), format)
+        .map_err(|e| format!("Failed to encode image with shadow: {}", e))?;
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+
+    #[test]
+    fn half_white_over_black_is_188_in_linear_light() {
+        let half_white = Rgba([255, 255, 255, 128]);
+        let black = Rgba([0, 0, 0, 255]);
+        assert_eq!(composite_over(half_white, black, false).0, [128, 128, 128, 255]);
+        assert_eq!(composite_over(half_white, black, true).0, [188, 188, 188, 255]);
+    }
+
+    #[test]
+    fn flatten_and_watermark_blend_in_linear_light_when_asked() {
+        let half_white = encode_png_fixture(RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 128])));
+        let flattened = image::load_from_memory(&flatten_alpha(&half_white, "black", true).unwrap()).unwrap();
+        assert_eq!(flattened.to_rgb8().get_pixel(0, 0).0, [188, 188, 188]);
+
+        let black = encode_png_fixture(RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 255])));
+        let white = encode_png_fixture(RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 255])));
+        let marked = image::load_from_memory(&overlay_watermark(&black, &white, 0, 0, 0.5, true).unwrap()).unwrap();
+        assert_eq!(marked.to_rgba8().get_pixel(1, 1).0, [188, 188, 188, 255]);
+        let plain = image::load_from_memory(&overlay_watermark(&black, &white, 0, 0, 0.5, false).unwrap()).unwrap();
+        assert_eq!(plain.to_rgba8().get_pixel(1, 1).0, [128, 128, 128, 255]);
+    }
+}
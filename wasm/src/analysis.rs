@@ -0,0 +1,379 @@
+use wasm_bindgen::prelude::*;
+use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+use image::imageops::FilterType;
+use std::collections::HashSet;
+use std::io::Cursor;
+
+use crate::animation::decode_all_frames;
+use crate::common::{decode_image, sobel_energy};
+use crate::filters::to_gray_on_white;
+
+/// Longest edge images are reduced to before computing statistics
+const ANALYSIS_MAX_EDGE: u32 = 512;
+
+/// Downscale large images for analysis. Nearest-neighbor keeps the original colors,
+/// so palette-based statistics stay meaningful.
+fn analysis_sample(img: &DynamicImage) -> DynamicImage {
+    if img.width().max(img.height()) > ANALYSIS_MAX_EDGE {
+        img.resize(ANALYSIS_MAX_EDGE, ANALYSIS_MAX_EDGE, FilterType::Nearest)
+    } else {
+        img.clone()
+    }
+}
+
+#[wasm_bindgen(getter_with_clone)]
+pub struct FormatRecommendation {
+    pub format: String,
+    pub rationale: String,
+}
+
+/// Suggest an output format from the image content: transparency, number of distinct
+/// colors, and whether edge statistics look photographic (few perfectly flat areas)
+/// or like a graphic (large flat regions with hard edges). With `ignore_transparent`
+/// fully transparent pixels (whose hidden colors often vary) are left out of the color
+/// count and edge statistics; transparency itself is still taken into account.
+#[wasm_bindgen]
+pub fn recommend_format(data: &[u8], ignore_transparent: bool) -> Result<FormatRecommendation, String> {
+    let decoded = decode_image(data)?;
+    let sample = analysis_sample(&decoded.img);
+    let rgba = sample.to_rgba8();
+
+    let has_alpha = rgba.pixels().any(|p| p.0[3] < 255);
+    let counted = |alpha: u8| !ignore_transparent || alpha > 0;
+
+    let mut colors = HashSet::new();
+    for pixel in rgba.pixels().filter(|p| counted(p.0[3])) {
+        colors.insert(pixel.0);
+        if colors.len() > 256 {
+            break;
+        }
+    }
+    let few_colors = colors.len() <= 256;
+
+    let energy = sobel_energy(&sample.to_luma8());
+    let counted_energy: Vec<f32> = energy.iter().zip(rgba.pixels())
+        .filter(|(_, p)| counted(p.0[3]))
+        .map(|(&e, _)| e)
+        .collect();
+    let flat_ratio = counted_energy.iter().filter(|&&e| e < 1.0).count() as f32 / counted_energy.len().max(1) as f32;
+    let photographic = !few_colors && flat_ratio < 0.3;
+
+    let (format, rationale) = if few_colors {
+        ("PNG-8", format!(
+            "{} distinct colors{} fit in a 256-color palette, so an indexed PNG is lossless and small",
+            colors.len(),
+            if has_alpha { " with transparency" } else { "" }
+        ))
+    } else if has_alpha && photographic {
+        ("WebP", "Photographic content with transparency: lossy WebP keeps the alpha channel at a fraction of PNG's size".to_string())
+    } else if has_alpha {
+        ("PNG", "Graphic content with transparency: PNG keeps hard edges and alpha without artifacts".to_string())
+    } else if photographic {
+        ("JPEG", "Opaque photographic content: JPEG (or WebP) compresses smooth tonal detail far better than lossless formats".to_string())
+    } else {
+        ("PNG", format!(
+            "Opaque graphic content ({:.0}% flat areas): PNG compresses flat regions well and avoids ringing around sharp edges",
+            flat_ratio * 100.0
+        ))
+    };
+
+    Ok(FormatRecommendation { format: format.to_string(), rationale })
+}
+
+/// Visual diff of two images with summary statistics
+#[wasm_bindgen(getter_with_clone)]
+pub struct DiffResult {
+    /// Grayscale PNG heatmap: per-pixel largest channel difference times `amplify`
+    pub data: Vec<u8>,
+    /// Largest absolute channel difference (0-255)
+    pub max_diff: u8,
+    /// Mean absolute difference over all RGBA channels (0-255)
+    pub mean_diff: f64,
+}
+
+/// Compare two images of equal dimensions channel by channel, rendering the per-pixel
+/// difference amplified by `amplify` so subtle encoding changes become visible
+#[wasm_bindgen]
+pub fn diff_image(a: &[u8], b: &[u8], amplify: f32) -> Result<DiffResult, String> {
+    if !amplify.is_finite() || amplify < 0.0 {
+        return Err(format!("Invalid amplification: {}", amplify));
+    }
+
+    let first = decode_image(a)?.img.to_rgba8();
+    let second = decode_image(b)?.img.to_rgba8();
+    if first.dimensions() != second.dimensions() {
+        return Err(format!(
+            "Image dimensions differ ({}x{} vs {}x{})",
+            first.width(), first.height(), second.width(), second.height()
+        ));
+    }
+
+    let mut max_diff = 0u8;
+    let mut total: u64 = 0;
+    let heatmap = GrayImage::from_fn(first.width(), first.height(), |x, y| {
+        let p = first.get_pixel(x, y).0;
+        let q = second.get_pixel(x, y).0;
+        let mut pixel_max = 0u8;
+        for c in 0..4 {
+            let d = p[c].abs_diff(q[c]);
+            total += d as u64;
+            pixel_max = pixel_max.max(d);
+        }
+        max_diff = max_diff.max(pixel_max);
+        Luma([(pixel_max as f32 * amplify).round().min(255.0) as u8])
+    });
+
+    let mean_diff = total as f64 / (first.width() as u64 * first.height() as u64 * 4) as f64;
+
+    let mut output = Vec::new();
+    DynamicImage::ImageLuma8(heatmap).write_to(&mut Cursor::new(&mut output), ImageFormat::Png)
+        .map_err(|e| format!("Failed to encode diff image: {}", e))?;
+
+    Ok(DiffResult { data: output, max_diff, mean_diff })
+}
+
+/// Average color of each cell of a `cols`x`rows` grid as "#RRGGBB", row-major. Cell
+/// edges are spread evenly when the dimensions are not divisible by the grid size.
+/// With `ignore_transparent` fully transparent pixels are skipped, and cells without
+/// any visible pixel are reported as "transparent".
+#[wasm_bindgen]
+pub fn grid_average_colors(data: &[u8], cols: u32, rows: u32, ignore_transparent: bool) -> Result<Vec<String>, String> {
+    let decoded = decode_image(data)?;
+    let rgba = decoded.img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    if cols == 0 || rows == 0 || cols > width || rows > height {
+        return Err(format!("Invalid grid {}x{} for a {}x{} image", cols, rows, width, height));
+    }
+
+    let edge = |i: u32, count: u32, len: u32| (i as u64 * len as u64 / count as u64) as u32;
+
+    let mut colors = Vec::with_capacity((cols * rows) as usize);
+    for row in 0..rows {
+        let (top, bottom) = (edge(row, rows, height), edge(row + 1, rows, height));
+        for col in 0..cols {
+            let (left, right) = (edge(col, cols, width), edge(col + 1, cols, width));
+
+            let mut sums = [0u64; 3];
+            let mut count = 0u64;
+            for y in top..bottom {
+                for x in left..right {
+                    let pixel = rgba.get_pixel(x, y);
+                    if ignore_transparent && pixel.0[3] == 0 {
+                        continue;
+                    }
+                    for (sum, &value) in sums.iter_mut().zip(&pixel.0[..3]) {
+                        *sum += value as u64;
+                    }
+                    count += 1;
+                }
+            }
+            if count == 0 {
+                colors.push("transparent".to_string());
+                continue;
+            }
+            let [r, g, b] = sums.map(|sum| ((sum + count / 2) / count) as u8);
+            colors.push(format!("#{:02X}{:02X}{:02X}", r, g, b));
+        }
+    }
+
+    Ok(colors)
+}
+
+/// Share of clipped and crushed pixels, in percent of all (counted) pixels
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct ClippingStats {
+    /// Pixels with at least one channel at 255
+    pub highlights: f64,
+    /// Pixels with at least one channel at 0
+    pub shadows: f64,
+    pub red_highlights: f64,
+    pub green_highlights: f64,
+    pub blue_highlights: f64,
+    pub red_shadows: f64,
+    pub green_shadows: f64,
+    pub blue_shadows: f64,
+}
+
+/// Exposure warnings ("blinkies"): how much of the image is blown out (any channel
+/// at 255) or crushed (any channel at 0), with a per-channel breakdown. Computed on
+/// the full-resolution image so small specular highlights are not missed. With
+/// `ignore_transparent` fully transparent pixels are left out of the statistics.
+#[wasm_bindgen]
+pub fn clipping_stats(data: &[u8], ignore_transparent: bool) -> Result<ClippingStats, String> {
+    let rgba = decode_image(data)?.img.to_rgba8();
+
+    let mut highlights = 0u64;
+    let mut shadows = 0u64;
+    let mut channel_highlights = [0u64; 3];
+    let mut channel_shadows = [0u64; 3];
+    let mut total = 0u64;
+    for pixel in rgba.pixels().filter(|p| !ignore_transparent || p.0[3] > 0) {
+        total += 1;
+        let rgb = &pixel.0[..3];
+        for c in 0..3 {
+            channel_highlights[c] += (rgb[c] == 255) as u64;
+            channel_shadows[c] += (rgb[c] == 0) as u64;
+        }
+        highlights += rgb.contains(&255) as u64;
+        shadows += rgb.contains(&0) as u64;
+    }
+
+    let percent = |count: u64| if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 };
+
+    Ok(ClippingStats {
+        highlights: percent(highlights),
+        shadows: percent(shadows),
+        red_highlights: percent(channel_highlights[0]),
+        green_highlights: percent(channel_highlights[1]),
+        blue_highlights: percent(channel_highlights[2]),
+        red_shadows: percent(channel_shadows[0]),
+        green_shadows: percent(channel_shadows[1]),
+        blue_shadows: percent(channel_shadows[2]),
+    })
+}
+
+/// Grayscale thumbnail size behind difference hashes: 9 columns give 8 comparisons per row
+const HASH_WIDTH: u32 = 9;
+const HASH_HEIGHT: u32 = 8;
+
+/// 64-bit difference hash of a grayscale thumbnail: one bit per horizontally adjacent
+/// pair, set when the left pixel is darker. Robust to re-encoding and rescaling.
+fn difference_hash(thumb: &[f32]) -> u64 {
+    let mut hash = 0u64;
+    for row in thumb.chunks(HASH_WIDTH as usize) {
+        for pair in row.windows(2) {
+            hash = (hash << 1) | (pair[0] < pair[1]) as u64;
+        }
+    }
+    hash
+}
+
+/// Fingerprint of an animation from `animation_hash`
+#[wasm_bindgen(getter_with_clone)]
+pub struct AnimationHash {
+    /// Difference hash of the average of all frames (16 hex digits), stable across
+    /// re-encodes that drop or merge duplicate frames
+    pub hash: String,
+    /// Difference hash of every frame, in order
+    pub frame_hashes: Vec<String>,
+    pub frame_count: u32,
+}
+
+/// Perceptual fingerprint for deduplicating animated GIFs, PNGs and WebPs (still
+/// images count as one frame). Each frame is reduced to a 9x8 grayscale thumbnail
+/// (transparency on white) and difference-hashed; `hash` hashes the average of those
+/// thumbnails. Re-encodings of the same clip give equal or nearly equal hashes, so
+/// compare them by Hamming distance (a few bits out of 64).
+#[wasm_bindgen]
+pub fn animation_hash(data: &[u8]) -> Result<AnimationHash, String> {
+    let frames = decode_all_frames(data)?;
+
+    let thumbs: Vec<Vec<f32>> = frames.into_iter()
+        .map(|frame| {
+            let gray = to_gray_on_white(&DynamicImage::ImageRgba8(frame));
+            image::imageops::resize(&gray, HASH_WIDTH, HASH_HEIGHT, FilterType::Triangle)
+                .into_raw().into_iter().map(f32::from).collect()
+        })
+        .collect();
+
+    let mut average = vec![0.0f32; (HASH_WIDTH * HASH_HEIGHT) as usize];
+    for thumb in &thumbs {
+        for (sum, value) in average.iter_mut().zip(thumb) {
+            *sum += value / thumbs.len() as f32;
+        }
+    }
+
+    Ok(AnimationHash {
+        hash: format!("{:016x}", difference_hash(&average)),
+        frame_hashes: thumbs.iter().map(|t| format!("{:016x}", difference_hash(t))).collect(),
+        frame_count: thumbs.len() as u32,
+    })
+}
+
+/// Longest edge used for sharpness scoring, so scores are comparable across resolutions
+const SHARPNESS_MAX_EDGE: u32 = 1024;
+
+/// Laplacian variance below which `sharpness_score` reports an image as blurry by default
+const DEFAULT_BLUR_THRESHOLD: f64 = 100.0;
+
+/// Result of `sharpness_score`
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Sharpness {
+    /// Variance of the Laplacian; higher means sharper
+    pub score: f64,
+    pub is_blurry: bool,
+}
+
+/// Blur detection by variance of the Laplacian: in-focus photos have strong, varied
+/// second derivatives, defocused or shaken ones do not. The grayscale image is first
+/// reduced to at most 1024 px on the long edge so scores do not depend on camera
+/// resolution. `is_blurry` compares against `threshold` (0 uses 100, a common
+/// starting point; tune it on your own uploads since it depends on content).
+#[wasm_bindgen]
+pub fn sharpness_score(data: &[u8], threshold: f64) -> Result<Sharpness, String> {
+    if !threshold.is_finite() || threshold < 0.0 {
+        return Err(format!("Invalid blur threshold: {}", threshold));
+    }
+    let threshold = if threshold == 0.0 { DEFAULT_BLUR_THRESHOLD } else { threshold };
+
+    let decoded = decode_image(data)?;
+    let img = if decoded.img.width().max(decoded.img.height()) > SHARPNESS_MAX_EDGE {
+        decoded.img.resize(SHARPNESS_MAX_EDGE, SHARPNESS_MAX_EDGE, FilterType::Triangle)
+    } else {
+        decoded.img
+    };
+    let gray = img.to_luma8();
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return Err(format!("Image is too small to measure sharpness ({}x{})", width, height));
+    }
+
+    let mut sum = 0.0f64;
+    let mut sum_squares = 0.0f64;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let at = |dx: i32, dy: i32| gray.get_pixel((x as i32 + dx) as u32, (y as i32 + dy) as u32).0[0] as f64;
+            let laplacian = at(-1, 0) + at(1, 0) + at(0, -1) + at(0, 1) - 4.0 * at(0, 0);
+            sum += laplacian;
+            sum_squares += laplacian * laplacian;
+        }
+    }
+    let count = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / count;
+    let score = sum_squares / count - mean * mean;
+
+    Ok(Sharpness { score, is_blurry: score < threshold })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+    use image::{Rgba, RgbaImage};
+
+    /// A red square on a fully transparent background whose hidden colors vary
+    fn logo() -> Vec<u8> {
+        let img = RgbaImage::from_fn(40, 40, |x, y| {
+            if (10..30).contains(&x) && (10..30).contains(&y) {
+                Rgba([220, 20, 30, 255])
+            } else {
+                Rgba([(x * 6) as u8, (y * 6) as u8, 255, 0])
+            }
+        });
+        encode_png_fixture(img)
+    }
+
+    #[test]
+    fn average_of_transparent_logo_reflects_only_opaque_pixels() {
+        assert_eq!(grid_average_colors(&logo(), 1, 1, true).unwrap(), ["#DC141E"]);
+        assert_ne!(grid_average_colors(&logo(), 1, 1, false).unwrap(), ["#DC141E"]);
+    }
+
+    #[test]
+    fn recommend_format_can_ignore_hidden_colors() {
+        assert_eq!(recommend_format(&logo(), true).unwrap().format, "PNG-8");
+        assert_ne!(recommend_format(&logo(), false).unwrap().format, "PNG-8");
+    }
+}
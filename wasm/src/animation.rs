@@ -0,0 +1,433 @@
+use wasm_bindgen::prelude::*;
+use image::{AnimationDecoder, ExtendedColorType, Frame, ImageFormat, RgbaImage};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::{WebPDecoder, WebPEncoder};
+use std::collections::HashSet;
+use std::io::Cursor;
+use color_quant::NeuQuant;
+use gif::{DisposalMethod, Repeat};
+
+use crate::common::{catch_panic, decode_image};
+use crate::palette::{Dither, index_pixels, nearest};
+use crate::transforms::resize_with_filter;
+
+/// Every frame of an animated GIF, PNG (APNG) or WebP, composited to full-canvas RGBA.
+/// Still images, including still PNGs and WebPs, give a single frame.
+pub(crate) fn decode_all_frames(data: &[u8]) -> Result<Vec<RgbaImage>, String> {
+    let error = |e: image::ImageError| format!("Failed to decode animation: {}", e);
+    let frames = match image::guess_format(data).ok() {
+        Some(ImageFormat::Gif) => GifDecoder::new(Cursor::new(data)).map_err(error)?.into_frames(),
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(Cursor::new(data)).map_err(error)?;
+            if !decoder.is_apng().map_err(error)? {
+                return Ok(vec![decode_image(data)?.img.to_rgba8()]);
+            }
+            decoder.apng().map_err(error)?.into_frames()
+        }
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(data)).map_err(error)?;
+            if !decoder.has_animation() {
+                return Ok(vec![decode_image(data)?.img.to_rgba8()]);
+            }
+            decoder.into_frames()
+        }
+        _ => return Ok(vec![decode_image(data)?.img.to_rgba8()]),
+    };
+
+    let frames = catch_panic("Failed to decode animation: decoder", || frames.collect_frames().map_err(error))?;
+    if frames.is_empty() {
+        return Err("Animation has no frames".to_string());
+    }
+    Ok(frames.into_iter().map(Frame::into_buffer).collect())
+}
+
+/// Decode every frame as RGBA, scaling frames that differ from the first frame's size
+fn decode_frames(frames: &[Vec<u8>]) -> Result<Vec<RgbaImage>, String> {
+    let mut decoded: Vec<RgbaImage> = Vec::with_capacity(frames.len());
+    for (i, data) in frames.iter().enumerate() {
+        let img = decode_image(data).map_err(|e| format!("Frame {}: {}", i + 1, e))?.img;
+        let rgba = match decoded.first() {
+            Some(first) if first.dimensions() != (img.width(), img.height()) => {
+                resize_with_filter(&img, first.width(), first.height(), "lanczos3").to_rgba8()
+            }
+            _ => img.to_rgba8(),
+        };
+        decoded.push(rgba);
+    }
+    Ok(decoded)
+}
+
+/// Entries in a GIF color table
+const MAX_GIF_COLORS: u16 = 256;
+
+/// Whether GIF frames share one color table or each get their own
+#[derive(Clone, Copy, PartialEq)]
+enum GifPalette {
+    Shared,
+    PerFrame,
+}
+
+impl GifPalette {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "shared" => Ok(GifPalette::Shared),
+            "" | "per_frame" => Ok(GifPalette::PerFrame),
+            _ => Err(format!("Invalid GIF palette mode: {} (expected shared or per_frame)", name)),
+        }
+    }
+}
+
+/// Quantization settings for GIF output
+struct GifOptions {
+    max_colors: u16,
+    palette: GifPalette,
+    dither: Dither,
+}
+
+impl GifOptions {
+    fn parse(max_colors: u16, palette: &str, dither: &str) -> Result<Self, String> {
+        let max_colors = if max_colors == 0 { MAX_GIF_COLORS } else { max_colors };
+        if !(2..=MAX_GIF_COLORS).contains(&max_colors) {
+            return Err(format!("GIF color count must be between 2 and {} (got {})", MAX_GIF_COLORS, max_colors));
+        }
+        Ok(GifOptions { max_colors, palette: GifPalette::parse(palette)?, dither: Dither::parse(dither)? })
+    }
+}
+
+/// Pixels below this alpha become the GIF's transparent color; the rest are opaque
+const GIF_ALPHA_THRESHOLD: u8 = 128;
+
+/// Below this many visible pixels NeuQuant looks at every pixel instead of every tenth
+const FULL_SAMPLE_PIXELS: usize = 65_536;
+
+/// NeuQuant's number of learning steps follows the input size, and with too few its
+/// palette stays close to its initial grays, so smaller inputs are repeated up to this
+const MIN_TRAINING_PIXELS: usize = 16_384;
+
+/// A GIF palette: the exact colors when there are few enough, otherwise NeuQuant's
+enum Quantizer {
+    Exact(Vec<[u8; 3]>),
+    Trained(NeuQuant),
+}
+
+impl Quantizer {
+    fn color_map_rgb(&self) -> Vec<u8> {
+        match self {
+            Quantizer::Exact(colors) => colors.concat(),
+            Quantizer::Trained(quantizer) => quantizer.color_map_rgb(),
+        }
+    }
+}
+
+/// Build a palette of at most `colors` entries for the visible pixels of `frames`
+fn train_quantizer(frames: &[RgbaImage], colors: usize) -> Quantizer {
+    let visible = || frames.iter()
+        .flat_map(|frame| frame.pixels())
+        .filter(|p| p.0[3] >= GIF_ALPHA_THRESHOLD);
+
+    let mut seen = HashSet::new();
+    let mut distinct = Vec::new();
+    for p in visible() {
+        if seen.insert([p.0[0], p.0[1], p.0[2]]) {
+            distinct.push([p.0[0], p.0[1], p.0[2]]);
+            if distinct.len() > colors {
+                break;
+            }
+        }
+    }
+    if distinct.len() <= colors {
+        if distinct.is_empty() {
+            distinct.push([0, 0, 0]);
+        }
+        return Quantizer::Exact(distinct);
+    }
+
+    let mut pixels: Vec<u8> = visible().flat_map(|p| [p.0[0], p.0[1], p.0[2], 255]).collect();
+    let count = pixels.len() / 4;
+    let sample_factor = if count < FULL_SAMPLE_PIXELS { 1 } else { 10 };
+    if count < MIN_TRAINING_PIXELS {
+        pixels = pixels.repeat(MIN_TRAINING_PIXELS.div_ceil(count));
+    }
+    Quantizer::Trained(NeuQuant::new(sample_factor, colors, &pixels))
+}
+
+/// Palette indices of `rgba` for the quantizer's colors, with invisible pixels set
+/// to `transparent_index`
+fn index_frame(rgba: &RgbaImage, quantizer: &Quantizer, dither: Dither, transparent_index: u8) -> Vec<u8> {
+    let palette: Vec<[f32; 3]> = quantizer.color_map_rgb().chunks(3)
+        .map(|c| [c[0] as f32, c[1] as f32, c[2] as f32])
+        .collect();
+    let lookup = |color: [f32; 3]| match quantizer {
+        Quantizer::Exact(_) => nearest(&palette, color),
+        Quantizer::Trained(quantizer) => {
+            let [r, g, b] = color.map(|c| c.round() as u8);
+            quantizer.index_of(&[r, g, b, 255])
+        }
+    };
+
+    index_pixels(rgba, &palette, dither, lookup).into_iter().zip(rgba.pixels())
+        .map(|(index, p)| if p.0[3] < GIF_ALPHA_THRESHOLD { transparent_index } else { index as u8 })
+        .collect()
+}
+
+fn encode_gif(frames: Vec<RgbaImage>, delays_ms: &[u32], loop_count: u32, options: GifOptions) -> Result<Vec<u8>, String> {
+    let repeat = match loop_count {
+        0 => Repeat::Infinite,
+        n => Repeat::Finite(u16::try_from(n).map_err(|_| format!("Loop count {} is too large for GIF", n))?),
+    };
+    let (width, height) = frames[0].dimensions();
+    let (Ok(gif_width), Ok(gif_height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(format!("GIF frames are limited to 65535x65535 pixels (got {}x{})", width, height));
+    };
+    let error = |e: gif::EncodingError| format!("Failed to encode GIF: {}", e);
+
+    // One palette slot is reserved for transparency when any frame needs it
+    let has_transparency = frames.iter().any(|f| f.pixels().any(|p| p.0[3] < GIF_ALPHA_THRESHOLD));
+    let colors = (options.max_colors - has_transparency as u16) as usize;
+    // The transparent color goes right after the palette's own colors
+    let transparent_index = |quantizer: &Quantizer| (quantizer.color_map_rgb().len() / 3) as u8;
+    let color_table = |quantizer: &Quantizer| {
+        let mut table = quantizer.color_map_rgb();
+        if has_transparency {
+            table.extend_from_slice(&[0, 0, 0]);
+        }
+        table
+    };
+
+    let shared = (options.palette == GifPalette::Shared).then(|| train_quantizer(&frames, colors));
+    let global_table = shared.as_ref().map(color_table).unwrap_or_default();
+
+    let mut output = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut output, gif_width, gif_height, &global_table).map_err(error)?;
+        encoder.set_repeat(repeat).map_err(error)?;
+
+        for (rgba, &ms) in frames.iter().zip(delays_ms) {
+            let local = match shared {
+                Some(_) => None,
+                None => Some(train_quantizer(std::slice::from_ref(rgba), colors)),
+            };
+            let quantizer = shared.as_ref().or(local.as_ref()).expect("a quantizer for every frame");
+
+            let frame = gif::Frame {
+                width: gif_width,
+                height: gif_height,
+                buffer: index_frame(rgba, quantizer, options.dither, transparent_index(quantizer)).into(),
+                palette: local.as_ref().map(color_table),
+                transparent: has_transparency.then(|| transparent_index(quantizer)),
+                // Frames cover the whole canvas, so clear transparent areas between them
+                dispose: if has_transparency { DisposalMethod::Background } else { DisposalMethod::Keep },
+                // GIF delays are in 1/100 s
+                delay: ms.div_ceil(10).min(u16::MAX as u32) as u16,
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).map_err(error)?;
+        }
+    }
+    Ok(output)
+}
+
+/// Animated PNG (APNG); browsers that do not support it show the first frame
+fn encode_apng(frames: Vec<RgbaImage>, delays_ms: &[u32], loop_count: u32) -> Result<Vec<u8>, String> {
+    let (width, height) = frames[0].dimensions();
+    let error = |e: png::EncodingError| format!("Failed to encode animated PNG: {}", e);
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, loop_count).map_err(error)?;
+
+    let mut writer = encoder.write_header().map_err(error)?;
+    for (rgba, &ms) in frames.iter().zip(delays_ms) {
+        // The frame delay is a u16 fraction, so long delays are stored in 1/100 s
+        let (numerator, denominator) = match u16::try_from(ms) {
+            Ok(ms) => (ms, 1000),
+            Err(_) => ((ms / 10).min(u16::MAX as u32) as u16, 100),
+        };
+        writer.set_frame_delay(numerator, denominator).map_err(error)?;
+        writer.write_image_data(rgba.as_raw()).map_err(error)?;
+    }
+    writer.finish().map_err(error)?;
+
+    Ok(output)
+}
+
+/// Append a RIFF chunk, padded to an even length
+fn write_riff_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Lossless VP8L bitstream of one frame, taken from the image crate's WebP encoder
+fn encode_vp8l(rgba: &RgbaImage) -> Result<Vec<u8>, String> {
+    let mut webp = Vec::new();
+    WebPEncoder::new_lossless(&mut webp)
+        .encode(rgba.as_raw(), rgba.width(), rgba.height(), ExtendedColorType::Rgba8)
+        .map_err(|e| format!("Failed to encode WebP frame: {}", e))?;
+
+    let mut pos = 12;
+    while let Some(header) = webp.get(pos..pos + 8) {
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if &header[..4] == b"VP8L" {
+            return webp.get(pos + 8..pos + 8 + size)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| "WebP encoder produced a truncated frame".to_string());
+        }
+        pos += 8 + size + size % 2;
+    }
+    Err("WebP encoder produced no VP8L frame".to_string())
+}
+
+/// Animated WebP with lossless frames. Every frame covers the whole canvas and
+/// replaces the previous one (no blending), as the frames are already composited.
+fn encode_webp(frames: Vec<RgbaImage>, delays_ms: &[u32], loop_count: u32) -> Result<Vec<u8>, String> {
+    let loop_count = u16::try_from(loop_count)
+        .map_err(|_| format!("Loop count {} is too large for WebP", loop_count))?;
+    let (width, height) = frames[0].dimensions();
+    let u24 = |value: u32| [value as u8, (value >> 8) as u8, (value >> 16) as u8];
+
+    // VP8X flags: alpha (0x10) when any pixel is translucent, animation (0x02)
+    let has_alpha = frames.iter().any(|f| f.pixels().any(|p| p.0[3] < 255));
+    let mut vp8x = vec![0x02 | if has_alpha { 0x10 } else { 0 }, 0, 0, 0];
+    vp8x.extend_from_slice(&u24(width - 1));
+    vp8x.extend_from_slice(&u24(height - 1));
+
+    // Transparent background, BGRA order
+    let mut anim = vec![0, 0, 0, 0];
+    anim.extend_from_slice(&loop_count.to_le_bytes());
+
+    let mut body = b"WEBP".to_vec();
+    write_riff_chunk(&mut body, b"VP8X", &vp8x);
+    write_riff_chunk(&mut body, b"ANIM", &anim);
+    for (rgba, &ms) in frames.iter().zip(delays_ms) {
+        // Offset (0, 0), size, 24-bit duration in ms, then "do not blend" and no disposal
+        let mut anmf = vec![0; 6];
+        anmf.extend_from_slice(&u24(width - 1));
+        anmf.extend_from_slice(&u24(height - 1));
+        anmf.extend_from_slice(&u24(ms.min(0xFF_FFFF)));
+        anmf.push(0x02);
+        write_riff_chunk(&mut anmf, b"VP8L", &encode_vp8l(rgba)?);
+        write_riff_chunk(&mut body, b"ANMF", &anmf);
+    }
+
+    let mut output = b"RIFF".to_vec();
+    output.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    output.extend(body);
+    Ok(output)
+}
+
+/// Assemble encoded still images into an animation. `delays_ms` gives each frame's
+/// display time and must match `frames` in length; frames whose size differs from the
+/// first are scaled to it. `loop_count` 0 loops forever. `format` is "gif", "webp"
+/// (lossless frames, full color and alpha) or "png" (APNG, likewise lossless).
+///
+/// GIF frames are quantized to `max_colors` (2-256, 0 means 256; one is reserved for
+/// transparency when needed). `gif_palette` "shared" builds one palette from all
+/// frames, which avoids colors flickering between frames; "per_frame" (the default)
+/// gives each frame its own palette, which is more faithful when scenes change.
+/// `dither` is "none" or "floyd_steinberg", which smooths gradients at the cost of
+/// a larger file. These options are ignored for WebP and APNG.
+#[wasm_bindgen]
+pub fn encode_animation(
+    frames: Vec<js_sys::Uint8Array>,
+    delays_ms: Vec<u32>,
+    loop_count: u32,
+    format: &str,
+    max_colors: u16,
+    gif_palette: &str,
+    dither: &str,
+) -> Result<Vec<u8>, String> {
+    let frames: Vec<Vec<u8>> = frames.iter().map(|f| f.to_vec()).collect();
+    encode_frames(&frames, &delays_ms, loop_count, format, max_colors, gif_palette, dither)
+}
+
+fn encode_frames(
+    frames: &[Vec<u8>],
+    delays_ms: &[u32],
+    loop_count: u32,
+    format: &str,
+    max_colors: u16,
+    gif_palette: &str,
+    dither: &str,
+) -> Result<Vec<u8>, String> {
+    if frames.is_empty() {
+        return Err("At least one frame is required".to_string());
+    }
+    if frames.len() != delays_ms.len() {
+        return Err(format!("Got {} frames but {} delays", frames.len(), delays_ms.len()));
+    }
+
+    match format.to_lowercase().as_str() {
+        "gif" => {
+            let options = GifOptions::parse(max_colors, gif_palette, dither)?;
+            encode_gif(decode_frames(frames)?, delays_ms, loop_count, options)
+        }
+        "png" | "apng" => encode_apng(decode_frames(frames)?, delays_ms, loop_count),
+        "webp" => encode_webp(decode_frames(frames)?, delays_ms, loop_count),
+        other => Err(format!("Unsupported animation format: {}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+    use image::Rgba;
+
+    #[test]
+    fn webp_animation_round_trips_frames_losslessly() {
+        let first = RgbaImage::from_fn(9, 7, |x, y| Rgba([x as u8 * 28, y as u8 * 36, 90, 255]));
+        let second = RgbaImage::from_fn(9, 7, |x, y| Rgba([200, x as u8 * 20, y as u8 * 30, (x * 28) as u8]));
+        let webp = encode_frames(&[encode_png_fixture(first.clone()), encode_png_fixture(second.clone())], &[100, 250], 3, "webp", 256, "", "none").unwrap();
+
+        assert_eq!(image::guess_format(&webp).unwrap(), ImageFormat::WebP);
+        let decoded = decode_all_frames(&webp).unwrap();
+        assert_eq!(decoded, vec![first, second]);
+
+        let delays: Vec<u32> = WebPDecoder::new(Cursor::new(&webp)).unwrap().into_frames()
+            .map(|f| f.unwrap().delay().numer_denom_ms().0)
+            .collect();
+        assert_eq!(delays, vec![100, 250]);
+    }
+
+    #[test]
+    fn webp_animation_rejects_loop_count_beyond_u16() {
+        let frame = encode_png_fixture(RgbaImage::new(2, 2));
+        assert!(encode_frames(&[frame], &[100], 70_000, "webp", 256, "", "none").is_err());
+    }
+
+    #[test]
+    fn gif_keeps_exact_colors_of_tiny_images() {
+        let red = encode_png_fixture(RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 255])));
+        for palette in ["shared", "per_frame"] {
+            let gif = encode_frames(std::slice::from_ref(&red), &[100], 0, "gif", 256, palette, "none").unwrap();
+            assert_eq!(decode_all_frames(&gif).unwrap()[0].get_pixel(0, 0).0, [255, 0, 0, 255], "{}", palette);
+        }
+
+        let colors = [[255, 0, 0, 255], [0, 200, 0, 255], [0, 0, 255, 255], [250, 250, 10, 255], [0, 0, 0, 0]];
+        let frame = RgbaImage::from_fn(5, 2, |x, y| Rgba(colors[((x + y) % 5) as usize]));
+        for (palette, dither) in [("shared", "none"), ("per_frame", "floyd_steinberg")] {
+            let gif = encode_frames(&[encode_png_fixture(frame.clone()), encode_png_fixture(frame.clone())], &[100, 100], 0, "gif", 8, palette, dither).unwrap();
+            for decoded in decode_all_frames(&gif).unwrap() {
+                assert_eq!(decoded, frame, "{} {}", palette, dither);
+            }
+        }
+    }
+
+    #[test]
+    fn gif_palette_of_small_many_color_images_follows_the_colors() {
+        let frame = RgbaImage::from_fn(12, 12, |x, y| Rgba([(x * 21) as u8, (y * 21) as u8, 255 - (x * 10) as u8, 255]));
+        let gif = encode_frames(&[encode_png_fixture(frame.clone())], &[100], 0, "gif", 16, "", "none").unwrap();
+        let decoded = &decode_all_frames(&gif).unwrap()[0];
+
+        let error = decoded.as_raw().iter().zip(frame.as_raw()).map(|(a, b)| a.abs_diff(*b) as f64).sum::<f64>()
+            / frame.as_raw().len() as f64;
+        assert!(error < 12.0, "mean error {}", error);
+    }
+}
@@ -1,22 +1,413 @@
-use image::{DynamicImage, ImageFormat, ImageReader};
+use image::{ColorType, DynamicImage, GrayImage, ImageError, ImageFormat, ImageReader, Rgba};
+use image::metadata::Orientation;
 use std::io::Cursor;
 
+use crate::jpeg::decode_jpeg_scaled;
+
+/// Upper bound on output pixel count, keeping RGBA buffers well inside wasm32 memory
+pub(crate) const MAX_PIXELS: u64 = 100_000_000;
+
 pub(crate) struct DecodedImage {
     pub img: DynamicImage,
     pub format: ImageFormat,
     pub format_name: String,
 }
 
+impl DecodedImage {
+    /// MIME type of the source format, e.g. "image/jpeg"
+    pub fn mime_type(&self) -> &'static str {
+        self.format.to_mime_type()
+    }
+
+    /// Usual lowercase file extension of the source format, e.g. "jpg"
+    pub fn extension(&self) -> &'static str {
+        self.format.extensions_str().first().copied().unwrap_or_default()
+    }
+}
+
+/// Recognize common image formats that this build cannot decode, from their signatures
+fn sniff_unsupported_format(data: &[u8]) -> Option<&'static str> {
+    if data.len() >= 12
+        && &data[4..8] == b"ftyp"
+        && matches!(&data[8..12], b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1")
+    {
+        return Some("HEIC/HEIF");
+    }
+
+    if data.starts_with(&[0xFF, 0x0A]) || data.starts_with(b"\0\0\0\x0CJXL \r\n\x87\n") {
+        return Some("JPEG XL");
+    }
+    if data.starts_with(b"8BPS") {
+        return Some("PSD");
+    }
+
+    let text_start = String::from_utf8_lossy(&data[..data.len().min(256)]).trim_start().to_ascii_lowercase();
+    if text_start.starts_with("<svg") || (text_start.starts_with("<?xml") && text_start.contains("<svg")) {
+        return Some("SVG");
+    }
+
+    None
+}
+
+/// Turn a decoder error into an actionable message that tells apart unsupported
+/// formats, corrupt data and input that is not an image at all
+fn describe_decode_error(data: &[u8], guessed: Option<ImageFormat>, err: &ImageError) -> String {
+    let Some(format) = guessed else {
+        return match sniff_unsupported_format(data) {
+            Some(name) => format!("{} detected but not supported in this build", name),
+            None => "unrecognized format, the data does not look like a supported image".to_string(),
+        };
+    };
+
+    match err {
+        ImageError::Unsupported(_) => format!("{:?} detected but not supported in this build ({})", format, err),
+        ImageError::Decoding(_) => format!("{:?} data appears corrupt or truncated ({})", format, err),
+        ImageError::IoError(io) if io.kind() == std::io::ErrorKind::UnexpectedEof => {
+            format!("{:?} data appears truncated ({})", format, err)
+        }
+        ImageError::Limits(_) => format!("{:?} image exceeds decoder limits ({})", format, err),
+        _ => format!("{:?}: {}", format, err),
+    }
+}
+
+/// Run `f`, turning a panic into an error such as "Failed to decode image: decoder
+/// panicked (...)". Decoders can panic on malformed input that passes their own
+/// checks, and an uncaught panic takes the whole WASM instance down. Recovery needs
+/// unwinding: the default `build:wasm` aborts on panic, so only the
+/// `build:wasm:unwind` build (and native tests) get the error back instead of a trap.
+/// Either way `init_logging`'s panic hook logs the message.
+pub(crate) fn catch_panic<T>(context: &str, f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown cause".to_string());
+        Err(format!("{} panicked ({})", context, message))
+    })
+}
+
 pub(crate) fn decode_image(data: &[u8]) -> Result<DecodedImage, String> {
     let reader = ImageReader::new(Cursor::new(data))
         .with_guessed_format()
         .map_err(|e| format!("Failed to identify format: {}", e))?;
 
-    let format = reader.format().unwrap_or(ImageFormat::Png);
+    let guessed = reader.format();
+    let format = guessed.unwrap_or(ImageFormat::Png);
     let format_name = format!("{:?}", format);
 
-    let img = reader.decode()
-        .map_err(|e| format!("Failed to decode image: {}", e))?;
+    let img = catch_panic("Failed to decode image: decoder", || {
+        reader.decode()
+            .map_err(|e| format!("Failed to decode image: {}", describe_decode_error(data, guessed, &e)))
+    })?;
 
     Ok(DecodedImage { img, format, format_name })
 }
+
+/// Decode for output at about `target_w`x`target_h`. JPEGs at least twice as large
+/// as the target on both axes are decoded at 1/2, 1/4 or 1/8 scale through DCT
+/// scaling, which never allocates the full-resolution image (a 1/8 decode needs about
+/// 1/64 of the memory). The scaled image is never smaller than the target, so the
+/// caller still resizes to the exact size. Anything else uses `decode_image`.
+pub(crate) fn decode_for_size(data: &[u8], target_w: u32, target_h: u32) -> Result<DecodedImage, String> {
+    let dimensions = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()
+        .filter(|reader| reader.format() == Some(ImageFormat::Jpeg))
+        .and_then(|reader| reader.into_dimensions().ok());
+
+    if let Some((width, height)) = dimensions {
+        let scale = [8, 4, 2].into_iter()
+            .find(|&s| width.div_ceil(s) >= target_w && height.div_ceil(s) >= target_h);
+        let scaled = scale.and_then(|s| catch_panic("Scaled JPEG decoder", || decode_jpeg_scaled(data, s)).ok());
+        if let Some(img) = scaled {
+            return Ok(DecodedImage { img, format: ImageFormat::Jpeg, format_name: format!("{:?}", ImageFormat::Jpeg) });
+        }
+    }
+
+    decode_image(data)
+}
+
+/// Convert `img` to the given color type (e.g. back to the source's type after
+/// processing in RGBA); unknown types fall back to RGBA8
+pub(crate) fn convert_color(img: &DynamicImage, color: ColorType) -> DynamicImage {
+    match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(img.to_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(img.to_rgb8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(img.to_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(img.to_rgb16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(img.to_rgba16()),
+        ColorType::Rgb32F => DynamicImage::ImageRgb32F(img.to_rgb32f()),
+        ColorType::Rgba32F => DynamicImage::ImageRgba32F(img.to_rgba32f()),
+        _ => DynamicImage::ImageRgba8(img.to_rgba8()),
+    }
+}
+
+/// sRGB transfer function: encoded value in 0..=1 to linear light
+pub(crate) fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Inverse of `srgb_to_linear`
+pub(crate) fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.003_130_8 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Whether the encoder for `format` can store an alpha channel
+pub(crate) fn supports_alpha(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Png | ImageFormat::WebP | ImageFormat::Gif | ImageFormat::Tiff
+            | ImageFormat::Tga | ImageFormat::Ico | ImageFormat::Qoi | ImageFormat::OpenExr
+    )
+}
+
+/// CSS basic color keywords accepted by `parse_color`
+const NAMED_COLORS: [(&str, [u8; 4]); 18] = [
+    ("transparent", [0, 0, 0, 0]),
+    ("black", [0, 0, 0, 255]),
+    ("white", [255, 255, 255, 255]),
+    ("gray", [128, 128, 128, 255]),
+    ("grey", [128, 128, 128, 255]),
+    ("silver", [192, 192, 192, 255]),
+    ("red", [255, 0, 0, 255]),
+    ("maroon", [128, 0, 0, 255]),
+    ("yellow", [255, 255, 0, 255]),
+    ("olive", [128, 128, 0, 255]),
+    ("lime", [0, 255, 0, 255]),
+    ("green", [0, 128, 0, 255]),
+    ("aqua", [0, 255, 255, 255]),
+    ("teal", [0, 128, 128, 255]),
+    ("blue", [0, 0, 255, 255]),
+    ("navy", [0, 0, 128, 255]),
+    ("fuchsia", [255, 0, 255, 255]),
+    ("purple", [128, 0, 128, 255]),
+];
+
+/// Arguments of CSS `rgb()`/`rgba()`: channels as 0-255 or percentages, optional
+/// alpha as 0-1 or a percentage, separated by commas or spaces (with `/` before alpha)
+fn parse_rgb_function(args: &str) -> Option<Rgba<u8>> {
+    let values: Vec<&str> = args.split([',', ' ', '/']).filter(|v| !v.is_empty()).collect();
+    if values.len() != 3 && values.len() != 4 {
+        return None;
+    }
+
+    let number = |v: &str, scale: f32| -> Option<f32> {
+        let value = match v.strip_suffix('%') {
+            Some(percent) => percent.parse::<f32>().ok()? / 100.0 * scale,
+            None => v.parse::<f32>().ok()?,
+        };
+        (value.is_finite() && (0.0..=scale).contains(&value)).then_some(value)
+    };
+    let channel = |v: &str| number(v, 255.0).map(|c| c.round() as u8);
+    let alpha = match values.get(3) {
+        Some(v) => (number(v, 1.0)? * 255.0).round() as u8,
+        None => 255,
+    };
+
+    Some(Rgba([channel(values[0])?, channel(values[1])?, channel(values[2])?, alpha]))
+}
+
+/// Parse a color string into RGBA: "#RGB", "#RRGGBB", "#RRGGBBAA", "rgb(r, g, b)",
+/// "rgba(r, g, b, a)" or a CSS basic color name such as "white" or "transparent".
+/// Every function taking a color goes through here, so they all accept the same forms.
+pub(crate) fn parse_color(s: &str) -> Result<Rgba<u8>, String> {
+    let s = s.trim();
+    let invalid = || format!(
+        "Invalid color: {} (expected #RGB, #RRGGBB, #RRGGBBAA, rgb(), rgba() or a color name)",
+        s
+    );
+
+    if let Some((_, rgba)) = NAMED_COLORS.iter().find(|(name, _)| s.eq_ignore_ascii_case(name)) {
+        return Ok(Rgba(*rgba));
+    }
+
+    let lower = s.to_ascii_lowercase();
+    if let Some(args) = lower.strip_prefix("rgba(").or_else(|| lower.strip_prefix("rgb(")) {
+        return args.strip_suffix(')').and_then(parse_rgb_function).ok_or_else(invalid);
+    }
+
+    let hex = s.strip_prefix('#')
+        .filter(|h| h.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or_else(invalid)?;
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    let short = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap() * 17;
+
+    match hex.len() {
+        3 => Ok(Rgba([short(0), short(1), short(2), 255])),
+        6 => Ok(Rgba([channel(0), channel(2), channel(4), 255])),
+        8 => Ok(Rgba([channel(0), channel(2), channel(4), channel(6)])),
+        _ => Err(invalid()),
+    }
+}
+
+/// Rotate and flip so the image displays upright for an EXIF orientation value (1-8);
+/// unknown values leave it unchanged
+pub(crate) fn apply_orientation(img: &mut DynamicImage, orientation: u32) {
+    if let Some(orientation) = u8::try_from(orientation).ok().and_then(Orientation::from_exif) {
+        img.apply_orientation(orientation);
+    }
+}
+
+/// Check that output dimensions are non-zero and within the pixel-limit guard
+pub(crate) fn check_dimensions(width: u32, height: u32) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Invalid dimensions {}x{}", width, height));
+    }
+    if width as u64 * height as u64 > MAX_PIXELS {
+        return Err(format!(
+            "Dimensions {}x{} exceed the limit of {} pixels",
+            width, height, MAX_PIXELS
+        ));
+    }
+    Ok(())
+}
+
+/// Check that a rectangle is non-empty and lies within `width`x`height`, without overflowing
+pub(crate) fn check_region(x: u32, y: u32, w: u32, h: u32, width: u32, height: u32) -> Result<(), String> {
+    if w == 0 || h == 0 {
+        return Err(format!("Region ({},{} {}x{}) is empty", x, y, w, h));
+    }
+
+    let fits = x.checked_add(w).is_some_and(|right| right <= width)
+        && y.checked_add(h).is_some_and(|bottom| bottom <= height);
+    if !fits {
+        return Err(format!(
+            "Region ({},{} {}x{}) exceeds image bounds ({}x{})",
+            x, y, w, h, width, height
+        ));
+    }
+
+    Ok(())
+}
+
+/// Sobel gradient magnitude (|gx| + |gy|) per pixel of a grayscale image, row-major.
+/// Borders are handled by clamping coordinates.
+pub(crate) fn sobel_energy(luma: &GrayImage) -> Vec<f32> {
+    let (width, height) = luma.dimensions();
+    let at = |x: i64, y: i64| -> f32 {
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        luma.get_pixel(x, y).0[0] as f32
+    };
+
+    let mut energy = Vec::with_capacity((width * height) as usize);
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1) - 2.0 * at(x - 1, y) - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1) - 2.0 * at(x, y - 1) - at(x + 1, y - 1);
+            energy.push(gx.abs() + gy.abs());
+        }
+    }
+    energy
+}
+
+/// PNG bytes of `img`, the encoded input most tests start from
+#[cfg(test)]
+pub(crate) fn encode_png_fixture(img: impl Into<DynamicImage>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    img.into().write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).unwrap();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transforms::resize_with_filter;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn scaled_jpeg_decode_matches_full_decode_and_resize() {
+        let img = RgbImage::from_fn(256, 192, |x, y| {
+            let wave = ((x as f32 / 9.0).sin() * 40.0) as i32;
+            Rgb([x as u8, (y + 40) as u8, (128 + wave) as u8])
+        });
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 92).encode_image(&img).unwrap();
+
+        for (target, scale) in [((32, 24), 8), ((64, 48), 4), ((100, 80), 2)] {
+            let scaled = decode_for_size(&jpeg, target.0, target.1).unwrap().img.to_rgb8();
+            assert_eq!(scaled.dimensions(), (256 / scale, 192 / scale));
+
+            let full = decode_image(&jpeg).unwrap().img;
+            let reference = resize_with_filter(&full, scaled.width(), scaled.height(), "box").to_rgb8();
+            let diffs: Vec<u8> = scaled.as_raw().iter().zip(reference.as_raw()).map(|(a, b)| a.abs_diff(*b)).collect();
+            let mean = diffs.iter().map(|&d| d as f64).sum::<f64>() / diffs.len() as f64;
+            assert!(mean < 2.0, "1/{} mean difference {}", scale, mean);
+            assert!(diffs.iter().all(|&d| d <= 12), "1/{} max difference {:?}", scale, diffs.iter().max());
+        }
+    }
+
+    #[test]
+    fn parse_color_accepts_hex_forms() {
+        assert_eq!(parse_color("#f80").unwrap().0, [255, 136, 0, 255]);
+        assert_eq!(parse_color("#FF8800").unwrap().0, [255, 136, 0, 255]);
+        assert_eq!(parse_color("#ff880080").unwrap().0, [255, 136, 0, 128]);
+        assert_eq!(parse_color("  #000000  ").unwrap().0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn parse_color_accepts_rgb_functions() {
+        assert_eq!(parse_color("rgb(10, 20, 30)").unwrap().0, [10, 20, 30, 255]);
+        assert_eq!(parse_color("rgba(10, 20, 30, 0.5)").unwrap().0, [10, 20, 30, 128]);
+        assert_eq!(parse_color("RGB(100%, 50%, 0%)").unwrap().0, [255, 128, 0, 255]);
+        assert_eq!(parse_color("rgb(10 20 30 / 25%)").unwrap().0, [10, 20, 30, 64]);
+        assert_eq!(parse_color("rgba(10 20 30 / 1)").unwrap().0, [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn parse_color_accepts_names_in_any_case() {
+        assert_eq!(parse_color("white").unwrap().0, [255, 255, 255, 255]);
+        assert_eq!(parse_color("Navy").unwrap().0, [0, 0, 128, 255]);
+        assert_eq!(parse_color("TRANSPARENT").unwrap().0, [0, 0, 0, 0]);
+        assert_eq!(parse_color("grey").unwrap(), parse_color("gray").unwrap());
+    }
+
+    #[test]
+    fn parse_color_rejects_malformed_input() {
+        for input in [
+            "", "#", "#12", "#12345", "#1234567", "#ggg", "123456", "#ééé",
+            "rgb(1, 2)", "rgb(1, 2, 3, 4, 5)", "rgb(256, 0, 0)", "rgb(-1, 0, 0)",
+            "rgb(1, 2, 3", "rgba(1, 2, 3, 2)", "rgb(101%, 0, 0)", "rgb(nan, 0, 0)",
+            "rgb(a, b, c)", "orange", "hsl(0, 100%, 50%)",
+        ] {
+            let err = parse_color(input).unwrap_err();
+            assert!(err.starts_with("Invalid color"), "{:?}: {}", input, err);
+        }
+    }
+
+    #[test]
+    fn catch_panic_turns_a_panic_into_an_error() {
+        let result: Result<(), String> = catch_panic("Decoder", || panic!("bad huffman table"));
+        assert_eq!(result.unwrap_err(), "Decoder panicked (bad huffman table)");
+
+        let result: Result<(), String> = catch_panic("Decoder", || panic!("{} bytes short", 12));
+        assert_eq!(result.unwrap_err(), "Decoder panicked (12 bytes short)");
+
+        assert_eq!(catch_panic("Decoder", || Ok(7)), Ok(7));
+        assert_eq!(catch_panic::<()>("Decoder", || Err("plain error".to_string())), Err("plain error".to_string()));
+    }
+
+    #[test]
+    fn malformed_images_fail_with_an_error() {
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 90)
+            .encode_image(&RgbImage::from_pixel(32, 32, Rgb([200, 10, 10]))).unwrap();
+        let png = encode_png_fixture(RgbImage::from_pixel(32, 32, Rgb([200, 10, 10])));
+
+        // Valid headers with the data cut off or overwritten, and bare garbage
+        let mut corrupted = jpeg.clone();
+        let start = corrupted.len() / 2;
+        corrupted[start..].iter_mut().for_each(|b| *b = 0xFF);
+        for data in [
+            &jpeg[..jpeg.len() / 2], &corrupted[..], &png[..png.len() / 2], &png[..40],
+            b"\x89PNG\r\n\x1a\n", b"GIF89a", b"not an image at all", &[],
+        ] {
+            assert!(decode_image(data).is_err(), "{} bytes decoded", data.len());
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use wasm_bindgen::prelude::*;
+use image::{ColorType, DynamicImage, ExtendedColorType, GrayImage, ImageFormat};
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use crate::common::{convert_color, decode_for_size, decode_image};
+
+thread_local! {
+    /// Format used when a caller passes "default", see `set_default_output_format`
+    static DEFAULT_FORMAT: Cell<Option<ImageFormat>> = const { Cell::new(None) };
+}
+
+/// Parse an output format name or extension ("png", "jpg", "jpeg", "webp", ...).
+/// "default" resolves to the format set with `set_default_output_format`.
+pub(crate) fn parse_format(name: &str) -> Result<ImageFormat, String> {
+    if name.trim() == "default" {
+        return DEFAULT_FORMAT.get()
+            .ok_or_else(|| "No default output format set; call set_default_output_format first".to_string());
+    }
+    ImageFormat::from_extension(name.trim())
+        .filter(|f| f.writing_enabled())
+        .ok_or_else(|| format!("Unsupported output format: {}", name))
+}
+
+/// Set the format that every function taking an output format uses when passed
+/// "default", e.g. "webp" for a frontend that always wants WebP. Explicit formats
+/// and empty strings (keep the source format) are unaffected. An empty `format`
+/// clears the default.
+#[wasm_bindgen]
+pub fn set_default_output_format(format: &str) -> Result<(), String> {
+    let format = if format.trim().is_empty() { None } else { Some(parse_format(format)?) };
+    DEFAULT_FORMAT.set(format);
+    Ok(())
+}
+
+/// Encode in `format`; `quality` (1-100) applies to lossy JPEG output only
+pub(crate) fn encode_to(img: &DynamicImage, format: ImageFormat, quality: u8) -> Result<Vec<u8>, String> {
+    if format == ImageFormat::Jpeg {
+        return encode_jpeg(img, quality, false);
+    }
+
+    let mut output = Vec::new();
+    img.write_to(&mut Cursor::new(&mut output), format)
+        .map_err(|e| format!("Failed to encode {:?} image: {}", format, e))?;
+
+    Ok(output)
+}
+
+/// Encode as JPEG at the given quality (1-100). The image crate only writes baseline
+/// JPEGs, so progressive output goes through `jpeg-encoder` instead.
+pub(crate) fn encode_jpeg(img: &DynamicImage, quality: u8, progressive: bool) -> Result<Vec<u8>, String> {
+    let quality = quality.clamp(1, 100);
+    let rgb = img.to_rgb8();
+    let mut output = Vec::new();
+
+    if progressive {
+        let (width, height) = match (u16::try_from(rgb.width()), u16::try_from(rgb.height())) {
+            (Ok(w), Ok(h)) => (w, h),
+            _ => return Err(format!("Image too large for JPEG ({}x{})", rgb.width(), rgb.height())),
+        };
+
+        let mut encoder = jpeg_encoder::Encoder::new(&mut output, quality);
+        encoder.set_progressive(true);
+        encoder.encode(rgb.as_raw(), width, height, jpeg_encoder::ColorType::Rgb)
+            .map_err(|e| format!("Failed to encode JPEG: {}", e))?;
+    } else {
+        JpegEncoder::new_with_quality(&mut output, quality)
+            .encode_image(&rgb)
+            .map_err(|e| format!("Failed to encode JPEG: {}", e))?;
+    }
+
+    Ok(output)
+}
+
+/// PNG color type to write. `Auto` drops an unused alpha channel and stores
+/// grayscale content as gray, keeping the source bit depth.
+#[derive(Clone, Copy)]
+pub(crate) enum PngColor {
+    Auto,
+    Rgb,
+    Rgba,
+    Gray,
+    Palette,
+}
+
+impl PngColor {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "" | "auto" => Ok(PngColor::Auto),
+            "rgb" => Ok(PngColor::Rgb),
+            "rgba" => Ok(PngColor::Rgba),
+            "gray" => Ok(PngColor::Gray),
+            "palette" => Ok(PngColor::Palette),
+            _ => Err(format!("Invalid PNG color type: {}", name)),
+        }
+    }
+}
+
+/// Smallest lossless color type for the content of `img`
+fn auto_png_color(img: &DynamicImage) -> ColorType {
+    let rgba = img.to_rgba16();
+    let opaque = rgba.pixels().all(|p| p.0[3] == u16::MAX);
+    let gray = rgba.pixels().all(|p| p.0[0] == p.0[1] && p.0[1] == p.0[2]);
+    let wide = img.color().bytes_per_pixel() / img.color().channel_count() > 1;
+
+    match (gray, opaque, wide) {
+        (true, true, false) => ColorType::L8,
+        (true, false, false) => ColorType::La8,
+        (false, true, false) => ColorType::Rgb8,
+        (false, false, false) => ColorType::Rgba8,
+        (true, true, true) => ColorType::L16,
+        (true, false, true) => ColorType::La16,
+        (false, true, true) => ColorType::Rgb16,
+        (false, false, true) => ColorType::Rgba16,
+    }
+}
+
+/// Write an 8-bit indexed PNG. Fails when the image has more than 256 distinct colors,
+/// since that would need lossy quantization.
+fn encode_png_palette(img: &DynamicImage) -> Result<Vec<u8>, String> {
+    let rgba = img.to_rgba8();
+    let mut palette: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut entries: Vec<[u8; 4]> = Vec::new();
+    let mut indices = Vec::with_capacity(rgba.width() as usize * rgba.height() as usize);
+
+    for pixel in rgba.pixels() {
+        let index = match palette.get(&pixel.0) {
+            Some(&index) => index,
+            None => {
+                if entries.len() == 256 {
+                    return Err("Image has more than 256 colors and cannot be written as a palette PNG".to_string());
+                }
+                let index = entries.len() as u8;
+                palette.insert(pixel.0, index);
+                entries.push(pixel.0);
+                index
+            }
+        };
+        indices.push(index);
+    }
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, rgba.width(), rgba.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(entries.iter().flat_map(|e| [e[0], e[1], e[2]]).collect::<Vec<u8>>());
+    if entries.iter().any(|e| e[3] < 255) {
+        encoder.set_trns(entries.iter().map(|e| e[3]).collect::<Vec<u8>>());
+    }
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(&indices))
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+
+    Ok(output)
+}
+
+/// Write a black and white image as a 1-bit grayscale PNG (pixels of 128 and above are white)
+pub(crate) fn encode_png_1bit(img: &GrayImage) -> Result<Vec<u8>, String> {
+    let packed: Vec<u8> = img.rows()
+        .flat_map(|row| {
+            let bits: Vec<bool> = row.map(|p| p.0[0] >= 128).collect();
+            bits.chunks(8)
+                .map(|chunk| chunk.iter().enumerate().fold(0u8, |byte, (i, &white)| byte | ((white as u8) << (7 - i))))
+                .collect::<Vec<u8>>()
+        })
+        .collect();
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, img.width(), img.height());
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::One);
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(&packed))
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+
+    Ok(output)
+}
+
+/// Encode as PNG with the requested color type
+pub(crate) fn encode_png(img: &DynamicImage, color: PngColor) -> Result<Vec<u8>, String> {
+    let target = match color {
+        PngColor::Auto => auto_png_color(img),
+        PngColor::Rgb => ColorType::Rgb8,
+        PngColor::Rgba => ColorType::Rgba8,
+        PngColor::Gray => ColorType::L8,
+        PngColor::Palette => return encode_png_palette(img),
+    };
+
+    let mut output = Vec::new();
+    convert_color(img, target).write_to(&mut Cursor::new(&mut output), ImageFormat::Png)
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+
+    Ok(output)
+}
+
+/// Re-encode any supported image as PNG with the given color type: "auto" (default),
+/// "rgb", "rgba", "gray" or "palette" (at most 256 distinct colors)
+#[wasm_bindgen]
+pub fn convert_to_png(data: &[u8], png_color: &str) -> Result<Vec<u8>, String> {
+    let color = PngColor::parse(png_color)?;
+    let decoded = decode_image(data)?;
+    encode_png(&decoded.img, color)
+}
+
+/// Re-encode any supported image as JPEG. Progressive JPEGs render incrementally
+/// while loading, which helps perceived speed for large images on slow connections.
+#[wasm_bindgen]
+pub fn convert_to_jpeg(data: &[u8], quality: u8, progressive: bool) -> Result<Vec<u8>, String> {
+    let decoded = decode_image(data)?;
+    encode_jpeg(&decoded.img, quality, progressive)
+}
+
+/// One encoding of an image, for comparing formats side by side
+#[wasm_bindgen(getter_with_clone)]
+pub struct EncodedVariant {
+    pub format: String,
+    pub bytes: Vec<u8>,
+    pub size: u32,
+}
+
+/// Downscale to fit `max_edge` (never upscaling) and encode as JPEG (quality 82), WebP
+/// and PNG, so the frontend can show which format is smallest. The image crate only
+/// writes lossless WebP, so the WebP variant is lossless rather than quality 80.
+#[wasm_bindgen]
+pub fn encode_variants(data: &[u8], max_edge: u32) -> Result<Vec<EncodedVariant>, String> {
+    if max_edge == 0 {
+        return Err("Maximum edge must be greater than 0".to_string());
+    }
+
+    let decoded = decode_image(data)?;
+    let img = if decoded.img.width().max(decoded.img.height()) > max_edge {
+        decoded.img.resize(max_edge, max_edge, FilterType::Lanczos3)
+    } else {
+        decoded.img
+    };
+
+    // The WebP encoder only accepts 8-bit buffers
+    let webp_color = if img.color().has_alpha() { ColorType::Rgba8 } else { ColorType::Rgb8 };
+
+    let variants = [
+        ("jpeg", encode_jpeg(&img, 82, false)?),
+        ("webp", encode_to(&convert_color(&img, webp_color), ImageFormat::WebP, 80)?),
+        ("png", encode_png(&img, PngColor::Auto)?),
+    ];
+
+    Ok(variants.into_iter()
+        .map(|(format, bytes)| EncodedVariant { format: format.to_string(), size: bytes.len() as u32, bytes })
+        .collect())
+}
+
+/// Build a multi-size favicon: the centered square of the image is scaled to each of
+/// `sizes` (1-256 px, ICO's limit) and stored as PNG entries in one .ico file
+#[wasm_bindgen]
+pub fn generate_favicon(data: &[u8], sizes: Vec<u32>) -> Result<Vec<u8>, String> {
+    if sizes.is_empty() {
+        return Err("At least one favicon size is required".to_string());
+    }
+    if let Some(size) = sizes.iter().find(|&&s| s == 0 || s > 256) {
+        return Err(format!("Favicon size {} is outside the ICO limit of 1-256", size));
+    }
+
+    let decoded = decode_image(data)?;
+    let (width, height) = (decoded.img.width(), decoded.img.height());
+    let side = width.min(height);
+    let square = decoded.img.crop_imm((width - side) / 2, (height - side) / 2, side, side);
+
+    let mut pngs = Vec::with_capacity(sizes.len());
+    for &size in &sizes {
+        let icon = square.resize_exact(size, size, FilterType::Lanczos3).to_rgba8();
+        pngs.push((size, encode_png(&DynamicImage::ImageRgba8(icon), PngColor::Rgba)?));
+    }
+
+    let frames = pngs.iter()
+        .map(|(size, png)| IcoFrame::with_encoded(png.as_slice(), *size, *size, ExtendedColorType::Rgba8))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to create favicon entry: {}", e))?;
+
+    let mut output = Vec::new();
+    IcoEncoder::new(&mut output).encode_images(&frames)
+        .map_err(|e| format!("Failed to encode favicon: {}", e))?;
+
+    Ok(output)
+}
+
+/// Standard base64 with padding, as used in data URLs
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Largest edge accepted by `tiny_placeholder`; placeholders are meant to be blurred up
+const MAX_PLACEHOLDER_EDGE: u32 = 64;
+
+/// Downscale to fit `max_edge` (1-64, 16-32 is typical) and return a ready-to-use
+/// `data:image/...;base64,...` URL for lazy-loading and SSR placeholders. An empty
+/// `format` picks JPEG (quality 60) for opaque images and PNG for transparent ones.
+/// For a photo the URL is about 1 KB as JPEG at 16 or 32 px, most of it JPEG headers;
+/// as PNG it is about 0.5 KB at 16 px but over 1.3 KB at 32 px.
+#[wasm_bindgen]
+pub fn tiny_placeholder(data: &[u8], max_edge: u32, format: &str) -> Result<String, String> {
+    if max_edge == 0 || max_edge > MAX_PLACEHOLDER_EDGE {
+        return Err(format!("Placeholder edge must be between 1 and {} (got {})", MAX_PLACEHOLDER_EDGE, max_edge));
+    }
+
+    let decoded = decode_for_size(data, max_edge, max_edge)?;
+    let (width, height) = (decoded.img.width(), decoded.img.height());
+    let scale = (max_edge as f64 / width.max(height) as f64).min(1.0);
+    let img = decoded.img.resize_exact(
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+        FilterType::Triangle,
+    );
+
+    let format = match format {
+        "" if img.color().has_alpha() => ImageFormat::Png,
+        "" => ImageFormat::Jpeg,
+        name => parse_format(name)?,
+    };
+    let bytes = match format {
+        ImageFormat::Jpeg => encode_jpeg(&img, 60, false)?,
+        ImageFormat::Png => encode_png(&img, PngColor::Auto)?,
+        ImageFormat::WebP => {
+            let color = if img.color().has_alpha() { ColorType::Rgba8 } else { ColorType::Rgb8 };
+            encode_to(&convert_color(&img, color), format, 60)?
+        }
+        _ => encode_to(&img, format, 60)?,
+    };
+
+    Ok(format!("data:{};base64,{}", format.to_mime_type(), base64_encode(&bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(40, 24, |x, y| Rgb([(x * 6) as u8, (y * 10) as u8, 128])))
+    }
+
+    fn has_marker(jpeg: &[u8], marker: u8) -> bool {
+        jpeg.windows(2).any(|w| w == [0xFF, marker])
+    }
+
+    #[test]
+    fn progressive_jpeg_has_sof2_and_decodes() {
+        let jpeg = encode_jpeg(&gradient(), 90, true).unwrap();
+        assert!(has_marker(&jpeg, 0xC2));
+        assert!(!has_marker(&jpeg, 0xC0));
+
+        let decoded = image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (40, 24));
+    }
+
+    #[test]
+    fn baseline_jpeg_has_sof0() {
+        let jpeg = encode_jpeg(&gradient(), 90, false).unwrap();
+        assert!(has_marker(&jpeg, 0xC0));
+        assert!(!has_marker(&jpeg, 0xC2));
+    }
+
+    /// Color type byte of a PNG's IHDR chunk (0 gray, 2 RGB, 3 palette, 4 gray+alpha, 6 RGBA)
+    fn png_color_type(png: &[u8]) -> u8 {
+        assert_eq!(&png[12..16], b"IHDR");
+        png[25]
+    }
+
+    #[test]
+    fn auto_png_drops_unused_alpha() {
+        let opaque = DynamicImage::ImageRgba8(gradient().to_rgba8());
+        assert_eq!(png_color_type(&encode_png(&opaque, PngColor::Auto).unwrap()), 2);
+        assert_eq!(png_color_type(&encode_png(&opaque, PngColor::Rgba).unwrap()), 6);
+    }
+
+    #[test]
+    fn auto_png_detects_gray_and_translucent_content() {
+        let gray = DynamicImage::ImageRgb8(RgbImage::from_fn(8, 8, |x, _| Rgb([x as u8 * 30; 3])));
+        assert_eq!(png_color_type(&encode_png(&gray, PngColor::Auto).unwrap()), 0);
+
+        let mut translucent = gradient().to_rgba8();
+        translucent.get_pixel_mut(0, 0).0[3] = 10;
+        assert_eq!(png_color_type(&encode_png(&DynamicImage::ImageRgba8(translucent), PngColor::Auto).unwrap()), 6);
+    }
+
+    #[test]
+    fn palette_png_round_trips() {
+        let two_colors = DynamicImage::ImageRgb8(RgbImage::from_fn(8, 8, |x, _| Rgb(if x < 4 { [255, 0, 0] } else { [0, 0, 255] })));
+        let png = encode_png(&two_colors, PngColor::Palette).unwrap();
+        assert_eq!(png_color_type(&png), 3);
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgb8(), two_colors.to_rgb8());
+    }
+}
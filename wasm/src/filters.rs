@@ -0,0 +1,652 @@
+use wasm_bindgen::prelude::*;
+use image::{DynamicImage, GrayImage, ImageBuffer, ImageFormat, Luma, Pixel, Rgba, RgbaImage};
+use image::imageops::{replace, unsharpen};
+use std::io::Cursor;
+
+use crate::adjustments::luminance;
+use crate::common::{check_region, decode_image};
+use crate::encode::encode_png_1bit;
+use crate::parallel::for_each_row;
+
+/// A changed rectangle of raw RGBA pixels (`width * height * 4` bytes) and its
+/// position in the full image, for patching a canvas with `putImageData`
+#[wasm_bindgen(getter_with_clone)]
+pub struct RegionPatch {
+    pub pixels: Vec<u8>,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Gaussian blur in place on an interleaved f32 buffer, as a horizontal then a
+/// vertical 1D pass. The kernel spans 3 sigma each side and edges are clamped. Cost
+/// grows linearly with sigma instead of quadratically as with a 2D kernel.
+pub(crate) fn blur_channels(buf: &mut [f32], width: usize, height: usize, channels: usize, sigma: f32) {
+    let radius = (sigma * 3.0).ceil() as usize;
+    let mut kernel: Vec<f32> = (0..=2 * radius)
+        .map(|i| {
+            let d = i as f32 - radius as f32;
+            (-d * d / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let total: f32 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|w| *w /= total);
+
+    // Convolve one line (a row, or a gathered column) with clamped edges
+    let convolve_line = |src: &[f32], dst: &mut [f32], len: usize| {
+        for i in 0..len {
+            for c in 0..channels {
+                dst[i * channels + c] = kernel.iter().enumerate()
+                    .map(|(k, w)| {
+                        let j = (i + k).saturating_sub(radius).min(len - 1);
+                        src[j * channels + c] * w
+                    })
+                    .sum();
+            }
+        }
+    };
+
+    let row_len = width * channels;
+    for_each_row(buf, row_len, |_, row| {
+        let line = row.to_vec();
+        convolve_line(&line, row, width);
+    });
+
+    // The vertical pass blends whole source rows into each output row
+    let source = buf.to_vec();
+    for_each_row(buf, row_len, |y, row| {
+        row.fill(0.0);
+        for (k, w) in kernel.iter().enumerate() {
+            let sy = (y + k).saturating_sub(radius).min(height - 1);
+            for (value, src) in row.iter_mut().zip(&source[sy * row_len..(sy + 1) * row_len]) {
+                *value += src * w;
+            }
+        }
+    });
+}
+
+/// Separable Gaussian blur of an 8-bit image (all channels, including alpha)
+pub(crate) fn gaussian_separable<P: Pixel<Subpixel = u8>>(img: &ImageBuffer<P, Vec<u8>>, sigma: f32) -> ImageBuffer<P, Vec<u8>> {
+    let mut buf: Vec<f32> = img.as_raw().iter().map(|&v| v as f32).collect();
+    blur_channels(&mut buf, img.width() as usize, img.height() as usize, P::CHANNEL_COUNT as usize, sigma);
+
+    let raw = buf.into_iter().map(|v| v.round().clamp(0.0, 255.0) as u8).collect();
+    ImageBuffer::from_raw(img.width(), img.height(), raw).expect("buffer size matches the image")
+}
+
+/// Gaussian-blur the given rectangle, sampling a margin around it so the edges
+/// blend with their surroundings. Returns the blurred rectangle only.
+fn blur_rect(img: &RgbaImage, x: u32, y: u32, w: u32, h: u32, sigma: f32) -> RgbaImage {
+    let margin = (sigma * 3.0).ceil() as u32;
+    let left = x.saturating_sub(margin);
+    let top = y.saturating_sub(margin);
+    let right = (x + w).saturating_add(margin).min(img.width());
+    let bottom = (y + h).saturating_add(margin).min(img.height());
+
+    let area = image::imageops::crop_imm(img, left, top, right - left, bottom - top).to_image();
+    let blurred = gaussian_separable(&area, sigma);
+
+    image::imageops::crop_imm(&blurred, x - left, y - top, w, h).to_image()
+}
+
+/// Largest accepted Gaussian blur sigma; the kernel and the edge margin span 3 sigma
+pub(crate) const MAX_BLUR_SIGMA: f32 = 100.0;
+
+fn validate_blur(sigma: f32) -> Result<(), String> {
+    if !sigma.is_finite() || sigma <= 0.0 || sigma > MAX_BLUR_SIGMA {
+        return Err(format!("Blur sigma must be between 0 and {} (got {})", MAX_BLUR_SIGMA, sigma));
+    }
+    Ok(())
+}
+
+/// Gaussian-blur the whole image with standard deviation `sigma` (in pixels)
+#[wasm_bindgen]
+pub fn blur_image(data: &[u8], sigma: f32) -> Result<Vec<u8>, String> {
+    validate_blur(sigma)?;
+    let decoded = decode_image(data)?;
+    let blurred = gaussian_separable(&decoded.img.to_rgba8(), sigma);
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(blurred).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode blurred image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Blur a rectangular region of the image, leaving the rest untouched
+#[wasm_bindgen]
+pub fn blur_region(data: &[u8], x: u32, y: u32, width: u32, height: u32, sigma: f32) -> Result<Vec<u8>, String> {
+    validate_blur(sigma)?;
+    let decoded = decode_image(data)?;
+    check_region(x, y, width, height, decoded.img.width(), decoded.img.height())?;
+
+    let mut rgba = decoded.img.to_rgba8();
+    let patch = blur_rect(&rgba, x, y, width, height, sigma);
+    replace(&mut rgba, &patch, x as i64, y as i64);
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode blurred image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Same as `blur_region`, but returns only the changed rectangle as raw RGBA pixels
+/// plus its offset, so interactive editors can patch their canvas instead of
+/// re-decoding the whole image
+#[wasm_bindgen]
+pub fn blur_region_patch(data: &[u8], x: u32, y: u32, width: u32, height: u32, sigma: f32) -> Result<RegionPatch, String> {
+    validate_blur(sigma)?;
+    let decoded = decode_image(data)?;
+    check_region(x, y, width, height, decoded.img.width(), decoded.img.height())?;
+
+    let patch = blur_rect(&decoded.img.to_rgba8(), x, y, width, height, sigma);
+
+    Ok(RegionPatch { pixels: patch.into_raw(), x, y, width, height })
+}
+
+/// Largest accepted `bokeh_blur` radius; the cost grows with its square
+const MAX_BOKEH_RADIUS: u32 = 100;
+
+/// Lens-like defocus: average every pixel over a flat disc of `radius` pixels, so
+/// bright points spread into discs instead of the soft blobs of a Gaussian blur.
+/// Edges are clamped and all four channels are averaged. Each output pixel reads
+/// about 3.14 * radius² samples, so the cost is O(width * height * radius²); keep
+/// the radius small on large images or blur a downscaled copy. 0 returns the input.
+/// A disc is not separable into 1D passes, so `gaussian_separable` cannot be used here.
+#[wasm_bindgen]
+pub fn bokeh_blur(data: &[u8], radius: u32) -> Result<Vec<u8>, String> {
+    if radius > MAX_BOKEH_RADIUS {
+        return Err(format!("Bokeh radius must be at most {} (got {})", MAX_BOKEH_RADIUS, radius));
+    }
+    if radius == 0 {
+        return Ok(data.to_vec());
+    }
+
+    let decoded = decode_image(data)?;
+    let rgba = decoded.img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+
+    let r = radius as i64;
+    let offsets: Vec<(i64, i64)> = (-r..=r)
+        .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+        // (r + 0.5)² avoids single-pixel spikes at the ends of the axes
+        .filter(|(dx, dy)| dx * dx + dy * dy <= r * r + r)
+        .collect();
+    let samples = offsets.len() as u32;
+
+    let blurred = RgbaImage::from_fn(width, height, |x, y| {
+        let mut sum = [0u32; 4];
+        for &(dx, dy) in &offsets {
+            let sx = (x as i64 + dx).clamp(0, width as i64 - 1) as u32;
+            let sy = (y as i64 + dy).clamp(0, height as i64 - 1) as u32;
+            for (total, &v) in sum.iter_mut().zip(&rgba.get_pixel(sx, sy).0) {
+                *total += v as u32;
+            }
+        }
+        Rgba(sum.map(|total| ((total + samples / 2) / samples).min(255) as u8))
+    });
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(blurred).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode blurred image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Largest spatial sigma accepted by `bilateral_filter`; the window grows with its square
+const MAX_BILATERAL_SIGMA: f32 = 20.0;
+
+/// Edge-preserving smoothing: each pixel becomes a weighted mean of its neighbors
+/// within 2 * `spatial_sigma` pixels, where the weight falls off with distance
+/// (Gaussian, `spatial_sigma`) and with RGB difference (Gaussian, `range_sigma` in
+/// 0..255 units). Neighbors across a strong edge differ a lot in color and barely
+/// count, so edges stay sharp while noise in flat areas is averaged away. Typical
+/// denoising values are 2-5 and 20-40. Each output pixel reads about
+/// 12.6 * spatial_sigma² neighbors, so a sigma of 5 on a 12 MP photo is billions of
+/// samples; run it on a downscaled copy for previews. Alpha is kept.
+#[wasm_bindgen]
+pub fn bilateral_filter(data: &[u8], spatial_sigma: f32, range_sigma: f32) -> Result<Vec<u8>, String> {
+    if !spatial_sigma.is_finite() || spatial_sigma <= 0.0 || spatial_sigma > MAX_BILATERAL_SIGMA {
+        return Err(format!("Spatial sigma must be between 0 and {} (got {})", MAX_BILATERAL_SIGMA, spatial_sigma));
+    }
+    if !range_sigma.is_finite() || range_sigma <= 0.0 || range_sigma > 255.0 {
+        return Err(format!("Range sigma must be between 0 and 255 (got {})", range_sigma));
+    }
+
+    let decoded = decode_image(data)?;
+    let rgba = decoded.img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+
+    let r = (2.0 * spatial_sigma).ceil() as i64;
+    let offsets: Vec<(i64, i64, f32)> = (-r..=r)
+        .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+        .filter(|(dx, dy)| dx * dx + dy * dy <= r * r)
+        .map(|(dx, dy)| (dx, dy, (-((dx * dx + dy * dy) as f32) / (2.0 * spatial_sigma * spatial_sigma)).exp()))
+        .collect();
+    // Range weights by rounded RGB distance, which is at most 255 * sqrt(3) < 442
+    let range_weights: Vec<f32> = (0..=442)
+        .map(|d| (-((d * d) as f32) / (2.0 * range_sigma * range_sigma)).exp())
+        .collect();
+
+    let mut filtered = RgbaImage::new(width, height);
+    for_each_row(&mut filtered, width as usize * 4, |y, row| {
+        let y = y as i64;
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let center = rgba.get_pixel(x as u32, y as u32).0;
+            let mut sum = [0.0f32; 3];
+            let mut total = 0.0;
+            for &(dx, dy, spatial) in &offsets {
+                let sx = (x as i64 + dx).clamp(0, width as i64 - 1) as u32;
+                let sy = (y + dy).clamp(0, height as i64 - 1) as u32;
+                let neighbor = rgba.get_pixel(sx, sy).0;
+                let distance = (0..3)
+                    .map(|c| (neighbor[c] as f32 - center[c] as f32).powi(2))
+                    .sum::<f32>()
+                    .sqrt();
+                let weight = spatial * range_weights[distance.round() as usize];
+                for c in 0..3 {
+                    sum[c] += neighbor[c] as f32 * weight;
+                }
+                total += weight;
+            }
+            let channel = |c: usize| (sum[c] / total).round().clamp(0.0, 255.0) as u8;
+            pixel.copy_from_slice(&[channel(0), channel(1), channel(2), center[3]]);
+        }
+    });
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(filtered).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode filtered image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Convolve the RGB channels with a square `size`x`size` kernel, clamping at the
+/// borders. Each result is `sum / divisor + offset`, clamped to 0..255; alpha is kept.
+fn convolve(img: &RgbaImage, kernel: &[f32], size: usize, divisor: f32, offset: f32) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let radius = (size / 2) as i64;
+    let mut output = RgbaImage::new(width, height);
+
+    for_each_row(&mut output, width as usize * 4, |y, row| {
+        let y = y as u32;
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let x = x as u32;
+            let mut sum = [0.0f32; 3];
+            for ky in 0..size {
+                let sy = (y as i64 + ky as i64 - radius).clamp(0, height as i64 - 1) as u32;
+                for kx in 0..size {
+                    let sx = (x as i64 + kx as i64 - radius).clamp(0, width as i64 - 1) as u32;
+                    let weight = kernel[ky * size + kx];
+                    let src = img.get_pixel(sx, sy).0;
+                    for c in 0..3 {
+                        sum[c] += src[c] as f32 * weight;
+                    }
+                }
+            }
+
+            let alpha = img.get_pixel(x, y).0[3];
+            let channel = |v: f32| (v / divisor + offset).round().clamp(0.0, 255.0) as u8;
+            pixel.copy_from_slice(&[channel(sum[0]), channel(sum[1]), channel(sum[2]), alpha]);
+        }
+    });
+
+    output
+}
+
+/// Return the side length of a supported kernel: 9 weights for 3x3 or 25 for 5x5.
+/// Larger kernels are refused, as convolution cost grows with the square of the size.
+fn kernel_size(kernel: &[f32]) -> Result<usize, String> {
+    match kernel.len() {
+        9 => Ok(3),
+        25 => Ok(5),
+        len => Err(format!("Kernel length {} must be 9 (3x3) or 25 (5x5)", len)),
+    }
+}
+
+/// Apply a user-supplied convolution kernel (row-major, 3x3 or 5x5) to each color channel
+#[wasm_bindgen]
+pub fn apply_kernel(data: &[u8], kernel: Vec<f32>, divisor: f32, offset: f32) -> Result<Vec<u8>, String> {
+    let size = kernel_size(&kernel)?;
+    if !divisor.is_finite() || divisor == 0.0 {
+        return Err(format!("Invalid kernel divisor: {}", divisor));
+    }
+    if !offset.is_finite() {
+        return Err(format!("Invalid kernel offset: {}", offset));
+    }
+    if kernel.iter().any(|w| !w.is_finite()) {
+        return Err("Kernel weights must be finite numbers".to_string());
+    }
+
+    let decoded = decode_image(data)?;
+    let filtered = convolve(&decoded.img.to_rgba8(), &kernel, size, divisor, offset);
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(filtered).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode filtered image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Emboss: a zero-sum directional kernel scaled by `strength`, offset by 128 so flat
+/// areas become neutral gray and edges appear raised. Alpha is preserved.
+#[wasm_bindgen]
+pub fn emboss_image(data: &[u8], strength: f32) -> Result<Vec<u8>, String> {
+    if !strength.is_finite() {
+        return Err(format!("Invalid emboss strength: {}", strength));
+    }
+
+    let kernel: Vec<f32> = [
+        -1.0, -1.0, 0.0,
+        -1.0, 0.0, 1.0,
+        0.0, 1.0, 1.0,
+    ].iter().map(|w| w * strength).collect();
+
+    apply_kernel(data, kernel, 1.0, 128.0)
+}
+
+/// Red-to-green/blue ratio above which a pixel starts being treated as red-eye
+const RED_EYE_MIN_RATIO: f32 = 1.5;
+
+/// Remove red-eye inside a rectangle: strongly red pixels (red well above the mean
+/// of green and blue) get their red replaced by that mean, giving a dark neutral
+/// pupil. The correction fades in over the ratio range 1.5-2 to avoid hard edges,
+/// and nothing outside the rectangle is changed.
+#[wasm_bindgen]
+pub fn remove_red_eye(data: &[u8], x: u32, y: u32, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let decoded = decode_image(data)?;
+    check_region(x, y, width, height, decoded.img.width(), decoded.img.height())?;
+
+    let mut rgba = decoded.img.to_rgba8();
+    for py in y..y + height {
+        for px in x..x + width {
+            let pixel = rgba.get_pixel_mut(px, py);
+            let [r, g, b, _] = pixel.0.map(|c| c as f32);
+            let neutral = (g + b) / 2.0;
+            if r < 50.0 || r < neutral * RED_EYE_MIN_RATIO {
+                continue;
+            }
+
+            let ratio = r / neutral.max(1.0);
+            let weight = ((ratio - RED_EYE_MIN_RATIO) / 0.5).clamp(0.0, 1.0);
+            pixel.0[0] = (r + (neutral - r) * weight).round() as u8;
+        }
+    }
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Large-radius unsharp mask on luminance, weighted towards midtones (`amount` -100..100)
+pub(crate) fn clarity(rgba: &mut RgbaImage, amount: f32) {
+    let (width, height) = rgba.dimensions();
+
+    let luma: ImageBuffer<Luma<f32>, Vec<f32>> = ImageBuffer::from_fn(width, height, |x, y| {
+        let [r, g, b, _] = rgba.get_pixel(x, y).0;
+        Luma([luminance(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)])
+    });
+
+    // Radius scales with the image so the effect looks the same at any resolution
+    let sigma = (width.max(height) as f32 / 50.0).clamp(4.0, 60.0);
+    let mut blurred = luma.clone();
+    blur_channels(&mut blurred, width as usize, height as usize, 1, sigma);
+    let strength = amount / 100.0;
+
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        let l = luma.get_pixel(x, y).0[0];
+        let detail = l - blurred.get_pixel(x, y).0[0];
+        let midtone_weight = 1.0 - (2.0 * l - 1.0).powi(2);
+        let delta = strength * detail * midtone_weight * 255.0;
+        for c in 0..3 {
+            pixel.0[c] = (pixel.0[c] as f32 + delta).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Clarity: a large-radius unsharp mask on luminance that boosts midtone local contrast.
+/// The effect is weighted towards midtones so shadows and highlights do not clip or
+/// halo. `amount` ranges from -100 (soften) to 100; 0 returns the input unchanged.
+#[wasm_bindgen]
+pub fn apply_clarity(data: &[u8], amount: f32) -> Result<Vec<u8>, String> {
+    if !amount.is_finite() || !(-100.0..=100.0).contains(&amount) {
+        return Err(format!("Clarity amount must be between -100 and 100 (got {})", amount));
+    }
+    if amount == 0.0 {
+        return Ok(data.to_vec());
+    }
+
+    let decoded = decode_image(data)?;
+    let mut rgba = decoded.img.to_rgba8();
+    clarity(&mut rgba, amount);
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Read a `[r, g, b, a]` array returned from a JS callback
+fn js_to_rgba(value: &JsValue) -> Option<[u8; 4]> {
+    let array = value.dyn_ref::<js_sys::Array>()?;
+    if array.length() != 4 {
+        return None;
+    }
+
+    let mut pixel = [0u8; 4];
+    for (i, channel) in pixel.iter_mut().enumerate() {
+        let v = array.get(i as u32).as_f64().filter(|v| v.is_finite())?;
+        *channel = v.round().clamp(0.0, 255.0) as u8;
+    }
+    Some(pixel)
+}
+
+/// Run a JS callback `(x, y, r, g, b, a) => [r, g, b, a]` on every pixel.
+///
+/// Each pixel costs a JS round trip, so this is orders of magnitude slower than the
+/// built-in operations; use it for prototyping or small images and prefer the native
+/// functions for bulk work.
+#[wasm_bindgen]
+pub fn map_pixels(data: &[u8], callback: &js_sys::Function) -> Result<Vec<u8>, String> {
+    let decoded = decode_image(data)?;
+    let mut rgba = decoded.img.to_rgba8();
+
+    let args = js_sys::Array::new_with_length(6);
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        for (i, v) in [x, y, r as u32, g as u32, b as u32, a as u32].into_iter().enumerate() {
+            args.set(i as u32, JsValue::from(v));
+        }
+
+        let result = callback.apply(&JsValue::NULL, &args)
+            .map_err(|e| format!("map_pixels callback threw at ({}, {}): {:?}", x, y, e))?;
+        pixel.0 = js_to_rgba(&result).ok_or_else(|| format!(
+            "map_pixels callback must return [r, g, b, a] with numeric values, got {:?} at ({}, {})",
+            result, x, y
+        ))?;
+    }
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode mapped image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Binarize against the mean of each pixel's `window`x`window` neighborhood minus
+/// `offset`: pixels brighter than that local threshold become white, the rest black.
+/// Window sums come from an integral image, so the cost does not depend on `window`.
+pub(crate) fn adaptive_binarize(gray: &GrayImage, window: u32, offset: i32) -> GrayImage {
+    let (width, height) = (gray.width() as usize, gray.height() as usize);
+    let pixels = gray.as_raw();
+
+    let stride = width + 1;
+    let mut integral = vec![0u64; stride * (height + 1)];
+    for y in 0..height {
+        let mut row_sum = 0u64;
+        for x in 0..width {
+            row_sum += pixels[y * width + x] as u64;
+            integral[(y + 1) * stride + x + 1] = integral[y * stride + x + 1] + row_sum;
+        }
+    }
+
+    let half = (window / 2) as usize;
+    GrayImage::from_fn(gray.width(), gray.height(), |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let (x0, y0) = (x.saturating_sub(half), y.saturating_sub(half));
+        let (x1, y1) = ((x + half + 1).min(width), (y + half + 1).min(height));
+        let sum = (integral[y1 * stride + x1] + integral[y0 * stride + x0]
+            - integral[y0 * stride + x1] - integral[y1 * stride + x0]) as i64;
+        let count = ((x1 - x0) * (y1 - y0)) as i64;
+
+        // value > mean - offset, kept in integers by scaling with the sample count
+        let value = pixels[y * width + x] as i64;
+        Luma([if value * count > sum - offset as i64 * count { 255 } else { 0 }])
+    })
+}
+
+/// Binarize unevenly lit documents: each pixel becomes white when its luminance is
+/// above the mean of its `window`x`window` neighborhood (odd sizes; even ones are
+/// rounded up) minus `offset`, and black otherwise. Larger offsets keep more of the
+/// background white; a window of about a text line's height works well. The cost per
+/// pixel is constant for any window thanks to an integral image. Transparent areas
+/// count as white.
+#[wasm_bindgen]
+pub fn adaptive_threshold(data: &[u8], window: u32, offset: i32) -> Result<Vec<u8>, String> {
+    if window < 3 {
+        return Err(format!("Threshold window must be at least 3 pixels (got {})", window));
+    }
+
+    let decoded = decode_image(data)?;
+    let binary = adaptive_binarize(&to_gray_on_white(&decoded.img), window, offset);
+
+    let mut output = Vec::new();
+    DynamicImage::ImageLuma8(binary).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode thresholded image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Luminance with transparent areas composited onto white, as scanned paper would be
+pub(crate) fn to_gray_on_white(img: &DynamicImage) -> GrayImage {
+    let rgba = img.to_rgba8();
+    GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let l = luminance(r as f32, g as f32, b as f32);
+        let alpha = a as f32 / 255.0;
+        Luma([(l * alpha + 255.0 * (1.0 - alpha)).round().clamp(0.0, 255.0) as u8])
+    })
+}
+
+/// Stretch gray levels so the darkest and brightest 0.5% of pixels map to 0 and 255
+fn stretch_contrast(gray: &mut GrayImage) {
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+
+    let clip = gray.pixels().len() as u64 / 200;
+    let first_unclipped = |bins: Vec<usize>| {
+        let mut count = 0;
+        bins.into_iter().find(|&i| {
+            count += histogram[i];
+            count > clip
+        }).unwrap_or(0) as f32
+    };
+    let low = first_unclipped((0..256).collect());
+    let high = first_unclipped((0..256).rev().collect());
+    if high - low < 1.0 {
+        return;
+    }
+
+    let scale = 255.0 / (high - low);
+    for pixel in gray.pixels_mut() {
+        pixel.0[0] = ((pixel.0[0] as f32 - low) * scale).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Prepare a scan or photo of text for OCR in one decode: grayscale (transparency
+/// becomes white), auto-contrast, a mild unsharp mask and, with `binarize`, adaptive
+/// thresholding so uneven lighting does not swallow text. Always returns PNG: 8-bit
+/// grayscale, or 1-bit black and white when binarized.
+#[wasm_bindgen]
+pub fn preprocess_for_ocr(data: &[u8], binarize: bool) -> Result<Vec<u8>, String> {
+    let decoded = decode_image(data)?;
+    let mut gray = to_gray_on_white(&decoded.img);
+    stretch_contrast(&mut gray);
+    let gray = unsharpen(&gray, 1.0, 3);
+
+    if binarize {
+        // About the height of a line of text at typical scan resolutions
+        let window = (gray.width().min(gray.height()) / 40).max(15);
+        return encode_png_1bit(&adaptive_binarize(&gray, window, 10));
+    }
+
+    let mut output = Vec::new();
+    DynamicImage::ImageLuma8(gray).write_to(&mut Cursor::new(&mut output), ImageFormat::Png)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+
+    #[test]
+    fn apply_kernel_accepts_only_3x3_and_5x5() {
+        let data = encode_png_fixture(RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255])));
+        assert!(apply_kernel(&data, vec![1.0; 9], 9.0, 0.0).is_ok());
+        assert!(apply_kernel(&data, vec![1.0; 25], 25.0, 0.0).is_ok());
+        assert!(apply_kernel(&data, vec![1.0], 1.0, 0.0).is_err());
+        assert!(apply_kernel(&data, vec![1.0; 49], 49.0, 0.0).is_err());
+        assert!(apply_kernel(&data, vec![1.0; 9], 9.0, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn emboss_turns_flat_areas_neutral_gray_and_keeps_alpha() {
+        let data = encode_png_fixture(RgbaImage::from_pixel(8, 8, Rgba([200, 40, 90, 180])));
+        let embossed = image::load_from_memory(&emboss_image(&data, 1.5).unwrap()).unwrap().to_rgba8();
+
+        assert!(embossed.pixels().all(|p| p.0 == [128, 128, 128, 180]));
+    }
+
+    #[test]
+    fn separable_blur_matches_direct_2d_gaussian() {
+        let img = GrayImage::from_fn(23, 17, |x, y| Luma([((x * 37 + y * 91 + x * y * 13) % 256) as u8]));
+        let sigma = 1.7;
+        let radius = (sigma * 3.0f32).ceil() as i64;
+        let blurred = gaussian_separable(&img, sigma);
+
+        // Full 2D kernel with the same 3 sigma extent and clamped edges
+        for (x, y, pixel) in blurred.enumerate_pixels() {
+            let (mut sum, mut total) = (0.0f64, 0.0f64);
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let w = (-((dx * dx + dy * dy) as f64) / (2.0 * sigma as f64 * sigma as f64)).exp();
+                    let sx = (x as i64 + dx).clamp(0, img.width() as i64 - 1) as u32;
+                    let sy = (y as i64 + dy).clamp(0, img.height() as i64 - 1) as u32;
+                    sum += w * img.get_pixel(sx, sy).0[0] as f64;
+                    total += w;
+                }
+            }
+            let expected = (sum / total).round() as i32;
+            assert!((pixel.0[0] as i32 - expected).abs() <= 1, "({}, {}): {} vs {}", x, y, pixel.0[0], expected);
+        }
+    }
+
+    #[test]
+    fn blur_sigma_is_capped() {
+        let data = encode_png_fixture(RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255])));
+        assert!(blur_image(&data, MAX_BLUR_SIGMA).is_ok());
+        assert!(blur_image(&data, MAX_BLUR_SIGMA + 1.0).is_err());
+        assert!(blur_image(&data, 0.0).is_err());
+        assert!(blur_image(&data, f32::INFINITY).is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use wasm_bindgen::prelude::*;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::io::Cursor;
+
+use crate::common::{check_dimensions, parse_color};
+use crate::encode::parse_format;
+
+fn encode_generated(img: RgbaImage, format: &str) -> Result<Vec<u8>, String> {
+    let format = parse_format(format)?;
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(img).write_to(&mut Cursor::new(&mut output), format)
+        .map_err(|e| format!("Failed to encode generated image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Create an image filled with a single color
+#[wasm_bindgen]
+pub fn generate_solid(width: u32, height: u32, color: &str, format: &str) -> Result<Vec<u8>, String> {
+    check_dimensions(width, height)?;
+    let fill = parse_color(color)?;
+
+    encode_generated(RgbaImage::from_pixel(width, height, fill), format)
+}
+
+/// Create a linear gradient from `from` to `to`. Direction is "horizontal"
+/// (left to right), "vertical" (top to bottom) or "diagonal" (top-left to bottom-right).
+#[wasm_bindgen]
+pub fn generate_gradient(
+    width: u32,
+    height: u32,
+    from: &str,
+    to: &str,
+    direction: &str,
+    format: &str
+) -> Result<Vec<u8>, String> {
+    check_dimensions(width, height)?;
+    let start = parse_color(from)?;
+    let end = parse_color(to)?;
+
+    let span_x = (width - 1).max(1) as f32;
+    let span_y = (height - 1).max(1) as f32;
+    let position: Box<dyn Fn(u32, u32) -> f32> = match direction {
+        "horizontal" => Box::new(|x, _| x as f32 / span_x),
+        "vertical" => Box::new(|_, y| y as f32 / span_y),
+        "diagonal" => Box::new(|x, y| (x as f32 / span_x + y as f32 / span_y) / 2.0),
+        _ => return Err(format!("Invalid gradient direction: {}", direction)),
+    };
+
+    let img = RgbaImage::from_fn(width, height, |x, y| {
+        let t = position(x, y);
+        let mut pixel = [0u8; 4];
+        for (c, value) in pixel.iter_mut().enumerate() {
+            let (a, b) = (start.0[c] as f32, end.0[c] as f32);
+            *value = (a + (b - a) * t).round() as u8;
+        }
+        Rgba(pixel)
+    });
+
+    encode_generated(img, format)
+}
@@ -0,0 +1,115 @@
+use wasm_bindgen::prelude::*;
+use image::{DynamicImage, ImageFormat};
+
+use crate::adjustments::{Adjustments, BrightnessMode, SaturationModel};
+use crate::common::{check_dimensions, check_region, decode_image};
+use crate::encode::{PngColor, encode_png, encode_to, parse_format};
+use crate::transforms::{resize_with_filter, rotate_dynamic};
+
+/// A decoded image kept alive on the WASM side, so a chain of operations only
+/// decodes once and encodes once:
+///
+/// ```js
+/// const handle = new ImageHandle(bytes);
+/// handle.crop(0, 0, 800, 600);
+/// handle.resize(400, 300, "lanczos3");
+/// const out = handle.encode("webp", 0);
+/// handle.free();
+/// ```
+///
+/// Call `free()` when done, the pixel buffer is not garbage collected by JS.
+#[wasm_bindgen]
+pub struct ImageHandle {
+    img: DynamicImage,
+    format: ImageFormat,
+}
+
+#[wasm_bindgen]
+impl ImageHandle {
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: &[u8]) -> Result<ImageHandle, String> {
+        let decoded = decode_image(data)?;
+        Ok(ImageHandle { img: decoded.img, format: decoded.format })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.img.width()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.img.height()
+    }
+
+    /// Format the image was decoded from, used by `encode` when no format is given
+    #[wasm_bindgen(getter)]
+    pub fn format(&self) -> String {
+        format!("{:?}", self.format)
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32, filter: &str) -> Result<(), String> {
+        check_dimensions(width, height)?;
+        self.img = resize_with_filter(&self.img, width, height, filter);
+        Ok(())
+    }
+
+    pub fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) -> Result<(), String> {
+        check_region(x, y, width, height, self.img.width(), self.img.height())?;
+        self.img = self.img.crop_imm(x, y, width, height);
+        Ok(())
+    }
+
+    pub fn rotate(&mut self, degrees: f32, bg: &str, interpolation: &str) -> Result<(), String> {
+        self.img = rotate_dynamic(&self.img, degrees, bg, interpolation, self.format)?;
+        Ok(())
+    }
+
+    /// Same parameters and ranges as `adjust_image`
+    #[allow(clippy::too_many_arguments)]
+    pub fn adjust(
+        &mut self,
+        brightness: i32,
+        contrast: f32,
+        saturation: f32,
+        hue: i32,
+        exposure: f32,
+        gamma: f32,
+        shadows: f32,
+        highlights: f32,
+        vibrance: f32,
+        saturation_model: &str,
+        brightness_mode: &str,
+    ) -> Result<(), String> {
+        let saturation_model = SaturationModel::parse(saturation_model)?;
+        let brightness_mode = BrightnessMode::parse(brightness_mode)?;
+        let adjustments = Adjustments {
+            brightness,
+            brightness_mode,
+            contrast,
+            saturation,
+            saturation_model,
+            hue,
+            exposure,
+            gamma,
+            shadows,
+            highlights,
+            vibrance,
+        };
+        adjustments.validate()?;
+        self.img = DynamicImage::ImageRgba8(adjustments.apply(self.img.to_rgba8()));
+        Ok(())
+    }
+
+    /// Encode the current image. An empty `format` keeps the source format;
+    /// `quality` (1-100) is used for JPEG output.
+    pub fn encode(&self, format: &str, quality: u8) -> Result<Vec<u8>, String> {
+        let format = if format.is_empty() { self.format } else { parse_format(format)? };
+        encode_to(&self.img, format, quality)
+    }
+
+    /// Encode the current image as PNG with the given color type (see `convert_to_png`)
+    pub fn encode_png(&self, png_color: &str) -> Result<Vec<u8>, String> {
+        encode_png(&self.img, PngColor::parse(png_color)?)
+    }
+}
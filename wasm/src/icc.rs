@@ -0,0 +1,317 @@
+use wasm_bindgen::prelude::*;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use std::io::Cursor;
+
+use crate::common::{decode_image, linear_to_srgb, srgb_to_linear};
+
+/// Linear sRGB to the D50 profile connection space (Bradford adapted, as in sRGB ICC profiles)
+const SRGB_TO_PCS: [[f32; 3]; 3] = [
+    [0.4360747, 0.3850649, 0.1430804],
+    [0.2225045, 0.7168786, 0.0606169],
+    [0.0139322, 0.0971045, 0.7141733],
+];
+
+/// D50 profile connection space to linear sRGB (inverse of `SRGB_TO_PCS`)
+const PCS_TO_SRGB: [[f32; 3]; 3] = [
+    [3.133856, -1.616867, -0.490615],
+    [-0.978768, 1.916142, 0.033454],
+    [0.071945, -0.228991, 1.405243],
+];
+
+/// A tone reproduction curve mapping encoded values (0..1) to linear light
+enum Curve {
+    Gamma(f32),
+    Table(Vec<f32>),
+    /// ICC parametric curve: function type and its parameters [g, a, b, c, d, e, f]
+    Parametric(u16, [f32; 7]),
+}
+
+impl Curve {
+    fn eval(&self, x: f32) -> f32 {
+        match self {
+            Curve::Gamma(g) => x.powf(*g),
+            Curve::Table(table) => {
+                let pos = x * (table.len() - 1) as f32;
+                let i = (pos as usize).min(table.len() - 2);
+                table[i] + (table[i + 1] - table[i]) * (pos - i as f32)
+            }
+            Curve::Parametric(kind, [g, a, b, c, d, e, f]) => match kind {
+                0 => x.powf(*g),
+                1 if x >= -b / a => (a * x + b).powf(*g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(*g) + c,
+                2 => *c,
+                3 if x >= *d => (a * x + b).powf(*g),
+                3 => c * x,
+                _ if x >= *d => (a * x + b).powf(*g) + e,
+                _ => c * x + f,
+            },
+        }
+    }
+}
+
+/// The parts of a matrix/TRC profile needed to convert to sRGB
+struct Profile {
+    /// Columns are the red, green and blue colorants in the D50 connection space
+    to_pcs: [[f32; 3]; 3],
+    curves: [Curve; 3],
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn s15_fixed16(data: &[u8], at: usize) -> Option<f32> {
+    Some(be_u32(data, at)? as i32 as f32 / 65536.0)
+}
+
+/// Body of the tag with the given signature
+fn find_tag<'a>(icc: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+    let count = be_u32(icc, 128)? as usize;
+    (0..count.min(1000)).find_map(|i| {
+        let entry = 132 + i * 12;
+        if icc.get(entry..entry + 4)? != signature {
+            return None;
+        }
+        let offset = be_u32(icc, entry + 4)? as usize;
+        let size = be_u32(icc, entry + 8)? as usize;
+        icc.get(offset..offset.checked_add(size)?)
+    })
+}
+
+fn read_xyz(icc: &[u8], signature: &[u8; 4]) -> Option<[f32; 3]> {
+    let tag = find_tag(icc, signature)?;
+    if !tag.starts_with(b"XYZ ") {
+        return None;
+    }
+    Some([s15_fixed16(tag, 8)?, s15_fixed16(tag, 12)?, s15_fixed16(tag, 16)?])
+}
+
+fn read_curve(icc: &[u8], signature: &[u8; 4]) -> Option<Curve> {
+    let tag = find_tag(icc, signature)?;
+    match tag.get(..4)? {
+        b"curv" => match be_u32(tag, 8)? {
+            0 => Some(Curve::Gamma(1.0)),
+            1 => Some(Curve::Gamma(be_u16(tag, 12)? as f32 / 256.0)),
+            n => (0..n as usize)
+                .map(|i| be_u16(tag, 12 + i * 2).map(|v| v as f32 / 65535.0))
+                .collect::<Option<Vec<_>>>()
+                .map(Curve::Table),
+        },
+        b"para" => {
+            let kind = be_u16(tag, 8)?;
+            let count = match kind {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return None,
+            };
+            let mut params = [0.0; 7];
+            for (i, param) in params.iter_mut().enumerate().take(count) {
+                *param = s15_fixed16(tag, 12 + i * 4)?;
+            }
+            Some(Curve::Parametric(kind, params))
+        }
+        _ => None,
+    }
+}
+
+/// Parse an RGB matrix/TRC or gray TRC profile. Gray profiles map to the sRGB white
+/// point, so they are treated as three identical channels.
+fn parse_profile(icc: &[u8]) -> Result<Profile, String> {
+    match icc.get(16..20) {
+        Some(b"RGB ") => {
+            let colorants = [b"rXYZ", b"gXYZ", b"bXYZ"].map(|sig| read_xyz(icc, sig));
+            let curves = [b"rTRC", b"gTRC", b"bTRC"].map(|sig| read_curve(icc, sig));
+            let ([Some(r), Some(g), Some(b)], [Some(rc), Some(gc), Some(bc)]) = (colorants, curves) else {
+                return Err("Unsupported ICC profile: only matrix/TRC RGB profiles can be converted".to_string());
+            };
+            let to_pcs = std::array::from_fn(|row| [r[row], g[row], b[row]]);
+            Ok(Profile { to_pcs, curves: [rc, gc, bc] })
+        }
+        Some(b"GRAY") => {
+            let curve = || read_curve(icc, b"kTRC").ok_or("Unsupported ICC profile: gray profile without a curve");
+            Ok(Profile { to_pcs: SRGB_TO_PCS, curves: [curve()?, curve()?, curve()?] })
+        }
+        Some(space) => Err(format!(
+            "Unsupported ICC profile: {} color space",
+            String::from_utf8_lossy(space).trim()
+        )),
+        None => Err("Invalid ICC profile: header is truncated".to_string()),
+    }
+}
+
+impl Profile {
+    /// True when the profile describes sRGB closely enough that converting is a no-op
+    fn is_srgb(&self) -> bool {
+        let matrix_matches = self.to_pcs.iter().flatten().zip(SRGB_TO_PCS.iter().flatten())
+            .all(|(a, b)| (a - b).abs() < 0.005);
+        let curves_match = self.curves.iter().all(|curve| {
+            (1..16).all(|i| {
+                let x = i as f32 / 16.0;
+                (curve.eval(x) - srgb_to_linear(x)).abs() < 0.005
+            })
+        });
+        matrix_matches && curves_match
+    }
+}
+
+/// Embedded ICC profile of an image, if the format and decoder expose one
+pub(crate) fn read_icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    let reader = ImageReader::new(Cursor::new(data)).with_guessed_format().ok()?;
+    let mut decoder = reader.into_decoder().ok()?;
+    decoder.icc_profile().ok().flatten()
+}
+
+/// Convert colors from the embedded ICC profile (e.g. Adobe RGB or Display P3) to
+/// sRGB so wide-gamut images do not look oversaturated in browsers. Matrix/TRC RGB
+/// and gray profiles are supported; colors outside sRGB are clipped. The output
+/// carries no profile since it is now sRGB. Untagged and sRGB images are returned
+/// unchanged.
+#[wasm_bindgen]
+pub fn convert_to_srgb(data: &[u8]) -> Result<Vec<u8>, String> {
+    let Some(icc) = read_icc_profile(data) else {
+        return Ok(data.to_vec());
+    };
+    let profile = parse_profile(&icc)?;
+    if profile.is_srgb() {
+        return Ok(data.to_vec());
+    }
+
+    let decoded = decode_image(data)?;
+    let mut rgba = decoded.img.to_rgba8();
+
+    let linear: [Vec<f32>; 3] = std::array::from_fn(|c| {
+        (0..256).map(|v| profile.curves[c].eval(v as f32 / 255.0).clamp(0.0, 1.0)).collect()
+    });
+    let matrix: [[f32; 3]; 3] = std::array::from_fn(|row| {
+        std::array::from_fn(|col| (0..3).map(|k| PCS_TO_SRGB[row][k] * profile.to_pcs[k][col]).sum())
+    });
+    // Encoding through a table keeps powf out of the per-pixel loop
+    let encode: Vec<u8> = (0..=4096)
+        .map(|i| (linear_to_srgb(i as f32 / 4096.0) * 255.0).round() as u8)
+        .collect();
+
+    for pixel in rgba.pixels_mut() {
+        let rgb: [f32; 3] = std::array::from_fn(|c| linear[c][pixel.0[c] as usize]);
+        for (channel, row) in pixel.0.iter_mut().zip(&matrix) {
+            let v = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+            *channel = encode[(v.clamp(0.0, 1.0) * 4096.0).round() as usize];
+        }
+    }
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::png::PngEncoder;
+    use image::{ImageEncoder, Rgb, RgbImage};
+
+    /// Display P3 colorants adapted to D50, as in Apple's profile
+    const P3_TO_PCS: [[f32; 3]; 3] = [
+        [0.515102, 0.291965, 0.157153],
+        [0.241182, 0.692236, 0.066582],
+        [-0.001050, 0.041882, 0.784378],
+    ];
+
+    /// Linear Display P3 to linear sRGB
+    const P3_TO_SRGB: [[f32; 3]; 3] = [
+        [1.22494, -0.22494, 0.0],
+        [-0.042057, 1.042057, 0.0],
+        [-0.019638, -0.078636, 1.098274],
+    ];
+
+    /// Matrix/TRC RGB profile with the sRGB parametric curve on every channel.
+    /// `tag_size` overrides the declared size of the curve tags.
+    fn rgb_profile(to_pcs: [[f32; 3]; 3], tag_size: Option<u32>) -> Vec<u8> {
+        let fixed = |v: f32| ((v * 65536.0).round() as i32).to_be_bytes();
+        let mut tags: Vec<(&[u8; 4], Vec<u8>)> = Vec::new();
+        for (col, sig) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+            let mut xyz = b"XYZ \0\0\0\0".to_vec();
+            (0..3).for_each(|row| xyz.extend_from_slice(&fixed(to_pcs[row][col])));
+            tags.push((sig, xyz));
+        }
+        let mut para = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for v in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+            para.extend_from_slice(&fixed(v));
+        }
+        for sig in [b"rTRC", b"gTRC", b"bTRC"] {
+            tags.push((sig, para.clone()));
+        }
+
+        let mut icc = vec![0u8; 128];
+        icc[16..20].copy_from_slice(b"RGB ");
+        icc[20..24].copy_from_slice(b"XYZ ");
+        icc.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        let mut offset = 132 + 12 * tags.len();
+        let mut bodies = Vec::new();
+        for (sig, body) in &tags {
+            let size = if sig.ends_with(b"TRC") { tag_size.unwrap_or(body.len() as u32) } else { body.len() as u32 };
+            icc.extend_from_slice(*sig);
+            icc.extend_from_slice(&(offset as u32).to_be_bytes());
+            icc.extend_from_slice(&size.to_be_bytes());
+            bodies.extend_from_slice(body);
+            offset += body.len();
+        }
+        icc.extend(bodies);
+        let len = icc.len() as u32;
+        icc[..4].copy_from_slice(&len.to_be_bytes());
+        icc
+    }
+
+    fn tagged_png(img: &RgbImage, icc: Vec<u8>) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut encoder = PngEncoder::new(&mut output);
+        encoder.set_icc_profile(icc).unwrap();
+        encoder.write_image(img.as_raw(), img.width(), img.height(), image::ExtendedColorType::Rgb8).unwrap();
+        output
+    }
+
+    #[test]
+    fn srgb_profile_is_a_no_op() {
+        let data = tagged_png(&RgbImage::from_pixel(4, 4, Rgb([200, 100, 50])), rgb_profile(SRGB_TO_PCS, None));
+        assert!(parse_profile(&read_icc_profile(&data).unwrap()).unwrap().is_srgb());
+        assert_eq!(convert_to_srgb(&data).unwrap(), data);
+    }
+
+    #[test]
+    fn display_p3_converts_to_srgb() {
+        let p3 = [200u8, 100, 50];
+        let data = tagged_png(&RgbImage::from_pixel(4, 4, Rgb(p3)), rgb_profile(P3_TO_PCS, None));
+        let converted = image::load_from_memory(&convert_to_srgb(&data).unwrap()).unwrap().to_rgb8();
+
+        let linear = p3.map(|v| srgb_to_linear(v as f32 / 255.0));
+        let expected: [u8; 3] = std::array::from_fn(|row| {
+            let v: f32 = (0..3).map(|k| P3_TO_SRGB[row][k] * linear[k]).sum();
+            (linear_to_srgb(v.clamp(0.0, 1.0)) * 255.0).round() as u8
+        });
+        let actual = converted.get_pixel(1, 1).0;
+        assert!(actual.iter().zip(expected).all(|(a, b)| a.abs_diff(b) <= 2), "{:?} vs {:?}", actual, expected);
+        assert!(actual[0] > p3[0] && actual[1] < p3[1], "P3 colors should spread out in sRGB: {:?}", actual);
+    }
+
+    #[test]
+    fn truncated_profiles_are_rejected() {
+        let img = RgbImage::from_pixel(4, 4, Rgb([200, 100, 50]));
+        for size in [0, 2, 3] {
+            let data = tagged_png(&img, rgb_profile(P3_TO_PCS, Some(size)));
+            assert!(convert_to_srgb(&data).unwrap_err().contains("Unsupported ICC profile"), "tag size {}", size);
+        }
+
+        let mut header_only = rgb_profile(P3_TO_PCS, None);
+        header_only.truncate(18);
+        assert!(convert_to_srgb(&tagged_png(&img, header_only)).is_err());
+    }
+}
@@ -0,0 +1,1617 @@
+use wasm_bindgen::prelude::*;
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+use std::io::Cursor;
+
+use crate::common::{check_dimensions, check_region, decode_image};
+use crate::encode::encode_jpeg;
+use crate::transforms::{CropResult, rotate};
+
+/// Natural (row-major) index of each coefficient in zigzag order
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10,
+    17, 24, 32, 25, 18, 11, 4, 5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// A marker segment before the first scan, as byte offsets into the file
+pub(crate) struct Segment {
+    pub marker: u8,
+    /// Offset of the 0xFF marker byte
+    pub start: usize,
+    /// Offset of the payload (after the length field)
+    pub payload: usize,
+    /// Offset just past the segment
+    pub end: usize,
+}
+
+/// Walk the marker segments of a JPEG up to (not including) the first SOS
+pub(crate) fn jpeg_segments(data: &[u8]) -> Result<Vec<Segment>, String> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err("Not a JPEG file".to_string());
+    }
+
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        let start = pos;
+        while pos < data.len() && data[pos] == 0xFF {
+            pos += 1;
+        }
+        let Some(&marker) = data.get(pos) else {
+            return Err("Unexpected end of JPEG data".to_string());
+        };
+        pos += 1;
+
+        if marker == 0xDA || marker == 0xD9 {
+            return Ok(segments);
+        }
+
+        let length = match data.get(pos..pos + 2) {
+            Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]) as usize,
+            None => return Err("Unexpected end of JPEG data".to_string()),
+        };
+        if length < 2 || pos + length > data.len() {
+            return Err("Truncated JPEG segment".to_string());
+        }
+        segments.push(Segment { marker, start, payload: pos + 2, end: pos + length });
+        pos += length;
+    }
+}
+
+/// Quantization tables of a DQT segment payload as (id, values in natural order, precision)
+fn parse_dqt(payload: &[u8]) -> Result<Vec<(usize, [u16; 64], u8)>, String> {
+    let mut tables = Vec::new();
+    let mut p = 0;
+    while p < payload.len() {
+        let precision = payload[p] >> 4;
+        let id = (payload[p] & 0x0F) as usize;
+        let entry_size = if precision == 0 { 1 } else { 2 };
+        let entries = payload.get(p + 1..p + 1 + 64 * entry_size)
+            .filter(|_| id < 4)
+            .ok_or("Invalid quantization table")?;
+        let mut table = [0u16; 64];
+        for (k, &natural) in ZIGZAG.iter().enumerate() {
+            table[natural] = if precision == 0 {
+                entries[k] as u16
+            } else {
+                u16::from_be_bytes([entries[2 * k], entries[2 * k + 1]])
+            };
+        }
+        tables.push((id, table, precision));
+        p += 1 + 64 * entry_size;
+    }
+    Ok(tables)
+}
+
+// ---------------------------------------------------------------------------
+// DCT coefficient codec (baseline / extended sequential Huffman, 8-bit)
+// ---------------------------------------------------------------------------
+
+struct HuffmanDecoder {
+    maxcode: [i32; 17],
+    valptr: [i32; 17],
+    mincode: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanDecoder {
+    fn new(bits: &[u8; 16], values: Vec<u8>) -> Self {
+        let mut maxcode = [-1; 17];
+        let mut valptr = [0; 17];
+        let mut mincode = [0; 17];
+        let mut code = 0i32;
+        let mut k = 0i32;
+        for len in 1..=16 {
+            let count = bits[len - 1] as i32;
+            if count > 0 {
+                valptr[len] = k;
+                mincode[len] = code;
+                code += count;
+                k += count;
+                maxcode[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        HuffmanDecoder { maxcode, valptr, mincode, values }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u32,
+    nbits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn fill(&mut self) {
+        while self.nbits <= 24 {
+            let mut byte = 0;
+            if self.pos < self.data.len() {
+                byte = self.data[self.pos];
+                if byte == 0xFF {
+                    match self.data.get(self.pos + 1) {
+                        Some(0x00) => self.pos += 2,
+                        // A marker: leave it in place and feed zeros
+                        _ => byte = 0,
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+            self.acc |= (byte as u32) << (24 - self.nbits);
+            self.nbits += 8;
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> i32 {
+        if n == 0 {
+            return 0;
+        }
+        self.fill();
+        let value = self.acc >> (32 - n);
+        self.acc <<= n;
+        self.nbits -= n;
+        value as i32
+    }
+
+    fn decode(&mut self, table: &HuffmanDecoder) -> Result<u8, String> {
+        let mut code = 0i32;
+        for len in 1..=16 {
+            code = (code << 1) | self.bits(1);
+            if code <= table.maxcode[len] {
+                let index = table.valptr[len] + code - table.mincode[len];
+                return table.values.get(index as usize).copied().ok_or_else(corrupt);
+            }
+        }
+        Err(corrupt())
+    }
+
+    /// Skip to the next restart marker and reset the bit buffer
+    fn restart(&mut self) -> Result<(), String> {
+        self.acc = 0;
+        self.nbits = 0;
+        while self.pos + 1 < self.data.len() {
+            if self.data[self.pos] == 0xFF && (0xD0..=0xD7).contains(&self.data[self.pos + 1]) {
+                self.pos += 2;
+                return Ok(());
+            }
+            self.pos += 1;
+        }
+        Err(corrupt())
+    }
+}
+
+fn corrupt() -> String {
+    "Corrupt JPEG entropy-coded data".to_string()
+}
+
+/// Sign-extend a `size`-bit magnitude category value (JPEG F.2.2.1)
+fn extend(value: i32, size: u32) -> i32 {
+    if size > 0 && value < (1 << (size - 1)) {
+        value - (1 << size) + 1
+    } else {
+        value
+    }
+}
+
+struct CodedComponent {
+    id: u8,
+    h: u8,
+    v: u8,
+    tq: u8,
+    blocks_w: usize,
+    blocks_h: usize,
+    /// Coefficients per 8x8 block in natural order, blocks in raster order
+    blocks: Vec<[i16; 64]>,
+}
+
+/// Quantized DCT coefficients of a JPEG, enough to re-encode it without
+/// decompressing to pixels
+pub(crate) struct JpegCoefficients {
+    sof_marker: u8,
+    width: u16,
+    height: u16,
+    components: Vec<CodedComponent>,
+    /// Quantization tables in natural order, with their precision (0 = 8-bit, 1 = 16-bit)
+    qtables: [Option<([u16; 64], u8)>; 4],
+    /// APPn and COM segments, re-emitted unchanged
+    passthrough: Vec<(u8, Vec<u8>)>,
+}
+
+/// Clockwise rotation in quarter turns
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum QuarterTurn {
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+/// Receives the blocks of the scan as they are decoded, so callers that only need
+/// pixels do not have to keep every coefficient in memory
+trait BlockSink {
+    /// Called before the scan with the laid-out components and quantization tables
+    fn begin(&mut self, components: &[CodedComponent], qtables: &[Option<([u16; 64], u8)>; 4]) -> Result<(), String>;
+    fn block(&mut self, component: usize, bx: usize, by: usize, coefficients: &[i16; 64]);
+}
+
+/// Collects every block, for transforms in the DCT domain
+#[derive(Default)]
+struct BlockStore {
+    blocks: Vec<Vec<[i16; 64]>>,
+    widths: Vec<usize>,
+}
+
+impl BlockSink for BlockStore {
+    fn begin(&mut self, components: &[CodedComponent], _qtables: &[Option<([u16; 64], u8)>; 4]) -> Result<(), String> {
+        self.blocks = components.iter().map(|c| vec![[0i16; 64]; c.blocks_w * c.blocks_h]).collect();
+        self.widths = components.iter().map(|c| c.blocks_w).collect();
+        Ok(())
+    }
+
+    fn block(&mut self, component: usize, bx: usize, by: usize, coefficients: &[i16; 64]) {
+        self.blocks[component][by * self.widths[component] + bx] = *coefficients;
+    }
+}
+
+/// Block grid of each component: single-component scans cover the image exactly,
+/// interleaved scans are padded to whole MCUs
+fn layout_components(width: u16, height: u16, components: &mut [CodedComponent]) {
+    if components.len() == 1 {
+        components[0].blocks_w = (width as usize).div_ceil(8);
+        components[0].blocks_h = (height as usize).div_ceil(8);
+        return;
+    }
+    let hmax = components.iter().map(|c| c.h as usize).max().unwrap_or(1);
+    let vmax = components.iter().map(|c| c.v as usize).max().unwrap_or(1);
+    let mcus_x = (width as usize).div_ceil(8 * hmax);
+    let mcus_y = (height as usize).div_ceil(8 * vmax);
+    for c in components.iter_mut() {
+        c.blocks_w = mcus_x * c.h as usize;
+        c.blocks_h = mcus_y * c.v as usize;
+    }
+}
+
+impl JpegCoefficients {
+    pub fn parse(data: &[u8]) -> Result<JpegCoefficients, String> {
+        let mut store = BlockStore::default();
+        let mut coefficients = Self::read(data, &mut store)?;
+        for (component, blocks) in coefficients.components.iter_mut().zip(store.blocks) {
+            component.blocks = blocks;
+        }
+        Ok(coefficients)
+    }
+
+    /// Parse the headers and decode the scan, handing each block to `sink`. The
+    /// returned components are laid out but hold no blocks.
+    fn read(data: &[u8], sink: &mut dyn BlockSink) -> Result<JpegCoefficients, String> {
+        let mut sof: Option<(u8, u16, u16, Vec<CodedComponent>)> = None;
+        let mut qtables: [Option<([u16; 64], u8)>; 4] = [None, None, None, None];
+        let mut dc_tables: [Option<HuffmanDecoder>; 4] = [None, None, None, None];
+        let mut ac_tables: [Option<HuffmanDecoder>; 4] = [None, None, None, None];
+        let mut passthrough = Vec::new();
+        let mut restart_interval = 0usize;
+        let mut scanned = false;
+
+        if !data.starts_with(&[0xFF, 0xD8]) {
+            return Err("Not a JPEG file".to_string());
+        }
+
+        let mut pos = 2;
+        loop {
+            while pos < data.len() && data[pos] == 0xFF {
+                pos += 1;
+            }
+            let Some(&marker) = data.get(pos) else {
+                return Err("Unexpected end of JPEG data".to_string());
+            };
+            pos += 1;
+
+            if marker == 0xD9 {
+                break;
+            }
+
+            let length = match data.get(pos..pos + 2) {
+                Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]) as usize,
+                None => return Err("Unexpected end of JPEG data".to_string()),
+            };
+            let payload = data.get(pos + 2..pos + length)
+                .filter(|_| length >= 2)
+                .ok_or("Truncated JPEG segment")?;
+            pos += length;
+
+            match marker {
+                0xE0..=0xEF | 0xFE => passthrough.push((marker, payload.to_vec())),
+                0xDB => {
+                    for (id, table, precision) in parse_dqt(payload)? {
+                        qtables[id] = Some((table, precision));
+                    }
+                }
+                0xC4 => {
+                    let mut p = 0;
+                    while p < payload.len() {
+                        let class = payload[p] >> 4;
+                        let id = (payload[p] & 0x0F) as usize;
+                        let bits: [u8; 16] = payload.get(p + 1..p + 17)
+                            .and_then(|b| b.try_into().ok())
+                            .ok_or("Invalid Huffman table")?;
+                        let count: usize = bits.iter().map(|&b| b as usize).sum();
+                        let values = payload.get(p + 17..p + 17 + count)
+                            .filter(|_| id < 4)
+                            .ok_or("Invalid Huffman table")?
+                            .to_vec();
+                        let decoder = HuffmanDecoder::new(&bits, values);
+                        if class == 0 {
+                            dc_tables[id] = Some(decoder);
+                        } else {
+                            ac_tables[id] = Some(decoder);
+                        }
+                        p += 17 + count;
+                    }
+                }
+                0xC0 | 0xC1 => {
+                    if payload.len() < 6 || payload[0] != 8 {
+                        return Err("Only 8-bit JPEGs are supported".to_string());
+                    }
+                    let height = u16::from_be_bytes([payload[1], payload[2]]);
+                    let width = u16::from_be_bytes([payload[3], payload[4]]);
+                    let count = payload[5] as usize;
+                    if width == 0 || height == 0 || count == 0 || payload.len() < 6 + 3 * count {
+                        return Err("Invalid JPEG frame header".to_string());
+                    }
+                    if count > 4 {
+                        return Err(format!("JPEGs with {} components are not supported", count));
+                    }
+                    // Blocks are allocated from the header alone, before any pixel is decoded
+                    check_dimensions(width as u32, height as u32)?;
+                    let components = (0..count).map(|i| {
+                        let c = &payload[6 + 3 * i..9 + 3 * i];
+                        CodedComponent {
+                            id: c[0],
+                            h: (c[1] >> 4).max(1),
+                            v: (c[1] & 0x0F).max(1),
+                            tq: c[2] & 0x03,
+                            blocks_w: 0,
+                            blocks_h: 0,
+                            blocks: Vec::new(),
+                        }
+                    }).collect();
+                    sof = Some((marker, width, height, components));
+                }
+                0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                    return Err("Only baseline and extended sequential JPEGs are supported".to_string());
+                }
+                0xDD => {
+                    restart_interval = payload.get(0..2)
+                        .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+                        .ok_or("Invalid restart interval")?;
+                }
+                0xDA => {
+                    if scanned {
+                        return Err("Multi-scan JPEGs are not supported".to_string());
+                    }
+                    let (_, width, height, components) = sof.as_mut().ok_or("Scan before frame header")?;
+                    let ns = *payload.first().ok_or("Invalid scan header")? as usize;
+                    if ns != components.len() && components.len() != 1 {
+                        return Err("Non-interleaved multi-component scans are not supported".to_string());
+                    }
+
+                    let mut selectors = Vec::with_capacity(ns);
+                    for i in 0..ns {
+                        let s = payload.get(1 + 2 * i..3 + 2 * i).ok_or("Invalid scan header")?;
+                        let index = components.iter().position(|c| c.id == s[0])
+                            .ok_or("Scan references an unknown component")?;
+                        selectors.push((index, (s[1] >> 4) as usize, (s[1] & 0x0F) as usize));
+                    }
+
+                    for c in components.iter() {
+                        if qtables[c.tq as usize].is_none() {
+                            return Err("Missing quantization table".to_string());
+                        }
+                    }
+                    layout_components(*width, *height, components);
+                    sink.begin(components, &qtables)?;
+
+                    let reader = BitReader { data, pos, acc: 0, nbits: 0 };
+                    pos = decode_scan(reader, components, &selectors, &dc_tables, &ac_tables, restart_interval, sink)?;
+                    scanned = true;
+                }
+                _ => {}
+            }
+        }
+
+        let (sof_marker, width, height, components) = sof.ok_or("Missing JPEG frame header")?;
+        if !scanned {
+            return Err("JPEG contains no image data".to_string());
+        }
+
+        Ok(JpegCoefficients { sof_marker, width, height, components, qtables, passthrough })
+    }
+
+    fn max_sampling(&self) -> (usize, usize) {
+        let h = self.components.iter().map(|c| c.h as usize).max().unwrap_or(1);
+        let v = self.components.iter().map(|c| c.v as usize).max().unwrap_or(1);
+        (h, v)
+    }
+
+    /// Width and height of an MCU in pixels
+    pub fn mcu_size(&self) -> (u32, u32) {
+        if self.components.len() == 1 {
+            return (8, 8);
+        }
+        let (h, v) = self.max_sampling();
+        (8 * h as u32, 8 * v as u32)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    /// Whether both dimensions are whole multiples of the MCU size, i.e. there are
+    /// no partially filled edge blocks that a geometric transform would misplace
+    pub fn is_mcu_aligned(&self) -> bool {
+        let (mcu_w, mcu_h) = self.mcu_size();
+        self.width().is_multiple_of(mcu_w) && self.height().is_multiple_of(mcu_h)
+    }
+
+    /// Rotate in the DCT domain: blocks are moved and their coefficients transposed
+    /// and sign-flipped, so no quantization error is introduced. The image must be
+    /// MCU-aligned for the result to be exact.
+    pub fn rotate(&mut self, turn: QuarterTurn) {
+        for c in &mut self.components {
+            let (bw, bh) = (c.blocks_w, c.blocks_h);
+            let (new_w, new_h) = if turn == QuarterTurn::Cw180 { (bw, bh) } else { (bh, bw) };
+            let mut blocks = vec![[0i16; 64]; new_w * new_h];
+
+            for by in 0..bh {
+                for bx in 0..bw {
+                    let (nx, ny) = match turn {
+                        QuarterTurn::Cw90 => (bh - 1 - by, bx),
+                        QuarterTurn::Cw180 => (bw - 1 - bx, bh - 1 - by),
+                        QuarterTurn::Cw270 => (by, bw - 1 - bx),
+                    };
+                    let src = &c.blocks[by * bw + bx];
+                    let dst = &mut blocks[ny * new_w + nx];
+                    for v in 0..8 {
+                        for u in 0..8 {
+                            // Horizontal flips negate odd horizontal frequencies (u),
+                            // vertical flips negate odd vertical frequencies (v)
+                            dst[v * 8 + u] = match turn {
+                                QuarterTurn::Cw90 => if u % 2 == 1 { -src[u * 8 + v] } else { src[u * 8 + v] },
+                                QuarterTurn::Cw270 => if v % 2 == 1 { -src[u * 8 + v] } else { src[u * 8 + v] },
+                                QuarterTurn::Cw180 => if (u + v) % 2 == 1 { -src[v * 8 + u] } else { src[v * 8 + u] },
+                            };
+                        }
+                    }
+                }
+            }
+
+            c.blocks = blocks;
+            c.blocks_w = new_w;
+            c.blocks_h = new_h;
+            if turn != QuarterTurn::Cw180 {
+                std::mem::swap(&mut c.h, &mut c.v);
+            }
+        }
+
+        if turn != QuarterTurn::Cw180 {
+            std::mem::swap(&mut self.width, &mut self.height);
+            for (table, _) in self.qtables.iter_mut().flatten() {
+                let original = *table;
+                for v in 0..8 {
+                    for u in 0..8 {
+                        table[v * 8 + u] = original[u * 8 + v];
+                    }
+                }
+            }
+        }
+    }
+
+    /// Crop to `width`x`height` at (`x`, `y`), which must lie on the MCU grid. Whole blocks
+    /// are copied, so no quantization error is introduced.
+    pub fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let (mcu_w, mcu_h) = self.mcu_size();
+        let single = self.components.len() == 1;
+        let old: Vec<(usize, Vec<[i16; 64]>)> = self.components.iter_mut()
+            .map(|c| (c.blocks_w, std::mem::take(&mut c.blocks)))
+            .collect();
+
+        layout_components(width as u16, height as u16, &mut self.components);
+        for (c, (old_w, old_blocks)) in self.components.iter_mut().zip(old) {
+            let (per_x, per_y) = if single { (1, 1) } else { (c.h as usize, c.v as usize) };
+            let bx0 = (x / mcu_w) as usize * per_x;
+            let by0 = (y / mcu_h) as usize * per_y;
+            c.blocks = (0..c.blocks_h)
+                .flat_map(|by| (0..c.blocks_w).map(move |bx| (by, bx)))
+                .map(|(by, bx)| old_blocks[(by0 + by) * old_w + bx0 + bx])
+                .collect();
+        }
+
+        self.width = width as u16;
+        self.height = height as u16;
+    }
+
+    /// Bring the EXIF segment in line with the current geometry (see `patch_exif`)
+    fn update_exif(&mut self, reset_orientation: bool) {
+        let (width, height) = (self.width(), self.height());
+        for (marker, payload) in &mut self.passthrough {
+            if *marker == 0xE1 {
+                patch_exif(payload, width, height, reset_orientation);
+            }
+        }
+    }
+
+    /// Re-encode as a single interleaved sequential scan with optimized Huffman tables
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8];
+        let segment = |out: &mut Vec<u8>, marker: u8, payload: &[u8]| {
+            out.extend_from_slice(&[0xFF, marker]);
+            out.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+            out.extend_from_slice(payload);
+        };
+
+        for (marker, payload) in &self.passthrough {
+            segment(&mut out, *marker, payload);
+        }
+
+        for (id, table) in self.qtables.iter().enumerate() {
+            if let Some((values, precision)) = table {
+                let mut payload = vec![(precision << 4) | id as u8];
+                for &natural in &ZIGZAG {
+                    if *precision == 0 {
+                        payload.push(values[natural] as u8);
+                    } else {
+                        payload.extend_from_slice(&values[natural].to_be_bytes());
+                    }
+                }
+                segment(&mut out, 0xDB, &payload);
+            }
+        }
+
+        let single = self.components.len() == 1;
+        let mut sof = vec![8];
+        sof.extend_from_slice(&self.height.to_be_bytes());
+        sof.extend_from_slice(&self.width.to_be_bytes());
+        sof.push(self.components.len() as u8);
+        for c in &self.components {
+            let sampling = if single { 0x11 } else { (c.h << 4) | c.v };
+            sof.extend_from_slice(&[c.id, sampling, c.tq]);
+        }
+        segment(&mut out, self.sof_marker, &sof);
+
+        // First pass: gather symbol statistics for luma (table 0) and chroma (table 1)
+        let mut freqs = [[0u32; 257]; 4];
+        self.for_each_symbol(|table, symbol, _, _| freqs[table][symbol as usize] += 1);
+        let tables: Vec<([u8; 16], Vec<u8>)> = freqs.iter().map(optimal_huffman_table).collect();
+
+        let mut dht = Vec::new();
+        for (index, (bits, values)) in tables.iter().enumerate() {
+            if values.is_empty() {
+                continue;
+            }
+            let class = (index % 2) as u8;
+            let id = (index / 2) as u8;
+            dht.push((class << 4) | id);
+            dht.extend_from_slice(bits);
+            dht.extend_from_slice(values);
+        }
+        segment(&mut out, 0xC4, &dht);
+
+        let mut sos = vec![self.components.len() as u8];
+        for (i, c) in self.components.iter().enumerate() {
+            let table = if i == 0 { 0x00 } else { 0x11 };
+            sos.extend_from_slice(&[c.id, table]);
+        }
+        sos.extend_from_slice(&[0, 63, 0]);
+        segment(&mut out, 0xDA, &sos);
+
+        // Second pass: emit the entropy-coded data
+        let codes: Vec<[(u16, u8); 256]> = tables.iter().map(|(bits, values)| huffman_codes(bits, values)).collect();
+        let mut writer = BitWriter { out, acc: 0, nbits: 0 };
+        self.for_each_symbol(|table, symbol, extra, extra_bits| {
+            let (code, size) = codes[table][symbol as usize];
+            writer.put(code as u32, size as u32);
+            writer.put(extra as u32, extra_bits as u32);
+        });
+        let mut out = writer.finish();
+        out.extend_from_slice(&[0xFF, 0xD9]);
+        out
+    }
+
+    /// Visit every Huffman symbol in scan order as (table index, symbol, extra bits, bit count),
+    /// where table index is 2 * table id + class (0 = DC, 1 = AC)
+    fn for_each_symbol(&self, mut emit: impl FnMut(usize, u8, u16, u8)) {
+        let mut preds = vec![0i32; self.components.len()];
+        let mut encode_block = |block: &[i16; 64], component: usize, emit: &mut dyn FnMut(usize, u8, u16, u8)| {
+            let table = if component == 0 { 0 } else { 2 };
+            let dc = block[0] as i32;
+            let (size, bits) = magnitude(dc - preds[component]);
+            preds[component] = dc;
+            emit(table, size, bits, size);
+
+            let mut run = 0;
+            for &natural in &ZIGZAG[1..] {
+                let value = block[natural] as i32;
+                if value == 0 {
+                    run += 1;
+                    continue;
+                }
+                while run > 15 {
+                    emit(table + 1, 0xF0, 0, 0);
+                    run -= 16;
+                }
+                let (size, bits) = magnitude(value);
+                emit(table + 1, (run << 4) | size, bits, size);
+                run = 0;
+            }
+            if run > 0 {
+                emit(table + 1, 0x00, 0, 0);
+            }
+        };
+
+        if self.components.len() == 1 {
+            let c = &self.components[0];
+            let blocks_w = (self.width as usize).div_ceil(8);
+            let blocks_h = (self.height as usize).div_ceil(8);
+            for by in 0..blocks_h {
+                for bx in 0..blocks_w {
+                    encode_block(&c.blocks[by * c.blocks_w + bx], 0, &mut emit);
+                }
+            }
+            return;
+        }
+
+        let (hmax, vmax) = self.max_sampling();
+        let mcus_x = (self.width as usize).div_ceil(8 * hmax);
+        let mcus_y = (self.height as usize).div_ceil(8 * vmax);
+        for my in 0..mcus_y {
+            for mx in 0..mcus_x {
+                for (index, c) in self.components.iter().enumerate() {
+                    let (h, v) = (c.h as usize, c.v as usize);
+                    for by in 0..v {
+                        for bx in 0..h {
+                            let block = &c.blocks[(my * v + by) * c.blocks_w + mx * h + bx];
+                            encode_block(block, index, &mut emit);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn decode_scan(
+    mut reader: BitReader,
+    components: &[CodedComponent],
+    selectors: &[(usize, usize, usize)],
+    dc_tables: &[Option<HuffmanDecoder>; 4],
+    ac_tables: &[Option<HuffmanDecoder>; 4],
+    restart_interval: usize,
+    sink: &mut dyn BlockSink,
+) -> Result<usize, String> {
+    let mut tables = Vec::with_capacity(selectors.len());
+    for &(index, dc, ac) in selectors {
+        let dc = dc_tables.get(dc).and_then(|t| t.as_ref()).ok_or("Missing Huffman table")?;
+        let ac = ac_tables.get(ac).and_then(|t| t.as_ref()).ok_or("Missing Huffman table")?;
+        tables.push((index, dc, ac));
+    }
+
+    let decode_block = |reader: &mut BitReader, dc: &HuffmanDecoder, ac: &HuffmanDecoder, pred: &mut i32| -> Result<[i16; 64], String> {
+        let mut block = [0i16; 64];
+        let size = reader.decode(dc)? as u32;
+        if size > 11 {
+            return Err(corrupt());
+        }
+        *pred += extend(reader.bits(size), size);
+        block[0] = *pred as i16;
+
+        let mut k = 1;
+        while k < 64 {
+            let rs = reader.decode(ac)?;
+            let (run, size) = ((rs >> 4) as usize, (rs & 0x0F) as u32);
+            if size == 0 {
+                if run != 15 {
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+            k += run;
+            if k > 63 {
+                return Err(corrupt());
+            }
+            block[ZIGZAG[k]] = extend(reader.bits(size), size) as i16;
+            k += 1;
+        }
+        Ok(block)
+    };
+
+    let mut preds = vec![0i32; components.len()];
+    let mut mcu_count = 0usize;
+    let mut check_restart = |reader: &mut BitReader, preds: &mut [i32]| -> Result<(), String> {
+        if restart_interval > 0 && mcu_count > 0 && mcu_count.is_multiple_of(restart_interval) {
+            reader.restart()?;
+            preds.iter_mut().for_each(|p| *p = 0);
+        }
+        mcu_count += 1;
+        Ok(())
+    };
+
+    if components.len() == 1 {
+        let (_, dc, ac) = tables[0];
+        let c = &components[0];
+        for by in 0..c.blocks_h {
+            for bx in 0..c.blocks_w {
+                check_restart(&mut reader, &mut preds)?;
+                let block = decode_block(&mut reader, dc, ac, &mut preds[0])?;
+                sink.block(0, bx, by, &block);
+            }
+        }
+    } else {
+        let (h0, v0) = (components[0].h as usize, components[0].v as usize);
+        let mcus_x = components[0].blocks_w / h0;
+        let mcus_y = components[0].blocks_h / v0;
+
+        for my in 0..mcus_y {
+            for mx in 0..mcus_x {
+                check_restart(&mut reader, &mut preds)?;
+                for &(index, dc, ac) in &tables {
+                    let (h, v) = (components[index].h as usize, components[index].v as usize);
+                    for by in 0..v {
+                        for bx in 0..h {
+                            let block = decode_block(&mut reader, dc, ac, &mut preds[index])?;
+                            sink.block(index, mx * h + bx, my * v + by, &block);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(reader.pos)
+}
+
+/// Magnitude category and the low bits encoding `value` (JPEG F.1.2.1)
+fn magnitude(value: i32) -> (u8, u16) {
+    if value == 0 {
+        return (0, 0);
+    }
+    let size = 32 - value.unsigned_abs().leading_zeros();
+    let bits = if value < 0 { value - 1 } else { value };
+    (size as u8, (bits as u32 & ((1 << size) - 1)) as u16)
+}
+
+/// Build a length-limited optimal Huffman table from symbol counts (JPEG K.2)
+fn optimal_huffman_table(counts: &[u32; 257]) -> ([u8; 16], Vec<u8>) {
+    let mut freq: Vec<u64> = counts.iter().map(|&c| c as u64).collect();
+    if freq[..256].iter().all(|&f| f == 0) {
+        return ([0; 16], Vec::new());
+    }
+    // Reserved symbol guarantees no code consists of all 1 bits
+    freq[256] = 1;
+
+    let mut code_size = [0usize; 257];
+    let mut others = [-1i32; 257];
+    loop {
+        let mut c1 = -1i32;
+        let mut c2 = -1i32;
+        let mut v1 = u64::MAX;
+        let mut v2 = u64::MAX;
+        for (i, &f) in freq.iter().enumerate() {
+            if f == 0 {
+                continue;
+            }
+            if f <= v1 {
+                v2 = v1;
+                c2 = c1;
+                v1 = f;
+                c1 = i as i32;
+            } else if f <= v2 {
+                v2 = f;
+                c2 = i as i32;
+            }
+        }
+        if c2 < 0 {
+            break;
+        }
+
+        freq[c1 as usize] += freq[c2 as usize];
+        freq[c2 as usize] = 0;
+
+        let mut c = c1 as usize;
+        code_size[c] += 1;
+        while others[c] >= 0 {
+            c = others[c] as usize;
+            code_size[c] += 1;
+        }
+        others[c] = c2;
+
+        let mut c = c2 as usize;
+        code_size[c] += 1;
+        while others[c] >= 0 {
+            c = others[c] as usize;
+            code_size[c] += 1;
+        }
+    }
+
+    let mut bits = [0u32; 33];
+    for &size in code_size.iter().filter(|&&s| s > 0) {
+        bits[size.min(32)] += 1;
+    }
+
+    // Limit code lengths to 16 bits
+    for i in (17..=32).rev() {
+        while bits[i] > 0 {
+            let mut j = i - 2;
+            while bits[j] == 0 {
+                j -= 1;
+            }
+            bits[i] -= 2;
+            bits[i - 1] += 1;
+            bits[j + 1] += 2;
+            bits[j] -= 1;
+        }
+    }
+    // Drop the reserved symbol from the longest length
+    let mut i = 16;
+    while bits[i] == 0 {
+        i -= 1;
+    }
+    bits[i] -= 1;
+
+    let mut values = Vec::new();
+    for size in 1..=32 {
+        for (symbol, &s) in code_size[..256].iter().enumerate() {
+            if s == size {
+                values.push(symbol as u8);
+            }
+        }
+    }
+
+    let mut lengths = [0u8; 16];
+    for (i, length) in lengths.iter_mut().enumerate() {
+        *length = bits[i + 1] as u8;
+    }
+    (lengths, values)
+}
+
+/// Canonical code and length per symbol for a table
+fn huffman_codes(bits: &[u8; 16], values: &[u8]) -> [(u16, u8); 256] {
+    let mut codes = [(0u16, 0u8); 256];
+    let mut code = 0u16;
+    let mut k = 0;
+    for (len, &count) in bits.iter().enumerate() {
+        for _ in 0..count {
+            codes[values[k] as usize] = (code, len as u8 + 1);
+            code += 1;
+            k += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, bits: u32, count: u32) {
+        if count == 0 {
+            return;
+        }
+        self.acc = (self.acc << count) | (bits & ((1 << count) - 1));
+        self.nbits += count;
+        while self.nbits >= 8 {
+            let byte = (self.acc >> (self.nbits - 8)) as u8;
+            self.out.push(byte);
+            if byte == 0xFF {
+                self.out.push(0x00);
+            }
+            self.nbits -= 8;
+        }
+        self.acc &= (1 << self.nbits) - 1;
+    }
+
+    /// Pad the final byte with 1 bits
+    fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            let pad = 8 - self.nbits;
+            self.put((1 << pad) - 1, pad);
+        }
+        self.out
+    }
+}
+
+/// Decodes blocks straight into reduced-resolution component planes: each block becomes
+/// `size`x`size` pixels through an inverse DCT of only its lowest frequencies (the DCT
+/// scaling libjpeg offers), so no full-resolution buffer ever exists
+struct ScaledDecoder {
+    size: usize,
+    /// `C(u)/2 * cos((2x+1)u*pi/2N)` at `x * size + u`
+    cosines: Vec<f32>,
+    planes: Vec<Vec<u8>>,
+    strides: Vec<usize>,
+    qtables: Vec<[u16; 64]>,
+}
+
+impl ScaledDecoder {
+    fn new(size: usize) -> Self {
+        let mut cosines = vec![0.0; size * size];
+        for x in 0..size {
+            for u in 0..size {
+                let c = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+                let angle = (2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / (2 * size) as f32;
+                cosines[x * size + u] = c / 2.0 * angle.cos();
+            }
+        }
+        ScaledDecoder { size, cosines, planes: Vec::new(), strides: Vec::new(), qtables: Vec::new() }
+    }
+}
+
+impl BlockSink for ScaledDecoder {
+    fn begin(&mut self, components: &[CodedComponent], qtables: &[Option<([u16; 64], u8)>; 4]) -> Result<(), String> {
+        for c in components {
+            let stride = c.blocks_w * self.size;
+            let rows = c.blocks_h * self.size;
+            check_dimensions(stride as u32, rows as u32)?;
+            self.planes.push(vec![0; stride * rows]);
+            self.strides.push(stride);
+            self.qtables.push(qtables[c.tq as usize].ok_or("Missing quantization table")?.0);
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, component: usize, bx: usize, by: usize, coefficients: &[i16; 64]) {
+        let n = self.size;
+        let q = &self.qtables[component];
+
+        // Rows first (horizontal frequencies), then columns
+        let mut rows = [0.0f32; 16];
+        for v in 0..n {
+            for x in 0..n {
+                rows[v * n + x] = (0..n)
+                    .map(|u| self.cosines[x * n + u] * coefficients[v * 8 + u] as f32 * q[v * 8 + u] as f32)
+                    .sum();
+            }
+        }
+
+        let stride = self.strides[component];
+        let plane = &mut self.planes[component];
+        for y in 0..n {
+            for x in 0..n {
+                let value: f32 = (0..n).map(|v| self.cosines[y * n + v] * rows[v * n + x]).sum();
+                plane[(by * n + y) * stride + bx * n + x] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// Decode a baseline/extended sequential grayscale or YCbCr JPEG at 1/`scale` of its
+/// size (`scale` 2, 4 or 8) without a full-resolution decode. Peak memory is the
+/// scaled output plus one small plane per component, e.g. 1/64 of a normal decode at
+/// scale 8. Chroma is upsampled with nearest neighbor.
+pub(crate) fn decode_jpeg_scaled(data: &[u8], scale: u32) -> Result<DynamicImage, String> {
+    if !matches!(scale, 2 | 4 | 8) {
+        return Err(format!("Unsupported JPEG scale 1/{}", scale));
+    }
+    let color_space = jpeg_color_space(data).ok_or("Unsupported JPEG color space")?;
+    if !matches!(color_space, "gray" | "ycbcr" | "rgb") {
+        return Err(format!("Scaled decoding does not support {} JPEGs", color_space));
+    }
+
+    let mut decoder = ScaledDecoder::new(8 / scale as usize);
+    let coefficients = JpegCoefficients::read(data, &mut decoder)?;
+    let width = coefficients.width().div_ceil(scale);
+    let height = coefficients.height().div_ceil(scale);
+
+    if coefficients.components.len() == 1 {
+        let plane = &decoder.planes[0];
+        let stride = decoder.strides[0];
+        return Ok(DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            Luma([plane[y as usize * stride + x as usize]])
+        })));
+    }
+
+    let (hmax, vmax) = coefficients.max_sampling();
+    let sample = |index: usize, x: u32, y: u32| -> f32 {
+        let c = &coefficients.components[index];
+        let px = x as usize * c.h as usize / hmax;
+        let py = y as usize * c.v as usize / vmax;
+        decoder.planes[index][py * decoder.strides[index] + px] as f32
+    };
+
+    Ok(DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let (a, b, c) = (sample(0, x, y), sample(1, x, y), sample(2, x, y));
+        let rgb = if color_space == "rgb" {
+            [a, b, c]
+        } else {
+            let (cb, cr) = (b - 128.0, c - 128.0);
+            [a + 1.402 * cr, a - 0.344136 * cb - 0.714136 * cr, a + 1.772 * cb]
+        };
+        Rgb(rgb.map(|v| v.round().clamp(0.0, 255.0) as u8))
+    })))
+}
+
+/// Byte-order aware access to the TIFF structure inside an EXIF payload
+struct TiffPatcher<'a> {
+    data: &'a mut [u8],
+    big_endian: bool,
+}
+
+impl TiffPatcher<'_> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn set_u16(&mut self, offset: usize, value: u16) -> Option<()> {
+        let bytes = if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        self.data.get_mut(offset..offset + 2)?.copy_from_slice(&bytes);
+        Some(())
+    }
+
+    fn set_u32(&mut self, offset: usize, value: u32) -> Option<()> {
+        let bytes = if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        self.data.get_mut(offset..offset + 4)?.copy_from_slice(&bytes);
+        Some(())
+    }
+
+    /// Offsets of the 12-byte entries of the IFD at `offset`, and of its next-IFD link
+    fn entries(&self, offset: usize) -> Option<(Vec<usize>, usize)> {
+        let count = self.u16(offset)? as usize;
+        let entries = (0..count).map(|i| offset + 2 + 12 * i).collect();
+        let next = offset + 2 + 12 * count;
+        self.u32(next)?;
+        Some((entries, next))
+    }
+}
+
+/// Update an EXIF APP1 payload in place after a lossless transform: PixelXDimension
+/// and PixelYDimension are set to `width`x`height`, the link to the thumbnail IFD is
+/// cut (the embedded thumbnail would show the old framing) and, with
+/// `reset_orientation`, Orientation becomes 1 because the pixels are now upright.
+/// Anything that does not parse as EXIF is left alone.
+fn patch_exif(payload: &mut [u8], width: u32, height: u32, reset_orientation: bool) -> Option<()> {
+    if !payload.starts_with(b"Exif\0\0") {
+        return None;
+    }
+    let data = &mut payload[6..];
+    let big_endian = match data.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let mut tiff = TiffPatcher { data, big_endian };
+
+    let (ifd0, next) = tiff.entries(tiff.u32(4)? as usize)?;
+    tiff.set_u32(next, 0)?;
+
+    let mut exif_ifd = None;
+    for entry in ifd0 {
+        match tiff.u16(entry)? {
+            0x0112 if reset_orientation => tiff.set_u16(entry + 8, 1)?,
+            0x8769 => exif_ifd = Some(tiff.u32(entry + 8)? as usize),
+            _ => {}
+        }
+    }
+
+    let (entries, _) = tiff.entries(exif_ifd?)?;
+    for entry in entries {
+        let value = match tiff.u16(entry)? {
+            0xA002 => width,
+            0xA003 => height,
+            _ => continue,
+        };
+        match tiff.u16(entry + 2)? {
+            3 => tiff.set_u16(entry + 8, u16::try_from(value).ok()?)?,
+            4 => tiff.set_u32(entry + 8, value)?,
+            _ => {}
+        }
+    }
+    Some(())
+}
+
+/// Re-encode `img`, the result of a pixel-domain edit of the JPEG `source`, at the
+/// source's estimated quality (95 if unknown) rather than the encoder default, and
+/// carry over its APP1-APP15 and COM segments (EXIF, ICC profile, XMP, comments) with
+/// the EXIF updated as in `patch_exif`. Only the lossless paths avoid generation loss.
+///
+/// The output is always YCbCr, so the Adobe APP14 segment (whose transform flag would
+/// make decoders read it as RGB or CMYK) is dropped, and so is the ICC profile of a
+/// CMYK or YCCK source, which does not describe the RGB pixels written.
+fn reencode_jpeg(source: &[u8], img: &DynamicImage, reset_orientation: bool) -> Result<Vec<u8>, String> {
+    let quality = estimate_jpeg_quality(source).unwrap_or(95);
+    let encoded = encode_jpeg(img, quality, false)?;
+    let keep_icc = matches!(jpeg_color_space(source), Some("ycbcr" | "rgb" | "gray"));
+
+    let mut metadata = Vec::new();
+    let segments = jpeg_segments(source)?;
+    let kept = segments.iter().filter(|s| match s.marker {
+        0xEE => false,
+        0xE2 if source[s.payload..s.end].starts_with(b"ICC_PROFILE\0") => keep_icc,
+        marker => matches!(marker, 0xE1..=0xEF | 0xFE),
+    });
+    for segment in kept {
+        let mut payload = source[segment.payload..segment.end].to_vec();
+        if segment.marker == 0xE1 {
+            patch_exif(&mut payload, img.width(), img.height(), reset_orientation);
+        }
+        metadata.extend_from_slice(&[0xFF, segment.marker]);
+        metadata.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        metadata.extend_from_slice(&payload);
+    }
+
+    // After the encoder's own JFIF header, which must come first
+    let insert_at = jpeg_segments(&encoded)?.iter()
+        .take_while(|s| s.marker == 0xE0)
+        .last()
+        .map_or(2, |s| s.end);
+    let mut output = encoded;
+    output.splice(insert_at..insert_at, metadata);
+    Ok(output)
+}
+
+/// Rotate a JPEG clockwise by 90, 180 or 270 degrees without re-compressing, by
+/// rearranging its DCT blocks, so repeated rotations never lose quality. The EXIF
+/// Orientation is reset to 1 (the pixels are rotated, viewers must not rotate again),
+/// the EXIF pixel dimensions are updated and the EXIF thumbnail is dropped.
+///
+/// The lossless path needs a baseline/extended sequential JPEG whose dimensions are
+/// multiples of the MCU size (8 or 16 pixels, depending on chroma subsampling). Other
+/// JPEGs are decoded, rotated and re-encoded at their estimated quality with the same
+/// metadata changes; other formats are rotated like `rotate`.
+#[wasm_bindgen]
+pub fn rotate_jpeg_lossless(data: &[u8], degrees: u16) -> Result<Vec<u8>, String> {
+    let turn = match degrees % 360 {
+        0 => return Ok(data.to_vec()),
+        90 => QuarterTurn::Cw90,
+        180 => QuarterTurn::Cw180,
+        270 => QuarterTurn::Cw270,
+        _ => return Err(format!("Lossless rotation requires a multiple of 90 degrees, got {}", degrees)),
+    };
+
+    match JpegCoefficients::parse(data) {
+        Ok(mut coefficients) if coefficients.is_mcu_aligned() => {
+            coefficients.rotate(turn);
+            coefficients.update_exif(true);
+            Ok(coefficients.encode())
+        }
+        _ if jpeg_segments(data).is_ok() => {
+            let img = decode_image(data)?.img;
+            let rotated = match turn {
+                QuarterTurn::Cw90 => img.rotate90(),
+                QuarterTurn::Cw180 => img.rotate180(),
+                QuarterTurn::Cw270 => img.rotate270(),
+            };
+            reencode_jpeg(data, &rotated, true)
+        }
+        _ => rotate(data, degrees as f32, "transparent", ""),
+    }
+}
+
+/// Crop a JPEG without re-compressing by copying whole DCT blocks. The top-left corner
+/// is snapped down to the MCU grid (8 or 16 pixels, depending on chroma subsampling)
+/// and the size grows by the same amount, so the requested area is always covered;
+/// the returned rectangle is the one actually used. The EXIF pixel dimensions are
+/// updated and the EXIF thumbnail is dropped.
+///
+/// Progressive JPEGs are cropped exactly through a decode and a re-encode at their
+/// estimated quality with the same metadata changes; other formats are cropped exactly
+/// and re-encoded in their own format.
+#[wasm_bindgen]
+pub fn crop_jpeg_lossless(data: &[u8], x: u32, y: u32, width: u32, height: u32) -> Result<CropResult, String> {
+    if let Ok(mut coefficients) = JpegCoefficients::parse(data) {
+        check_region(x, y, width, height, coefficients.width(), coefficients.height())?;
+        let (mcu_w, mcu_h) = coefficients.mcu_size();
+        let (snapped_x, snapped_y) = (x - x % mcu_w, y - y % mcu_h);
+        let (crop_w, crop_h) = (width + x - snapped_x, height + y - snapped_y);
+
+        coefficients.crop(snapped_x, snapped_y, crop_w, crop_h);
+        coefficients.update_exif(false);
+        return Ok(CropResult { data: coefficients.encode(), x: snapped_x, y: snapped_y, width: crop_w, height: crop_h });
+    }
+
+    let decoded = decode_image(data)?;
+    check_region(x, y, width, height, decoded.img.width(), decoded.img.height())?;
+    let cropped = decoded.img.crop_imm(x, y, width, height);
+    let output = if jpeg_segments(data).is_ok() {
+        reencode_jpeg(data, &cropped, false)?
+    } else {
+        let mut output = Vec::new();
+        cropped.write_to(&mut Cursor::new(&mut output), decoded.format)
+            .map_err(|e| format!("Failed to encode cropped image: {}", e))?;
+        output
+    };
+
+    Ok(CropResult { data: output, x, y, width, height })
+}
+
+/// Color space of a JPEG's coded components, from the frame header and the Adobe APP14
+/// transform flag: "gray", "ycbcr", "rgb", "cmyk" or "ycck". The decoder already converts
+/// CMYK and YCCK (with Adobe's inverted storage) to RGB, this only reports the source.
+pub(crate) fn jpeg_color_space(data: &[u8]) -> Option<&'static str> {
+    let segments = jpeg_segments(data).ok()?;
+
+    let frame = segments.iter()
+        .find(|s| matches!(s.marker, 0xC0..=0xCF) && !matches!(s.marker, 0xC4 | 0xC8 | 0xCC))?;
+    let components = *data.get(frame.payload + 5)?;
+
+    let adobe_transform = segments.iter()
+        .find(|s| s.marker == 0xEE && data[s.payload..s.end].starts_with(b"Adobe"))
+        .and_then(|s| data.get(s.payload + 11).copied());
+
+    match (components, adobe_transform) {
+        (1, _) => Some("gray"),
+        (3, Some(0)) => Some("rgb"),
+        (3, _) => Some("ycbcr"),
+        (4, Some(2)) => Some("ycck"),
+        (4, _) => Some("cmyk"),
+        _ => None,
+    }
+}
+
+/// Text of the first JPEG comment (COM) segment, if any
+#[wasm_bindgen]
+pub fn read_jpeg_comment(data: &[u8]) -> Result<Option<String>, String> {
+    let segments = jpeg_segments(data)?;
+    Ok(segments.iter().find(|s| s.marker == 0xFE).map(|s| {
+        String::from_utf8_lossy(&data[s.payload..s.end]).trim_end_matches('\0').to_string()
+    }))
+}
+
+/// Replace all JPEG comment (COM) segments with a single one holding `comment`, placed
+/// after the APPn headers. An empty comment removes them. The image data is untouched.
+#[wasm_bindgen]
+pub fn set_jpeg_comment(data: &[u8], comment: &str) -> Result<Vec<u8>, String> {
+    const MAX_COMMENT_LEN: usize = u16::MAX as usize - 2;
+    if comment.len() > MAX_COMMENT_LEN {
+        return Err(format!("Comment is too long ({} bytes, maximum {})", comment.len(), MAX_COMMENT_LEN));
+    }
+
+    let segments = jpeg_segments(data)?;
+    let app_count = segments.iter().take_while(|s| (0xE0..=0xEF).contains(&s.marker)).count();
+    let tail = segments.last().map_or(2, |s| s.end);
+
+    let mut output = Vec::with_capacity(data.len() + comment.len() + 4);
+    output.extend_from_slice(&data[..2]);
+    for segment in &segments[..app_count] {
+        output.extend_from_slice(&data[segment.start..segment.end]);
+    }
+    if !comment.is_empty() {
+        output.extend_from_slice(&[0xFF, 0xFE]);
+        output.extend_from_slice(&((comment.len() + 2) as u16).to_be_bytes());
+        output.extend_from_slice(comment.as_bytes());
+    }
+    for segment in segments[app_count..].iter().filter(|s| s.marker != 0xFE) {
+        output.extend_from_slice(&data[segment.start..segment.end]);
+    }
+    output.extend_from_slice(&data[tail..]);
+
+    Ok(output)
+}
+
+/// Annex K luminance quantization table (natural order), the base of IJG quality scaling
+const STD_LUMA_TABLE: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61,
+    12, 12, 14, 19, 26, 58, 60, 55,
+    14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62,
+    18, 22, 37, 56, 68, 109, 103, 77,
+    24, 35, 55, 64, 81, 104, 113, 92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// Annex K chrominance quantization table (natural order)
+const STD_CHROMA_TABLE: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99,
+    18, 21, 26, 66, 99, 99, 99, 99,
+    24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// Estimate the quality (1-100) a JPEG was saved at by comparing its luminance and
+/// chrominance quantization tables with the standard tables scaled the way libjpeg
+/// does. Encoders with custom tables (e.g. Photoshop) get the closest equivalent.
+/// Returns `None` for non-JPEG input or files without quantization tables.
+#[wasm_bindgen]
+pub fn estimate_jpeg_quality(data: &[u8]) -> Option<u8> {
+    let segments = jpeg_segments(data).ok()?;
+    let mut tables = Vec::new();
+    for segment in segments.iter().filter(|s| s.marker == 0xDB) {
+        tables.extend(parse_dqt(&data[segment.payload..segment.end]).ok()?);
+    }
+    if tables.is_empty() {
+        return None;
+    }
+
+    let error = |quality: u32| -> u64 {
+        let scale = if quality < 50 { 5000 / quality } else { 200 - 2 * quality };
+        tables.iter()
+            .filter(|(id, _, _)| *id < 2)
+            .map(|(id, table, _)| {
+                let standard = if *id == 0 { &STD_LUMA_TABLE } else { &STD_CHROMA_TABLE };
+                standard.iter().zip(table)
+                    .map(|(&base, &actual)| {
+                        let expected = ((base as u32 * scale + 50) / 100).clamp(1, 255);
+                        expected.abs_diff(actual as u32) as u64
+                    })
+                    .sum::<u64>()
+            })
+            .sum()
+    };
+
+    (1..=100).min_by_key(|&quality| error(quality)).map(|quality| quality as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{GenericImageView, ImageFormat};
+
+    /// Smooth color gradient as a baseline JPEG
+    fn gradient_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let img = RgbImage::from_fn(width, height, |x, y| {
+            Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, ((x + y) * 2) as u8])
+        });
+        let mut output = Vec::new();
+        JpegEncoder::new_with_quality(&mut output, 90).encode_image(&img).unwrap();
+        output
+    }
+
+    /// Big-endian EXIF APP1 segment with Orientation, PixelX/YDimension and a thumbnail IFD
+    fn exif_segment(orientation: u16, width: u32, height: u16) -> Vec<u8> {
+        let entry = |tag: u16, kind: u16, value: u32| {
+            let mut e = Vec::new();
+            e.extend_from_slice(&tag.to_be_bytes());
+            e.extend_from_slice(&kind.to_be_bytes());
+            e.extend_from_slice(&1u32.to_be_bytes());
+            if kind == 3 {
+                e.extend_from_slice(&(value as u16).to_be_bytes());
+                e.extend_from_slice(&[0, 0]);
+            } else {
+                e.extend_from_slice(&value.to_be_bytes());
+            }
+            e
+        };
+
+        let mut tiff = b"MM\0\x2A\0\0\0\x08".to_vec();
+        tiff.extend_from_slice(&2u16.to_be_bytes());
+        tiff.extend(entry(0x0112, 3, orientation as u32));
+        tiff.extend(entry(0x8769, 4, 38));
+        tiff.extend_from_slice(&68u32.to_be_bytes());
+        tiff.extend_from_slice(&2u16.to_be_bytes());
+        tiff.extend(entry(0xA002, 4, width));
+        tiff.extend(entry(0xA003, 3, height as u32));
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        tiff.extend(entry(0x0103, 3, 6));
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        segment.extend_from_slice(b"Exif\0\0");
+        segment.extend(tiff);
+        segment
+    }
+
+    fn with_exif(jpeg: &[u8], orientation: u16) -> Vec<u8> {
+        let (width, height) = image::load_from_memory(jpeg).unwrap().dimensions();
+        let mut output = jpeg.to_vec();
+        output.splice(2..2, exif_segment(orientation, width, height as u16));
+        output
+    }
+
+    fn exif_uint(data: &[u8], tag: exif::Tag) -> Option<u32> {
+        let exif = exif::Reader::new().read_from_container(&mut Cursor::new(data)).ok()?;
+        exif.get_field(tag, exif::In::PRIMARY)?.value.get_uint(0)
+    }
+
+    fn has_thumbnail_ifd(data: &[u8]) -> bool {
+        let exif = exif::Reader::new().read_from_container(&mut Cursor::new(data)).unwrap();
+        exif.fields().any(|f| f.ifd_num == exif::In::THUMBNAIL)
+    }
+
+    fn max_difference(a: &DynamicImage, b: &DynamicImage) -> u8 {
+        assert_eq!(a.dimensions(), b.dimensions());
+        a.to_rgb8().as_raw().iter().zip(b.to_rgb8().as_raw()).map(|(x, y)| x.abs_diff(*y)).max().unwrap_or(0)
+    }
+
+    #[test]
+    fn four_quarter_turns_restore_identical_pixels() {
+        let source = gradient_jpeg(48, 32);
+        assert!(JpegCoefficients::parse(&source).unwrap().is_mcu_aligned());
+
+        let mut rotated = source.clone();
+        for _ in 0..4 {
+            rotated = rotate_jpeg_lossless(&rotated, 90).unwrap();
+        }
+
+        let original = image::load_from_memory(&source).unwrap();
+        let restored = image::load_from_memory(&rotated).unwrap();
+        assert_eq!(original.to_rgb8(), restored.to_rgb8());
+    }
+
+    #[test]
+    fn lossless_rotation_matches_pixel_rotation() {
+        let source = gradient_jpeg(48, 32);
+        let expected = image::load_from_memory(&source).unwrap().rotate90();
+
+        for (degrees, expected) in [(90, expected.clone()), (180, expected.rotate90()), (270, expected.rotate180())] {
+            let rotated = image::load_from_memory(&rotate_jpeg_lossless(&source, degrees).unwrap()).unwrap();
+            assert!(max_difference(&rotated, &expected) <= 2, "{} degrees", degrees);
+        }
+    }
+
+    #[test]
+    fn lossless_rotation_resets_exif_orientation() {
+        let source = with_exif(&gradient_jpeg(48, 32), 6);
+        let rotated = rotate_jpeg_lossless(&source, 90).unwrap();
+
+        assert_eq!(exif_uint(&rotated, exif::Tag::Orientation), Some(1));
+        assert_eq!(exif_uint(&rotated, exif::Tag::PixelXDimension), Some(32));
+        assert_eq!(exif_uint(&rotated, exif::Tag::PixelYDimension), Some(48));
+        assert!(!has_thumbnail_ifd(&rotated));
+    }
+
+    #[test]
+    fn fallback_rotation_keeps_metadata_and_quality() {
+        let source = with_exif(&gradient_jpeg(30, 20), 6);
+        assert!(!JpegCoefficients::parse(&source).unwrap().is_mcu_aligned());
+        let rotated = rotate_jpeg_lossless(&source, 90).unwrap();
+
+        assert_eq!(exif_uint(&rotated, exif::Tag::Orientation), Some(1));
+        assert_eq!(exif_uint(&rotated, exif::Tag::PixelXDimension), Some(20));
+        assert_eq!(exif_uint(&rotated, exif::Tag::PixelYDimension), Some(30));
+        assert_eq!(estimate_jpeg_quality(&rotated), estimate_jpeg_quality(&source));
+
+        let expected = image::load_from_memory_with_format(&source, ImageFormat::Jpeg).unwrap().rotate90();
+        let rotated = image::load_from_memory(&rotated).unwrap();
+        assert!(max_difference(&rotated, &expected) <= 8);
+    }
+
+    #[test]
+    fn lossless_crop_matches_pixel_crop_at_returned_rect() {
+        let source = with_exif(&gradient_jpeg(64, 48), 1);
+        let result = crop_jpeg_lossless(&source, 13, 9, 30, 20).unwrap();
+        assert!(result.x <= 13 && result.y <= 9);
+        assert!(result.x + result.width >= 43 && result.y + result.height >= 29);
+
+        let cropped = image::load_from_memory(&result.data).unwrap();
+        let expected = image::load_from_memory(&source).unwrap()
+            .crop_imm(result.x, result.y, result.width, result.height);
+        assert!(max_difference(&cropped, &expected) <= 2);
+
+        assert_eq!(exif_uint(&result.data, exif::Tag::PixelXDimension), Some(result.width));
+        assert_eq!(exif_uint(&result.data, exif::Tag::PixelYDimension), Some(result.height));
+        assert!(!has_thumbnail_ifd(&result.data));
+    }
+
+    #[test]
+    fn progressive_crop_is_exact_and_updates_exif() {
+        let img = image::load_from_memory(&gradient_jpeg(64, 48)).unwrap();
+        let source = with_exif(&encode_jpeg(&img, 90, true).unwrap(), 1);
+        assert!(JpegCoefficients::parse(&source).is_err());
+
+        let result = crop_jpeg_lossless(&source, 13, 9, 30, 20).unwrap();
+        assert_eq!((result.x, result.y, result.width, result.height), (13, 9, 30, 20));
+        assert_eq!(image::guess_format(&result.data).unwrap(), ImageFormat::Jpeg);
+        assert_eq!(exif_uint(&result.data, exif::Tag::PixelXDimension), Some(30));
+        assert_eq!(exif_uint(&result.data, exif::Tag::PixelYDimension), Some(20));
+        assert!(!has_thumbnail_ifd(&result.data));
+    }
+
+    /// `jpeg` with the frame header claiming `width`x`height`
+    fn with_frame_size(jpeg: &[u8], width: u16, height: u16) -> Vec<u8> {
+        let sof = jpeg_segments(jpeg).unwrap().into_iter().find(|s| s.marker == 0xC0).unwrap();
+        let mut output = jpeg.to_vec();
+        output[sof.payload + 1..sof.payload + 3].copy_from_slice(&height.to_be_bytes());
+        output[sof.payload + 3..sof.payload + 5].copy_from_slice(&width.to_be_bytes());
+        output
+    }
+
+    #[test]
+    fn lossless_paths_reject_oversized_frame_headers() {
+        let huge = with_frame_size(&gradient_jpeg(48, 32), 65535, 65535);
+        let error = JpegCoefficients::parse(&huge).err().unwrap();
+        assert!(error.contains("exceed"), "{}", error);
+        assert!(rotate_jpeg_lossless(&huge, 90).is_err());
+        assert!(crop_jpeg_lossless(&huge, 0, 0, 16, 16).is_err());
+    }
+
+    #[test]
+    fn scaled_decode_rejects_oversized_frame_headers() {
+        let huge = with_frame_size(&gradient_jpeg(48, 32), 65535, 65535);
+        for scale in [2, 4, 8] {
+            assert!(decode_jpeg_scaled(&huge, scale).is_err());
+        }
+        assert!(crate::common::decode_for_size(&huge, 100, 100).is_err());
+    }
+
+    fn solid_cmyk_jpeg(cmyk: [u8; 4], color_type: jpeg_encoder::ColorType) -> Vec<u8> {
+        let pixels: Vec<u8> = cmyk.repeat(16 * 16);
+        let mut output = Vec::new();
+        jpeg_encoder::Encoder::new(&mut output, 95).encode(&pixels, 16, 16, color_type).unwrap();
+        output
+    }
+
+    /// `jpeg` with an APP2 ICC profile segment after SOI
+    fn with_icc_segment(jpeg: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, 0xE2, 0, 0];
+        segment.extend_from_slice(b"ICC_PROFILE\0\x01\x01");
+        segment.extend_from_slice(&[0; 32]);
+        let length = (segment.len() - 2) as u16;
+        segment[2..4].copy_from_slice(&length.to_be_bytes());
+        let mut output = jpeg.to_vec();
+        output.splice(2..2, segment);
+        output
+    }
+
+    fn has_segment(data: &[u8], marker: u8) -> bool {
+        jpeg_segments(data).unwrap().iter().any(|s| s.marker == marker)
+    }
+
+    #[test]
+    fn reencode_drops_adobe_and_cmyk_icc_segments() {
+        let cmyk = with_icc_segment(&solid_cmyk_jpeg([255, 0, 0, 0], jpeg_encoder::ColorType::Cmyk));
+        assert!(has_segment(&cmyk, 0xEE) && has_segment(&cmyk, 0xE2));
+
+        let img = decode_image(&cmyk).unwrap().img;
+        let output = reencode_jpeg(&cmyk, &img, false).unwrap();
+        assert!(!has_segment(&output, 0xEE) && !has_segment(&output, 0xE2));
+        assert_eq!(jpeg_color_space(&output), Some("ycbcr"));
+        let pixel = decode_image(&output).unwrap().img.to_rgb8().get_pixel(8, 8).0;
+        assert!(pixel[0] <= 8 && pixel[1] >= 247 && pixel[2] >= 247, "{:?}", pixel);
+
+        let ycbcr = with_icc_segment(&gradient_jpeg(30, 20));
+        let img = decode_image(&ycbcr).unwrap().img;
+        assert!(has_segment(&reencode_jpeg(&ycbcr, &img, false).unwrap(), 0xE2));
+    }
+
+    #[test]
+    fn cmyk_and_ycck_jpegs_decode_to_the_right_colors() {
+        let samples = [
+            ([255, 0, 0, 0], [0, 255, 255]),
+            ([0, 255, 0, 0], [255, 0, 255]),
+            ([0, 0, 255, 0], [255, 255, 0]),
+            ([0, 0, 0, 255], [0, 0, 0]),
+            ([0, 0, 0, 0], [255, 255, 255]),
+        ];
+
+        for (color_type, space) in [(jpeg_encoder::ColorType::Cmyk, "cmyk"), (jpeg_encoder::ColorType::CmykAsYcck, "ycck")] {
+            for (cmyk, rgb) in samples {
+                let jpeg = solid_cmyk_jpeg(cmyk, color_type);
+                assert_eq!(jpeg_color_space(&jpeg), Some(space));
+
+                let decoded = decode_image(&jpeg).unwrap().img.to_rgb8();
+                let pixel = decoded.get_pixel(8, 8).0;
+                assert!(
+                    pixel.iter().zip(rgb).all(|(a, b)| a.abs_diff(b) <= 8),
+                    "{} {:?} decoded to {:?}, expected {:?}", space, cmyk, pixel, rgb
+                );
+            }
+        }
+    }
+}
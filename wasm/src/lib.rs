@@ -1,14 +1,46 @@
 use wasm_bindgen::prelude::*;
 
 mod common;
+mod timing;
+mod parallel;
 mod metadata;
+mod icc;
 mod transforms;
 mod adjustments;
+mod alpha;
+mod analysis;
+mod filters;
+mod palette;
+mod lut;
+mod animation;
+mod encode;
+mod jpeg;
+mod resolution;
+mod generate;
+mod text;
+mod raw;
+mod handle;
+mod pipeline;
 
 pub use metadata::ImageMetadata;
-pub use metadata::read_image_metadata;
-pub use transforms::{crop_image, resize_image, rotate_image};
-pub use adjustments::adjust_image;
+pub use metadata::{Thumbnail, extract_exif_thumbnail, orientation_category, read_image_metadata};
+pub use icc::convert_to_srgb;
+pub use transforms::{CropOutcome, CropRect, CropResult, Tile, TransformResult, auto_straighten_from_exif, compute_crop_rect, crop_clamped, crop_image, crop_multiple, crop_resize, perspective_transform, resize, resize_cover_focal, resize_for_print, resize_image, resize_within, rotate, rotate_image, smart_crop, split_tiles};
+pub use adjustments::{PreviewResult, adjust_image, adjust_preview, auto_color_cast_removal, auto_enhance, black_and_white, black_and_white_preset, match_histogram, tonal_mask};
+pub use alpha::{add_drop_shadow, composite_on_checkerboard, flatten_alpha, overlay_watermark, remove_alpha, replace_color, set_alpha_from_mask, to_straight_alpha};
+pub use analysis::{AnimationHash, ClippingStats, DiffResult, FormatRecommendation, Sharpness, animation_hash, clipping_stats, diff_image, grid_average_colors, recommend_format, sharpness_score};
+pub use filters::{RegionPatch, adaptive_threshold, apply_clarity, apply_kernel, bilateral_filter, blur_image, blur_region, blur_region_patch, bokeh_blur, emboss_image, map_pixels, preprocess_for_ocr, remove_red_eye};
+pub use palette::map_to_palette;
+pub use lut::{apply_cube_lut, apply_lut, build_lut, export_cube_lut};
+pub use animation::encode_animation;
+pub use encode::{EncodedVariant, convert_to_jpeg, convert_to_png, encode_variants, generate_favicon, set_default_output_format, tiny_placeholder};
+pub use jpeg::{crop_jpeg_lossless, estimate_jpeg_quality, read_jpeg_comment, rotate_jpeg_lossless, set_jpeg_comment};
+pub use resolution::{Resolution, read_dpi, set_dpi};
+pub use generate::{generate_gradient, generate_solid};
+pub use text::draw_text;
+pub use raw::{PartialImage, RawImage, decode_partial, decode_to_rgba, encode_from_rgba};
+pub use handle::ImageHandle;
+pub use pipeline::{PipelineResult, try_or_original};
 
 #[wasm_bindgen]
 pub fn init_logging() {
@@ -0,0 +1,304 @@
+use wasm_bindgen::prelude::*;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::io::Cursor;
+
+use crate::adjustments::{Adjustments, BrightnessMode, SaturationModel};
+use crate::common::decode_image;
+use crate::parallel::for_each_pixel;
+
+/// Lattice points per axis accepted for 3D LUTs
+const LUT_SIZES: std::ops::RangeInclusive<u32> = 2..=65;
+
+/// Lattice cell of each 8-bit channel value: lower index, upper index and weight of the upper one
+fn lattice_positions(size: usize) -> [(usize, usize, f32); 256] {
+    std::array::from_fn(|v| {
+        let pos = v as f32 * (size - 1) as f32 / 255.0;
+        let lower = (pos as usize).min(size - 1);
+        (lower, (lower + 1).min(size - 1), pos - lower as f32)
+    })
+}
+
+/// Map every pixel through a 3D LUT with trilinear interpolation. `lut` holds
+/// `size³` RGB entries scaled to 0..255, red varying fastest, then green, then blue.
+/// Alpha is kept.
+fn apply_lattice(rgba: &mut RgbaImage, lut: &[f32], size: usize) {
+    let positions = lattice_positions(size);
+    let entry = |r: usize, g: usize, b: usize| &lut[((b * size + g) * size + r) * 3..][..3];
+
+    for_each_pixel(rgba, |pixel| {
+        let [(r0, r1, fr), (g0, g1, fg), (b0, b1, fb)] =
+            [0, 1, 2].map(|c| positions[pixel.0[c] as usize]);
+        for c in 0..3 {
+            let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+            let along_r = |g: usize, b: usize| lerp(entry(r0, g, b)[c], entry(r1, g, b)[c], fr);
+            let along_g = |b: usize| lerp(along_r(g0, b), along_r(g1, b), fg);
+            let value = lerp(along_g(b0), along_g(b1), fb);
+            pixel.0[c] = value.round().clamp(0.0, 255.0) as u8;
+        }
+    });
+}
+
+/// Bake a set of `adjust_image` parameters into a `size`x`size`x`size` 3D LUT
+/// (17 or 33 are typical; 2..=65 are accepted). The result has `size³ * 3` bytes:
+/// RGB entries with red varying fastest, then green, then blue, as in .cube files.
+/// Apply it with `apply_lut`, which is much faster than re-running the adjustment
+/// pipeline for every image of a batch.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn build_lut(
+    brightness: i32,
+    contrast_val: f32,
+    saturation: f32,
+    hue: i32,
+    exposure: f32,
+    gamma: f32,
+    shadows: f32,
+    highlights: f32,
+    vibrance: f32,
+    saturation_model: &str,
+    brightness_mode: &str,
+    size: u32,
+) -> Result<Vec<u8>, String> {
+    if !LUT_SIZES.contains(&size) {
+        return Err(format!("LUT size must be between 2 and 65 (got {})", size));
+    }
+
+    let adjustments = Adjustments {
+        brightness,
+        brightness_mode: BrightnessMode::parse(brightness_mode)?,
+        contrast: contrast_val,
+        saturation,
+        saturation_model: SaturationModel::parse(saturation_model)?,
+        hue,
+        exposure,
+        gamma,
+        shadows,
+        highlights,
+        vibrance,
+    };
+    adjustments.validate()?;
+
+    // Every adjustment works per pixel, so running the lattice colors through the
+    // pipeline as one image samples it exactly
+    let level = |i: u32| (i as f32 * 255.0 / (size - 1) as f32).round() as u8;
+    let lattice = RgbaImage::from_fn(size, size * size, |r, row| {
+        Rgba([level(r), level(row % size), level(row / size), 255])
+    });
+    let adjusted = adjustments.apply(lattice);
+
+    Ok(adjusted.pixels().flat_map(|p| [p.0[0], p.0[1], p.0[2]]).collect())
+}
+
+fn check_lut(lut: &[u8], size: u32) -> Result<(), String> {
+    if !LUT_SIZES.contains(&size) {
+        return Err(format!("LUT size must be between 2 and 65 (got {})", size));
+    }
+    let expected = (size * size * size * 3) as usize;
+    if lut.len() != expected {
+        return Err(format!("LUT of size {} needs {} bytes, got {}", size, expected, lut.len()));
+    }
+    Ok(())
+}
+
+/// Apply a 3D LUT from `build_lut` (`size³ * 3` bytes, red varying fastest) with
+/// trilinear interpolation. Alpha is kept.
+#[wasm_bindgen]
+pub fn apply_lut(data: &[u8], lut: &[u8], size: u32) -> Result<Vec<u8>, String> {
+    check_lut(lut, size)?;
+
+    let lut: Vec<f32> = lut.iter().map(|&v| v as f32).collect();
+    let decoded = decode_image(data)?;
+    let mut rgba = decoded.img.to_rgba8();
+    apply_lattice(&mut rgba, &lut, size as usize);
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Parse the text of an Adobe .cube 3D LUT into lattice entries scaled to 0..255
+fn parse_cube(text: &str) -> Result<(Vec<f32>, usize), String> {
+    let mut size = None;
+    let mut entries = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let keyword = fields.next().unwrap_or_default();
+        let error = |what: &str| format!("Invalid .cube file, line {}: {}", number + 1, what);
+        match keyword {
+            "TITLE" => {}
+            "LUT_3D_SIZE" => {
+                let value = fields.next().and_then(|v| v.parse::<u32>().ok())
+                    .filter(|v| LUT_SIZES.contains(v))
+                    .ok_or_else(|| error("LUT_3D_SIZE must be between 2 and 65"))?;
+                size = Some(value as usize);
+            }
+            "LUT_1D_SIZE" => return Err("1D .cube LUTs are not supported, only LUT_3D_SIZE".to_string()),
+            "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                let expected = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                if !fields.all(|v| v.parse::<f32>().ok() == Some(expected)) {
+                    return Err(error("only the default 0..1 domain is supported"));
+                }
+            }
+            _ => {
+                let values = line.split_whitespace().map(|v| v.parse::<f32>()).collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| error(&format!("unexpected \"{}\"", line)))?;
+                if values.len() != 3 {
+                    return Err(error("expected three values per entry"));
+                }
+                if let Some(v) = values.iter().find(|v| !(0.0..=1.0).contains(*v)) {
+                    return Err(error(&format!("value {} is outside 0..1", v)));
+                }
+                entries.extend(values.iter().map(|v| v * 255.0));
+            }
+        }
+    }
+
+    let size = size.ok_or("Invalid .cube file: missing LUT_3D_SIZE")?;
+    if entries.len() != size * size * size * 3 {
+        return Err(format!(
+            "Invalid .cube file: LUT_3D_SIZE {} needs {} entries, found {}",
+            size, size * size * size, entries.len() / 3
+        ));
+    }
+    Ok((entries, size))
+}
+
+/// Apply an Adobe .cube 3D LUT (as exported by DaVinci Resolve, Lightroom and most
+/// editors) with trilinear interpolation. Only 3D LUTs over the default 0..1 domain
+/// are supported. Alpha is kept.
+#[wasm_bindgen]
+pub fn apply_cube_lut(data: &[u8], cube_text: &str) -> Result<Vec<u8>, String> {
+    let (lut, size) = parse_cube(cube_text)?;
+
+    let decoded = decode_image(data)?;
+    let mut rgba = decoded.img.to_rgba8();
+    apply_lattice(&mut rgba, &lut, size);
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Write a LUT from `build_lut` as .cube text so other editors can use the look
+#[wasm_bindgen]
+pub fn export_cube_lut(lut: &[u8], size: u32, title: &str) -> Result<String, String> {
+    check_lut(lut, size)?;
+
+    let mut text = String::new();
+    if !title.is_empty() {
+        text += &format!("TITLE \"{}\"\n", title.replace('"', "'"));
+    }
+    text += &format!("LUT_3D_SIZE {}\n", size);
+    for entry in lut.chunks(3) {
+        let [r, g, b] = [0, 1, 2].map(|c| entry[c] as f32 / 255.0);
+        text += &format!("{:.6} {:.6} {:.6}\n", r, g, b);
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+
+    /// Every channel value in steps of 5, with varying alpha
+    fn swatch() -> RgbaImage {
+        RgbaImage::from_fn(52, 52, |x, y| Rgba([(x * 5) as u8, (y * 5) as u8, ((x + y) * 5 / 2) as u8, 255 - x as u8]))
+    }
+
+    fn max_difference(a: &RgbaImage, b: &RgbaImage) -> u8 {
+        a.as_raw().iter().zip(b.as_raw()).map(|(x, y)| x.abs_diff(*y)).max().unwrap()
+    }
+
+    fn neutral_lut(size: u32) -> Vec<u8> {
+        build_lut(0, 0.0, 1.0, 0, 0.0, 1.0, 0.0, 0.0, 0.0, "", "", size).unwrap()
+    }
+
+    #[test]
+    fn neutral_lut_is_the_identity() {
+        let data = encode_png_fixture(swatch());
+        for size in [2, 17, 33] {
+            let lut = neutral_lut(size);
+            let applied = image::load_from_memory(&apply_lut(&data, &lut, size).unwrap()).unwrap().to_rgba8();
+            assert!(max_difference(&applied, &swatch()) <= 1, "size {}", size);
+        }
+    }
+
+    #[test]
+    fn lut_entries_are_ordered_red_fastest() {
+        // Size 2 lattice that swaps red and blue: entry (r, g, b) holds (b, g, r)
+        let lut: Vec<u8> = (0..8)
+            .flat_map(|i| {
+                let [r, g, b] = [i & 1, (i >> 1) & 1, i >> 2].map(|bit| bit as u8 * 255);
+                [b, g, r]
+            })
+            .collect();
+        let data = encode_png_fixture(RgbaImage::from_pixel(1, 1, Rgba([255, 64, 0, 200])));
+        let applied = image::load_from_memory(&apply_lut(&data, &lut, 2).unwrap()).unwrap().to_rgba8();
+        assert_eq!(applied.get_pixel(0, 0).0, [0, 64, 255, 200]);
+    }
+
+    #[test]
+    fn lut_length_must_match_its_size() {
+        let data = encode_png_fixture(swatch());
+        let lut = neutral_lut(17);
+        assert!(apply_lut(&data, &lut[..lut.len() - 3], 17).unwrap_err().contains("needs"));
+        assert!(apply_lut(&data, &lut, 16).is_err());
+        assert!(apply_lut(&data, &lut, 1).is_err());
+        assert!(export_cube_lut(&lut, 33, "").is_err());
+    }
+
+    #[test]
+    fn exported_cube_round_trips_to_the_identity() {
+        let data = encode_png_fixture(swatch());
+        let cube = export_cube_lut(&neutral_lut(17), 17, "Neutral \"look\"").unwrap();
+        assert!(cube.starts_with("TITLE \"Neutral 'look'\"\nLUT_3D_SIZE 17\n"));
+
+        let applied = image::load_from_memory(&apply_cube_lut(&data, &cube).unwrap()).unwrap().to_rgba8();
+        assert!(max_difference(&applied, &swatch()) <= 1);
+    }
+
+    #[test]
+    fn cube_entry_count_must_match_the_size() {
+        let cube = export_cube_lut(&neutral_lut(2), 2, "").unwrap();
+        let short = cube.lines().take(cube.lines().count() - 1).collect::<Vec<_>>().join("\n");
+        assert!(parse_cube(&short).unwrap_err().contains("needs 8 entries, found 7"));
+
+        let long = format!("{}0 0 0\n", cube);
+        assert!(parse_cube(&long).unwrap_err().contains("found 9"));
+
+        assert!(parse_cube("0 0 0\n").unwrap_err().contains("missing LUT_3D_SIZE"));
+        assert!(parse_cube("LUT_3D_SIZE 1\n").is_err());
+        assert!(parse_cube("LUT_3D_SIZE 66\n").is_err());
+    }
+
+    #[test]
+    fn cube_values_must_be_within_the_domain() {
+        let cube = export_cube_lut(&neutral_lut(2), 2, "").unwrap();
+        let out_of_range = cube.replacen("1.000000 1.000000 1.000000", "1.000000 1.200000 1.000000", 1);
+        assert!(parse_cube(&out_of_range).unwrap_err().contains("value 1.2 is outside 0..1"));
+
+        let negative = cube.replacen("0.000000 0.000000 0.000000", "-0.100000 0.000000 0.000000", 1);
+        assert!(parse_cube(&negative).unwrap_err().contains("outside 0..1"));
+
+        assert!(parse_cube(&format!("DOMAIN_MAX 2 2 2\n{}", cube)).is_err());
+        assert!(parse_cube(&cube.replacen("0.000000 0.000000 0.000000", "0 0", 1)).unwrap_err().contains("three values"));
+    }
+
+    #[test]
+    fn one_dimensional_cube_files_are_rejected() {
+        let cube = "LUT_1D_SIZE 2\n0 0 0\n1 1 1\n";
+        assert!(parse_cube(cube).unwrap_err().contains("1D .cube LUTs are not supported"));
+        let data = encode_png_fixture(swatch());
+        assert!(apply_cube_lut(&data, cube).is_err());
+    }
+}
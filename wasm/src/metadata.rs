@@ -1,14 +1,20 @@
 use wasm_bindgen::prelude::*;
+use image::{ImageFormat, ImageReader};
 use std::io::Cursor;
 use exif::{In, Tag};
 
-use crate::common::decode_image;
+use crate::common::{decode_for_size, decode_image};
+use crate::jpeg::{jpeg_color_space, jpeg_segments};
+use crate::resolution::detect_resolution;
+use crate::transforms::resize_with_filter;
 
 struct ExifData {
     orientation: Option<u32>,
     camera_make: Option<String>,
     camera_model: Option<String>,
     date_taken: Option<String>,
+    date_digitized: Option<String>,
+    date_modified: Option<String>,
     iso: Option<u32>,
     aperture: Option<String>,
     shutter_speed: Option<String>,
@@ -17,6 +23,13 @@ struct ExifData {
     lens_model: Option<String>,
     software: Option<String>,
     exposure_program: Option<String>,
+    exposure_bias: Option<String>,
+    metering_mode: Option<String>,
+    white_balance: Option<String>,
+    focal_length_35mm: Option<u32>,
+    lens_spec: Option<String>,
+    image_unique_id: Option<String>,
+    body_serial: Option<String>,
 }
 
 fn get_exif_string(exif: &exif::Exif, tag: Tag) -> Option<String> {
@@ -56,12 +69,112 @@ fn format_exposure_program(value: u32) -> String {
     }
 }
 
+fn format_metering_mode(value: u32) -> String {
+    match value {
+        0 => "Unknown".to_string(),
+        1 => "Average".to_string(),
+        2 => "Center-weighted".to_string(),
+        3 => "Spot".to_string(),
+        4 => "Multi-spot".to_string(),
+        5 => "Multi-segment".to_string(),
+        6 => "Partial".to_string(),
+        255 => "Other".to_string(),
+        _ => format!("Unknown ({})", value),
+    }
+}
+
+fn format_white_balance(value: u32) -> String {
+    match value {
+        0 => "Auto".to_string(),
+        1 => "Manual".to_string(),
+        _ => format!("Unknown ({})", value),
+    }
+}
+
+fn format_exposure_bias(ev: f64) -> String {
+    if ev.abs() < 0.005 {
+        "0 EV".to_string()
+    } else {
+        format!("{:+.2} EV", ev)
+    }
+}
+
+/// Format a focal length or f-number without trailing zeros ("24", "2.8")
+fn format_decimal(value: f64) -> String {
+    let text = format!("{:.1}", value);
+    text.strip_suffix(".0").unwrap_or(&text).to_string()
+}
+
+/// Format LensSpecification (min/max focal length, f-number at each end) as e.g.
+/// "24-70mm f/2.8" or "18-55mm f/3.5-5.6". Unknown entries are stored as 0/0.
+fn format_lens_spec(values: &[exif::Rational]) -> Option<String> {
+    let value = |i: usize| values.get(i).filter(|r| r.denom != 0 && r.num != 0).map(|r| r.to_f64());
+
+    let focal = match (value(0), value(1)) {
+        (Some(min), Some(max)) if (max - min).abs() > 0.05 => {
+            format!("{}-{}mm", format_decimal(min), format_decimal(max))
+        }
+        (Some(length), _) | (None, Some(length)) => format!("{}mm", format_decimal(length)),
+        (None, None) => return None,
+    };
+
+    let aperture = match (value(2), value(3)) {
+        (Some(wide), Some(tele)) if (tele - wide).abs() > 0.05 => {
+            format!(" f/{}-{}", format_decimal(wide), format_decimal(tele))
+        }
+        (Some(f), _) | (None, Some(f)) => format!(" f/{}", format_decimal(f)),
+        (None, None) => String::new(),
+    };
+
+    Some(focal + &aperture)
+}
+
+/// EXIF orientation tag (1-8), if present
+pub(crate) fn read_orientation(data: &[u8]) -> Option<u32> {
+    let exif_reader = exif::Reader::new().read_from_container(&mut Cursor::new(data)).ok()?;
+    get_exif_uint(&exif_reader, Tag::Orientation)
+}
+
+/// Camera roll in degrees from the XMP `GPano:PoseRollDegrees` property (written by
+/// phone cameras and panorama apps), in either attribute or element form. EXIF itself
+/// has no roll tag, `CameraElevationAngle` only records pitch.
+pub(crate) fn read_xmp_roll(data: &[u8]) -> Option<f32> {
+    const PROPERTY: &[u8] = b"GPano:PoseRollDegrees";
+    let start = data.windows(PROPERTY.len()).position(|w| w == PROPERTY)? + PROPERTY.len();
+
+    let rest = data.get(start..(start + 64).min(data.len()))?;
+    let value_start = rest.iter().position(|&b| b == b'"' || b == b'\'' || b == b'>')? + 1;
+    let value = &rest[value_start..];
+    let value_end = value.iter().position(|&b| b == b'"' || b == b'\'' || b == b'<')?;
+
+    std::str::from_utf8(&value[..value_end]).ok()?.trim().parse::<f32>().ok()
+        .filter(|roll| roll.is_finite())
+}
+
+/// Whether the alpha channel is stored premultiplied (associated). Only TIFF declares
+/// this, through ExtraSamples = 1; OpenEXR is premultiplied by definition. PNG, GIF,
+/// WebP and AVIF always store straight alpha, and formats that are not decoded here
+/// (PSD) or do not record it are reported as straight.
+pub(crate) fn read_premultiplied_alpha(data: &[u8], format: ImageFormat) -> bool {
+    const EXTRA_SAMPLES: Tag = Tag(exif::Context::Tiff, 338);
+
+    match format {
+        ImageFormat::OpenExr => true,
+        ImageFormat::Tiff => exif::Reader::new().read_raw(data.to_vec()).ok()
+            .and_then(|tiff| tiff.get_field(EXTRA_SAMPLES, In::PRIMARY).map(|f| f.value.clone()))
+            .is_some_and(|value| value.iter_uint().is_some_and(|mut kinds| kinds.any(|kind| kind == 1))),
+        _ => false,
+    }
+}
+
 fn extract_exif_data(data: &[u8]) -> ExifData {
     let mut exif_data = ExifData {
         orientation: None,
         camera_make: None,
         camera_model: None,
         date_taken: None,
+        date_digitized: None,
+        date_modified: None,
         iso: None,
         aperture: None,
         shutter_speed: None,
@@ -70,6 +183,13 @@ fn extract_exif_data(data: &[u8]) -> ExifData {
         lens_model: None,
         software: None,
         exposure_program: None,
+        exposure_bias: None,
+        metering_mode: None,
+        white_balance: None,
+        focal_length_35mm: None,
+        lens_spec: None,
+        image_unique_id: None,
+        body_serial: None,
     };
 
     let exif_reader = match exif::Reader::new().read_from_container(&mut Cursor::new(data)) {
@@ -77,15 +197,19 @@ fn extract_exif_data(data: &[u8]) -> ExifData {
         Err(_) => return exif_data,
     };
 
-    if let Some(field) = exif_reader.get_field(Tag::Orientation, In::PRIMARY) {
-        if let Some(val) = field.value.get_uint(0) {
-            exif_data.orientation = Some(val);
-        }
-    }
+    exif_data.orientation = get_exif_uint(&exif_reader, Tag::Orientation);
 
     exif_data.camera_make = get_exif_string(&exif_reader, Tag::Make);
     exif_data.camera_model = get_exif_string(&exif_reader, Tag::Model);
-    exif_data.date_taken = get_exif_string(&exif_reader, Tag::DateTimeOriginal);
+    // Date taken - append SubSecTimeOriginal when present so burst shots sort stably
+    exif_data.date_taken = get_exif_string(&exif_reader, Tag::DateTimeOriginal).map(|date| {
+        match get_exif_string(&exif_reader, Tag::SubSecTimeOriginal) {
+            Some(subsec) if !subsec.trim().is_empty() => format!("{}.{}", date, subsec.trim()),
+            _ => date,
+        }
+    });
+    exif_data.date_digitized = get_exif_string(&exif_reader, Tag::DateTimeDigitized);
+    exif_data.date_modified = get_exif_string(&exif_reader, Tag::DateTime);
 
     // ISO - try PhotographicSensitivity first, fall back to ISOSpeedRatings
     exif_data.iso = get_exif_uint(&exif_reader, Tag::PhotographicSensitivity)
@@ -122,22 +246,236 @@ fn extract_exif_data(data: &[u8]) -> ExifData {
         exif_data.exposure_program = Some(format_exposure_program(program_val));
     }
 
+    // Exposure bias - signed rational in EV
+    if let Some(field) = exif_reader.get_field(Tag::ExposureBiasValue, In::PRIMARY)
+        && let exif::Value::SRational(ref values) = field.value
+        && let Some(bias) = values.first()
+        && bias.denom != 0
+    {
+        exif_data.exposure_bias = Some(format_exposure_bias(bias.to_f64()));
+    }
+
+    // Metering mode - map to human-readable
+    if let Some(mode_val) = get_exif_uint(&exif_reader, Tag::MeteringMode) {
+        exif_data.metering_mode = Some(format_metering_mode(mode_val));
+    }
+
+    // White balance - map to human-readable
+    if let Some(wb_val) = get_exif_uint(&exif_reader, Tag::WhiteBalance) {
+        exif_data.white_balance = Some(format_white_balance(wb_val));
+    }
+
+    // 35mm-equivalent focal length in mm (0 means unknown)
+    exif_data.focal_length_35mm = get_exif_uint(&exif_reader, Tag::FocalLengthIn35mmFilm)
+        .filter(|&mm| mm > 0);
+
+    // Lens specification - four rationals describing the focal and aperture range
+    if let Some(field) = exif_reader.get_field(Tag::LensSpecification, In::PRIMARY)
+        && let exif::Value::Rational(ref values) = field.value
+    {
+        exif_data.lens_spec = format_lens_spec(values);
+    }
+
+    // Identifiers for linking edited exports back to the original capture
+    exif_data.image_unique_id = get_exif_string(&exif_reader, Tag::ImageUniqueID);
+    exif_data.body_serial = get_exif_string(&exif_reader, Tag::BodySerialNumber);
+
     exif_data
 }
 
+#[derive(Default)]
+struct IptcData {
+    caption: Option<String>,
+    keywords: Vec<String>,
+    byline: Option<String>,
+    credit: Option<String>,
+    copyright: Option<String>,
+}
+
+/// Photoshop image resources from the JPEG APP13 segments, joined in file order
+/// (large resource blocks are split across several segments)
+fn photoshop_resources(data: &[u8]) -> Vec<u8> {
+    const HEADER: &[u8] = b"Photoshop 3.0\0";
+    let Ok(segments) = jpeg_segments(data) else {
+        return Vec::new();
+    };
+    segments.iter()
+        .filter(|s| s.marker == 0xED)
+        .filter_map(|s| data[s.payload..s.end].strip_prefix(HEADER))
+        .flatten()
+        .copied()
+        .collect()
+}
+
+/// The IPTC-IIM block (image resource 0x0404) among Photoshop image resources
+fn iptc_block(resources: &[u8]) -> Option<&[u8]> {
+    let mut pos = 0;
+    while resources.get(pos..pos + 4)? == b"8BIM" {
+        let id = u16::from_be_bytes(resources.get(pos + 4..pos + 6)?.try_into().ok()?);
+        // Pascal string name, padded to an even length including its length byte
+        let name_len = *resources.get(pos + 6)? as usize;
+        let size_at = pos + 6 + ((name_len + 2) & !1);
+        let size = u32::from_be_bytes(resources.get(size_at..size_at + 4)?.try_into().ok()?) as usize;
+        let block = resources.get(size_at + 4..(size_at + 4).checked_add(size)?)?;
+        if id == 0x0404 {
+            return Some(block);
+        }
+        pos = size_at + 4 + size + (size & 1);
+    }
+    None
+}
+
+/// IIM datasets as (record, dataset, value), stopping at the first malformed one
+fn iptc_datasets(block: &[u8]) -> Vec<(u8, u8, &[u8])> {
+    let mut datasets = Vec::new();
+    let mut pos = 0;
+    while let Some(&[0x1C, record, dataset, hi, lo]) = block.get(pos..pos + 5) {
+        let mut start = pos + 5;
+        let mut size = u16::from_be_bytes([hi, lo]) as usize;
+        // Extended datasets store the length of the size field in the low 15 bits
+        if size & 0x8000 != 0 {
+            let Some(bytes) = block.get(start..start + (size & 0x7FFF)) else {
+                break;
+            };
+            start += bytes.len();
+            size = bytes.iter().fold(0usize, |acc, &b| acc.saturating_mul(256) | b as usize);
+        }
+        let Some(value) = block.get(start..start.saturating_add(size)) else {
+            break;
+        };
+        datasets.push((record, dataset, value));
+        pos = start + size;
+    }
+    datasets
+}
+
+/// IIM text is UTF-8 in current files and Latin-1 in older ones
+fn iptc_text(value: &[u8]) -> Option<String> {
+    let text = match std::str::from_utf8(value) {
+        Ok(text) => text.to_string(),
+        Err(_) => value.iter().map(|&b| b as char).collect(),
+    };
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Caption, keywords and credits from the IPTC-IIM block of a JPEG's APP13 segment
+fn extract_iptc_data(data: &[u8]) -> IptcData {
+    let mut iptc = IptcData::default();
+    let resources = photoshop_resources(data);
+    let Some(block) = iptc_block(&resources) else {
+        return iptc;
+    };
+
+    for (record, dataset, value) in iptc_datasets(block) {
+        if record != 2 {
+            continue;
+        }
+        let Some(text) = iptc_text(value) else {
+            continue;
+        };
+        let field = match dataset {
+            25 => {
+                iptc.keywords.push(text);
+                continue;
+            }
+            80 => &mut iptc.byline,
+            110 => &mut iptc.credit,
+            116 => &mut iptc.copyright,
+            120 => &mut iptc.caption,
+            _ => continue,
+        };
+        field.get_or_insert(text);
+    }
+    iptc
+}
+
+/// Count GIF frames without LZW-decoding them
+fn gif_frame_count(data: &[u8]) -> Option<u32> {
+    let mut options = gif::DecodeOptions::new();
+    options.skip_frame_decoding(true);
+    let mut decoder = options.read_info(Cursor::new(data)).ok()?;
+
+    let mut frames = 0;
+    while let Ok(Some(_)) = decoder.read_next_frame() {
+        frames += 1;
+    }
+    Some(frames)
+}
+
+/// Frame count of an animated PNG from its acTL chunk, or 1 for a still PNG
+fn png_frame_count(data: &[u8]) -> Option<u32> {
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+        match &data[pos + 4..pos + 8] {
+            b"acTL" => {
+                let frames = data.get(pos + 8..pos + 12)?;
+                return Some(u32::from_be_bytes(frames.try_into().ok()?));
+            }
+            b"IDAT" => return Some(1),
+            // The length is untrusted and would wrap a 32-bit usize
+            _ => pos = length.checked_add(12).and_then(|n| pos.checked_add(n))?,
+        }
+    }
+    None
+}
+
+/// Number of ANMF chunks in an animated WebP, or 1 for a still WebP
+fn webp_frame_count(data: &[u8]) -> Option<u32> {
+    let mut pos = 12;
+    let mut frames = 0;
+    while pos + 8 <= data.len() {
+        let length = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().ok()?) as usize;
+        if &data[pos..pos + 4] == b"ANMF" {
+            frames += 1;
+        }
+        // Chunks are padded to an even size; stop at a length that would wrap
+        match length.checked_add(8 + (length & 1)).and_then(|n| pos.checked_add(n)) {
+            Some(next) => pos = next,
+            None => break,
+        }
+    }
+    Some(frames.max(1))
+}
+
+/// Frame count for formats that can be animated, `None` for the rest
+fn frame_count(data: &[u8], format: ImageFormat) -> Option<u32> {
+    match format {
+        ImageFormat::Gif => gif_frame_count(data),
+        ImageFormat::Png => png_frame_count(data),
+        ImageFormat::WebP => webp_frame_count(data),
+        _ => None,
+    }
+}
+
 #[wasm_bindgen(getter_with_clone)]
 pub struct ImageMetadata {
     pub format: String,
+    /// MIME type of the format, e.g. "image/jpeg"
+    pub mime_type: String,
+    /// Usual lowercase file extension, e.g. "jpg"
+    pub extension: String,
     pub width: u32,
     pub height: u32,
     pub color_type: String,
+    /// JPEG only: color space of the stored data ("gray", "ycbcr", "rgb", "cmyk" or
+    /// "ycck"). CMYK and YCCK files are converted to RGB on decode.
+    pub color_space: Option<String>,
     pub bits_per_pixel: u16,
+    pub bit_depth_per_channel: u8,
+    pub channel_count: u8,
     pub has_alpha: bool,
+    /// Alpha is stored premultiplied (see `to_straight_alpha`); only TIFF and OpenEXR
+    /// can report true
+    pub premultiplied_alpha: bool,
     pub aspect_ratio: f64,
     pub exif_orientation: Option<u32>,
     pub camera_make: Option<String>,
     pub camera_model: Option<String>,
     pub date_taken: Option<String>,
+    pub date_digitized: Option<String>,
+    pub date_modified: Option<String>,
     pub iso: Option<u32>,
     pub aperture: Option<String>,
     pub shutter_speed: Option<String>,
@@ -146,6 +484,34 @@ pub struct ImageMetadata {
     pub lens_model: Option<String>,
     pub software: Option<String>,
     pub exposure_program: Option<String>,
+    pub exposure_bias: Option<String>,
+    pub metering_mode: Option<String>,
+    pub white_balance: Option<String>,
+    /// 35mm-equivalent focal length in millimeters
+    pub focal_length_35mm: Option<u32>,
+    /// Lens focal and aperture range, e.g. "24-70mm f/2.8"
+    pub lens_spec: Option<String>,
+    /// EXIF ImageUniqueID, for matching RAW+JPEG pairs and edits to their original
+    pub image_unique_id: Option<String>,
+    /// Camera body serial number
+    pub body_serial: Option<String>,
+    /// Resolution from JFIF density, PNG pHYs or EXIF, in dots per inch
+    pub dpi_x: Option<f32>,
+    pub dpi_y: Option<f32>,
+    /// Unit the file declares its resolution in ("inch", "cm" or "meter"); `dpi_x`
+    /// and `dpi_y` are converted to inches regardless
+    pub resolution_unit: Option<String>,
+    /// IPTC Caption/Abstract
+    pub caption: Option<String>,
+    /// IPTC Keywords, empty when there are none
+    pub keywords: Vec<String>,
+    /// IPTC By-line (author)
+    pub byline: Option<String>,
+    pub credit: Option<String>,
+    pub copyright: Option<String>,
+    pub is_animated: bool,
+    /// Number of frames for formats that can be animated (GIF, PNG, WebP)
+    pub frame_count: Option<u32>,
 }
 
 #[wasm_bindgen]
@@ -154,19 +520,30 @@ pub fn read_image_metadata(data: &[u8]) -> Result<ImageMetadata, String> {
     let color = decoded.img.color();
 
     let exif = extract_exif_data(data);
+    let iptc = extract_iptc_data(data);
+    let resolution = detect_resolution(data);
+    let frames = frame_count(data, decoded.format);
 
     Ok(ImageMetadata {
+        mime_type: decoded.mime_type().to_string(),
+        extension: decoded.extension().to_string(),
         format: decoded.format_name,
         width: decoded.img.width(),
         height: decoded.img.height(),
         color_type: format!("{:?}", color),
+        color_space: jpeg_color_space(data).map(str::to_string),
         bits_per_pixel: color.bits_per_pixel(),
+        bit_depth_per_channel: (color.bits_per_pixel() / color.channel_count() as u16) as u8,
+        channel_count: color.channel_count(),
         has_alpha: color.has_alpha(),
+        premultiplied_alpha: color.has_alpha() && read_premultiplied_alpha(data, decoded.format),
         aspect_ratio: decoded.img.width() as f64 / decoded.img.height() as f64,
         exif_orientation: exif.orientation,
         camera_make: exif.camera_make,
         camera_model: exif.camera_model,
         date_taken: exif.date_taken,
+        date_digitized: exif.date_digitized,
+        date_modified: exif.date_modified,
         iso: exif.iso,
         aperture: exif.aperture,
         shutter_speed: exif.shutter_speed,
@@ -175,5 +552,137 @@ pub fn read_image_metadata(data: &[u8]) -> Result<ImageMetadata, String> {
         lens_model: exif.lens_model,
         software: exif.software,
         exposure_program: exif.exposure_program,
+        exposure_bias: exif.exposure_bias,
+        metering_mode: exif.metering_mode,
+        white_balance: exif.white_balance,
+        focal_length_35mm: exif.focal_length_35mm,
+        lens_spec: exif.lens_spec,
+        image_unique_id: exif.image_unique_id,
+        body_serial: exif.body_serial,
+        dpi_x: resolution.map(|(dpi, _)| dpi.x_dpi),
+        dpi_y: resolution.map(|(dpi, _)| dpi.y_dpi),
+        resolution_unit: resolution.map(|(_, unit)| unit.to_string()),
+        caption: iptc.caption,
+        keywords: iptc.keywords,
+        byline: iptc.byline,
+        credit: iptc.credit,
+        copyright: iptc.copyright,
+        is_animated: frames.is_some_and(|n| n > 1),
+        frame_count: frames,
     })
 }
+
+/// "portrait", "landscape" or "square" for the image as displayed, i.e. with width and
+/// height swapped when the EXIF orientation rotates it by 90 degrees (5-8). Only the
+/// header is read unless the format needs a full decode to know its size.
+#[wasm_bindgen]
+pub fn orientation_category(data: &[u8]) -> Result<String, String> {
+    let header = ImageReader::new(Cursor::new(data)).with_guessed_format().ok()
+        .and_then(|reader| reader.into_dimensions().ok());
+    let (width, height) = match header {
+        Some(dimensions) => dimensions,
+        None => {
+            let decoded = decode_image(data)?;
+            (decoded.img.width(), decoded.img.height())
+        }
+    };
+
+    let (width, height) = match read_orientation(data) {
+        Some(5..=8) => (height, width),
+        _ => (width, height),
+    };
+    let category = match width.cmp(&height) {
+        std::cmp::Ordering::Greater => "landscape",
+        std::cmp::Ordering::Less => "portrait",
+        std::cmp::Ordering::Equal => "square",
+    };
+    Ok(category.to_string())
+}
+
+/// A small preview image and where it came from
+#[wasm_bindgen(getter_with_clone)]
+pub struct Thumbnail {
+    pub data: Vec<u8>,
+    /// True for the JPEG embedded in EXIF, false for a thumbnail generated from the image
+    pub from_exif: bool,
+}
+
+/// The JPEG thumbnail stored in EXIF IFD1, if any
+fn embedded_thumbnail(data: &[u8]) -> Option<Vec<u8>> {
+    let exif_reader = exif::Reader::new().read_from_container(&mut Cursor::new(data)).ok()?;
+    let field = |tag| exif_reader.get_field(tag, In::THUMBNAIL).and_then(|f| f.value.get_uint(0));
+    let offset = field(Tag::JPEGInterchangeFormat)? as usize;
+    let length = field(Tag::JPEGInterchangeFormatLength)? as usize;
+
+    let thumbnail = exif_reader.buf().get(offset..offset.checked_add(length)?)?;
+    thumbnail.starts_with(&[0xFF, 0xD8]).then(|| thumbnail.to_vec())
+}
+
+/// Return the thumbnail embedded in EXIF without decoding the image. When there is
+/// none and `fallback_max_edge` is non-zero, a thumbnail fitting in that size is
+/// generated instead with `filter` (see `resize_image`), decoding large JPEGs at
+/// reduced scale; with 0 a missing thumbnail is an error.
+#[wasm_bindgen]
+pub fn extract_exif_thumbnail(data: &[u8], fallback_max_edge: u32, filter: &str) -> Result<Thumbnail, String> {
+    if let Some(thumbnail) = embedded_thumbnail(data) {
+        return Ok(Thumbnail { data: thumbnail, from_exif: true });
+    }
+    if fallback_max_edge == 0 {
+        return Err("Image has no EXIF thumbnail".to_string());
+    }
+
+    let decoded = decode_for_size(data, fallback_max_edge, fallback_max_edge)?;
+    let (width, height) = (decoded.img.width(), decoded.img.height());
+    let scale = (fallback_max_edge as f64 / width.max(height) as f64).min(1.0);
+    let thumb_w = ((width as f64 * scale).round() as u32).max(1);
+    let thumb_h = ((height as f64 * scale).round() as u32).max(1);
+
+    let img = if (thumb_w, thumb_h) == (width, height) {
+        decoded.img
+    } else {
+        resize_with_filter(&decoded.img, thumb_w, thumb_h, filter)
+    };
+
+    let mut output = Vec::new();
+    img.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode thumbnail: {}", e))?;
+
+    Ok(Thumbnail { data: output, from_exif: false })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn riff_chunk(fourcc: &[u8; 4], length: u32, payload: &[u8]) -> Vec<u8> {
+        let mut chunk = fourcc.to_vec();
+        chunk.extend_from_slice(&length.to_le_bytes());
+        chunk.extend_from_slice(payload);
+        chunk
+    }
+
+    #[test]
+    fn webp_frame_count_counts_anmf_chunks() {
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend(riff_chunk(b"VP8X", 10, &[0x02; 10]));
+        webp.extend(riff_chunk(b"ANMF", 3, &[0, 0, 0, 0]));
+        webp.extend(riff_chunk(b"ANMF", 2, &[0, 0]));
+        assert_eq!(webp_frame_count(&webp), Some(2));
+    }
+
+    #[test]
+    fn oversized_chunk_lengths_stop_the_scan() {
+        for length in [u32::MAX, 0xFFFF_FFF4, 0xFFFF_FFF7] {
+            let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+            webp.extend(riff_chunk(b"ANMF", 2, &[0, 0]));
+            webp.extend(riff_chunk(b"EXIF", length, &[0; 16]));
+            assert_eq!(webp_frame_count(&webp), Some(1));
+
+            let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+            png.extend_from_slice(&length.to_be_bytes());
+            png.extend_from_slice(b"tEXt");
+            png.extend_from_slice(&[0; 16]);
+            assert_eq!(png_frame_count(&png), None);
+        }
+    }
+}
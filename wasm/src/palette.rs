@@ -0,0 +1,108 @@
+use wasm_bindgen::prelude::*;
+use image::{DynamicImage, RgbaImage};
+use std::io::Cursor;
+
+use crate::common::{decode_image, parse_color};
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Dither {
+    None,
+    FloydSteinberg,
+}
+
+impl Dither {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "" | "none" => Ok(Dither::None),
+            "floyd_steinberg" => Ok(Dither::FloydSteinberg),
+            other => Err(format!("Unknown dither mode: {} (expected none or floyd_steinberg)", other)),
+        }
+    }
+}
+
+/// Index of the palette entry closest to `color` (squared RGB distance)
+pub(crate) fn nearest(palette: &[[f32; 3]], color: [f32; 3]) -> usize {
+    let distance = |p: &[f32; 3]| (0..3).map(|c| (p[c] - color[c]).powi(2)).sum::<f32>();
+    let mut best = 0;
+    for (i, entry) in palette.iter().enumerate().skip(1) {
+        if distance(entry) < distance(&palette[best]) {
+            best = i;
+        }
+    }
+    best
+}
+
+/// Palette index for every pixel, row-major. `lookup` picks the entry closest to a
+/// color; with Floyd-Steinberg the quantization error is diffused to the neighbors
+/// that are not mapped yet. Alpha is ignored.
+pub(crate) fn index_pixels(
+    rgba: &RgbaImage,
+    palette: &[[f32; 3]],
+    dither: Dither,
+    lookup: impl Fn([f32; 3]) -> usize,
+) -> Vec<usize> {
+    let (width, height) = (rgba.width() as usize, rgba.height() as usize);
+    let mut indices = Vec::with_capacity(width * height);
+
+    // Accumulated diffusion error for the current and the next row
+    let mut errors = vec![[0.0f32; 3]; width * 2];
+    for y in 0..height {
+        let (current, next) = errors.split_at_mut(width);
+        for x in 0..width {
+            let pixel = rgba.get_pixel(x as u32, y as u32);
+            let color: [f32; 3] = std::array::from_fn(|c| (pixel.0[c] as f32 + current[x][c]).clamp(0.0, 255.0));
+            let index = lookup(color);
+            indices.push(index);
+
+            if dither == Dither::FloydSteinberg {
+                let mapped = palette[index];
+                for c in 0..3 {
+                    let error = color[c] - mapped[c];
+                    if x + 1 < width {
+                        current[x + 1][c] += error * 7.0 / 16.0;
+                        next[x + 1][c] += error / 16.0;
+                    }
+                    if x > 0 {
+                        next[x - 1][c] += error * 3.0 / 16.0;
+                    }
+                    next[x][c] += error * 5.0 / 16.0;
+                }
+            }
+        }
+        current.copy_from_slice(next);
+        next.fill([0.0; 3]);
+    }
+
+    indices
+}
+
+/// Snap every pixel to the closest color of a fixed palette, e.g.
+/// `["#0F380F", "#306230", "#8BAC0F", "#9BBC0F"]` for a GameBoy look.
+/// `dither` is "none" or "floyd_steinberg" (error diffusion, which keeps gradients
+/// readable with few colors). Alpha is left untouched.
+#[wasm_bindgen]
+pub fn map_to_palette(data: &[u8], palette: Vec<String>, dither: &str) -> Result<Vec<u8>, String> {
+    if palette.is_empty() {
+        return Err("Palette must contain at least one color".to_string());
+    }
+    let palette = palette.iter()
+        .map(|c| parse_color(c).map(|p| [p.0[0] as f32, p.0[1] as f32, p.0[2] as f32]))
+        .collect::<Result<Vec<_>, _>>()?;
+    let dither = Dither::parse(dither)?;
+
+    let decoded = decode_image(data)?;
+    let mut rgba: RgbaImage = decoded.img.to_rgba8();
+
+    let indices = index_pixels(&rgba, &palette, dither, |color| nearest(&palette, color));
+    for (pixel, index) in rgba.pixels_mut().zip(indices) {
+        for (channel, value) in pixel.0.iter_mut().zip(palette[index]) {
+            *channel = value as u8;
+        }
+    }
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(output)
+}
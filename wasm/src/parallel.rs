@@ -0,0 +1,41 @@
+//! Row-parallel loops for the heavy per-pixel passes (the exposure, contrast,
+//! shadows/highlights, saturation, vibrance and gamma stages of `adjust_image`, LUTs,
+//! blur and convolution), split across rayon's thread pool with the `parallel`
+//! feature. Without the feature the same loops run sequentially.
+//!
+//! On the web, threads need a wasm build with shared memory (nightly Rust with
+//! `-C target-feature=+atomics,+bulk-memory,+mutable-globals` and
+//! `-Z build-std=panic_abort,std`), a rayon pool started from JS before the first
+//! call (e.g. `initThreadPool` from wasm-bindgen-rayon), and a cross-origin isolated
+//! page so `SharedArrayBuffer` is available: serve it with
+//! `Cross-Origin-Opener-Policy: same-origin` and
+//! `Cross-Origin-Embedder-Policy: require-corp`. Without a pool, rayon runs
+//! everything on the calling thread.
+
+use image::{Pixel, Rgba, RgbaImage};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Call `f(y, row)` for every `row_len`-sized row of `buf`
+#[cfg(feature = "parallel")]
+pub(crate) fn for_each_row<T: Send>(buf: &mut [T], row_len: usize, f: impl Fn(usize, &mut [T]) + Sync + Send) {
+    buf.par_chunks_mut(row_len).enumerate().for_each(|(y, row)| f(y, row));
+}
+
+/// Call `f(y, row)` for every `row_len`-sized row of `buf`
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn for_each_row<T: Send>(buf: &mut [T], row_len: usize, f: impl Fn(usize, &mut [T]) + Sync + Send) {
+    buf.chunks_mut(row_len).enumerate().for_each(|(y, row)| f(y, row));
+}
+
+/// Call `f` on every pixel of `img`, a row at a time
+pub(crate) fn for_each_pixel(img: &mut RgbaImage, f: impl Fn(&mut Rgba<u8>) + Sync + Send) {
+    let row_len = img.width() as usize * 4;
+    if row_len == 0 {
+        return;
+    }
+    for_each_row(img, row_len, |_, row| {
+        row.chunks_exact_mut(4).for_each(|pixel| f(Rgba::from_slice_mut(pixel)));
+    });
+}
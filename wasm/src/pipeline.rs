@@ -0,0 +1,160 @@
+use wasm_bindgen::prelude::*;
+use serde::Deserialize;
+
+use crate::common::catch_panic;
+use crate::handle::ImageHandle;
+
+/// One step of a pipeline, e.g. `{"op": "resize", "width": 800, "height": 600}`
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+enum Operation {
+    Resize {
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        filter: String,
+    },
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Rotate {
+        degrees: f32,
+        #[serde(default)]
+        background: String,
+        #[serde(default)]
+        interpolation: String,
+    },
+    Adjust {
+        #[serde(default)]
+        brightness: i32,
+        #[serde(default)]
+        contrast: f32,
+        #[serde(default = "one")]
+        saturation: f32,
+        #[serde(default)]
+        hue: i32,
+        #[serde(default)]
+        exposure: f32,
+        #[serde(default = "one")]
+        gamma: f32,
+        #[serde(default)]
+        shadows: f32,
+        #[serde(default)]
+        highlights: f32,
+        #[serde(default)]
+        vibrance: f32,
+    },
+    Encode {
+        #[serde(default)]
+        format: String,
+        #[serde(default = "default_quality")]
+        quality: u8,
+    },
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn default_quality() -> u8 {
+    85
+}
+
+/// Output of `try_or_original`: the processed image, or the untouched input together
+/// with the reason the pipeline failed
+#[wasm_bindgen(getter_with_clone)]
+pub struct PipelineResult {
+    pub data: Vec<u8>,
+    pub error: Option<String>,
+}
+
+/// Decode once, run every operation on an `ImageHandle` and encode (in the source
+/// format unless the last step is an `encode` op)
+fn run_pipeline(data: &[u8], op_json: &str) -> Result<Vec<u8>, String> {
+    let operations: Vec<Operation> = serde_json::from_str(op_json)
+        .map_err(|e| format!("Invalid pipeline: {}", e))?;
+
+    let mut handle = ImageHandle::new(data)?;
+    let mut output = (String::new(), default_quality());
+    for (i, operation) in operations.into_iter().enumerate() {
+        let result = match operation {
+            Operation::Resize { width, height, filter } => handle.resize(width, height, &filter),
+            Operation::Crop { x, y, width, height } => handle.crop(x, y, width, height),
+            Operation::Rotate { degrees, background, interpolation } => {
+                let background = if background.is_empty() { "transparent" } else { &background };
+                handle.rotate(degrees, background, &interpolation)
+            }
+            Operation::Adjust {
+                brightness, contrast, saturation, hue, exposure, gamma, shadows, highlights, vibrance,
+            } => handle.adjust(
+                brightness, contrast, saturation, hue, exposure, gamma, shadows, highlights, vibrance, "", "",
+            ),
+            Operation::Encode { format, quality } => {
+                output = (format, quality);
+                Ok(())
+            }
+        };
+        result.map_err(|e| format!("Step {} failed: {}", i + 1, e))?;
+    }
+
+    handle.encode(&output.0, output.1)
+}
+
+/// Best-effort batch processing: apply a JSON pipeline such as
+///
+/// ```json
+/// [{"op": "crop", "x": 0, "y": 0, "width": 800, "height": 800},
+///  {"op": "resize", "width": 400, "height": 400, "filter": "lanczos3"},
+///  {"op": "encode", "format": "webp"}]
+/// ```
+///
+/// and never fail: if any step (or decoding) goes wrong, the original bytes are
+/// returned unchanged and `error` says why. Panics are reported the same way in
+/// the `build:wasm:unwind` build (see `catch_panic`). Supported ops are `resize`, `crop`,
+/// `rotate`, `adjust` (same names as `adjust_image`, omitted ones are neutral) and
+/// `encode`.
+#[wasm_bindgen]
+pub fn try_or_original(data: &[u8], op_json: &str) -> PipelineResult {
+    match catch_panic("Pipeline", || run_pipeline(data, op_json)) {
+        Ok(data) => PipelineResult { data, error: None },
+        Err(error) => PipelineResult { data: data.to_vec(), error: Some(error) },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+    use image::{Rgba, RgbaImage};
+
+    fn png() -> Vec<u8> {
+        encode_png_fixture(RgbaImage::from_pixel(16, 16, Rgba([10, 20, 30, 255])))
+    }
+
+    #[test]
+    fn pipeline_runs_operations() {
+        let result = try_or_original(&png(), r#"[{"op": "resize", "width": 8, "height": 4}]"#);
+        assert_eq!(result.error, None);
+        assert_eq!(image::load_from_memory(&result.data).unwrap().width(), 8);
+    }
+
+    #[test]
+    fn pipeline_returns_the_original_on_bad_input() {
+        let data = png();
+        let truncated = &data[..data.len() / 2];
+        for (input, ops) in [
+            (truncated, r#"[{"op": "rotate", "degrees": 90}]"#),
+            (&b"garbage"[..], r#"[{"op": "rotate", "degrees": 90}]"#),
+            (&data[..], r#"[{"op": "explode"}]"#),
+            (&data[..], r#"[{"op": "crop", "x": 10, "y": 10, "width": 50, "height": 50}]"#),
+            (&data[..], "not json"),
+        ] {
+            let result = try_or_original(input, ops);
+            assert!(result.error.is_some(), "{}", ops);
+            assert_eq!(result.data, input);
+        }
+    }
+}
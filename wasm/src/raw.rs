@@ -0,0 +1,205 @@
+use wasm_bindgen::prelude::*;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use std::io::Cursor;
+
+use crate::common::{apply_orientation, check_dimensions, decode_image};
+use crate::encode::{encode_to, parse_format};
+use crate::metadata::read_orientation;
+
+/// Raw 8-bit RGBA pixels, row-major, `width * height * 4` bytes long, ready for
+/// `new ImageData(new Uint8ClampedArray(pixels), width, height)`
+#[wasm_bindgen(getter_with_clone)]
+pub struct RawImage {
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Decode to raw RGBA so a canvas can draw it with `putImageData` without a second
+/// decode in the browser. With `orient` the EXIF orientation is applied, so the
+/// buffer is upright and `width`/`height` are the rotated dimensions; otherwise
+/// pixels are returned as stored.
+#[wasm_bindgen]
+pub fn decode_to_rgba(data: &[u8], orient: bool) -> Result<RawImage, String> {
+    let mut img = decode_image(data)?.img;
+    if orient && let Some(orientation) = read_orientation(data) {
+        apply_orientation(&mut img, orientation);
+    }
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+
+    Ok(RawImage { pixels: rgba.into_raw(), width, height })
+}
+
+/// Encode raw RGBA pixels (e.g. from `getImageData`) as `format`; `quality` (1-100)
+/// applies to JPEG. `pixels` must be exactly `width * height * 4` bytes.
+#[wasm_bindgen]
+pub fn encode_from_rgba(pixels: &[u8], width: u32, height: u32, format: &str, quality: u8) -> Result<Vec<u8>, String> {
+    check_dimensions(width, height)?;
+    let format = parse_format(format)?;
+
+    let expected = width as usize * height as usize * 4;
+    if pixels.len() != expected {
+        return Err(format!(
+            "Pixel buffer has {} bytes, expected {} for {}x{} RGBA",
+            pixels.len(), expected, width, height
+        ));
+    }
+
+    let rgba = RgbaImage::from_raw(width, height, pixels.to_vec())
+        .ok_or("Failed to create image from pixel buffer")?;
+    encode_to(&DynamicImage::ImageRgba8(rgba), format, quality)
+}
+
+/// Raw RGBA pixels of a possibly truncated image, see `decode_partial`
+#[wasm_bindgen(getter_with_clone)]
+pub struct PartialImage {
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// Rows from the top that hold image data; rows below are transparent
+    pub rows_decoded: u32,
+    /// False when the data ended before the end of the image
+    pub complete: bool,
+}
+
+/// Decode the rows of a PNG up to the point where its data ends. Interlaced PNGs
+/// deliver rows in seven passes over the whole image, so they are not handled.
+fn decode_png_rows(data: &[u8]) -> Option<PartialImage> {
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().ok()?;
+    let (width, height) = (reader.info().width, reader.info().height);
+    if reader.info().interlaced || check_dimensions(width, height).is_err() {
+        return None;
+    }
+
+    let samples = match reader.output_color_type().0 {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => return None,
+    };
+
+    let row_bytes = width as usize * 4;
+    let mut pixels = vec![0u8; row_bytes * height as usize];
+    let mut rows_decoded = 0;
+    while let Ok(Some(row)) = reader.next_row() {
+        let start = rows_decoded as usize * row_bytes;
+        for (out, src) in pixels[start..start + row_bytes].chunks_mut(4).zip(row.data().chunks(samples)) {
+            out.copy_from_slice(&match *src {
+                [g] => [g, g, g, 255],
+                [g, a] => [g, g, g, a],
+                [r, g, b] => [r, g, b, 255],
+                [r, g, b, a] => [r, g, b, a],
+                _ => return None,
+            });
+        }
+        rows_decoded += 1;
+    }
+
+    Some(PartialImage { pixels, width, height, rows_decoded, complete: rows_decoded == height })
+}
+
+/// Like `decode_to_rgba`, but for uploads that may have been cut off: returns as much
+/// of the image as can be decoded plus a `complete` flag. Truncated PNGs (not
+/// interlaced) yield every row up to the cut, the rest left transparent. Truncated
+/// JPEGs are detected by the missing end marker; the decoder fills the lost part with
+/// gray, so `rows_decoded` is the full height there. Other formats only decode when
+/// complete. When nothing can be recovered the error tells a truncated or corrupt
+/// file apart from data that is not an image.
+#[wasm_bindgen]
+pub fn decode_partial(data: &[u8]) -> Result<PartialImage, String> {
+    let error = match decode_image(data) {
+        Ok(decoded) => {
+            let rgba = decoded.img.to_rgba8();
+            let (width, height) = rgba.dimensions();
+            let complete = decoded.format != ImageFormat::Jpeg
+                || data.iter().rposition(|&b| b != 0).is_some_and(|end| end >= 1 && data[end - 1..=end] == [0xFF, 0xD9]);
+            return Ok(PartialImage { pixels: rgba.into_raw(), width, height, rows_decoded: height, complete });
+        }
+        Err(error) => error,
+    };
+
+    if image::guess_format(data).ok() == Some(ImageFormat::Png)
+        && let Some(partial) = decode_png_rows(data)
+        && partial.rows_decoded > 0
+    {
+        return Ok(partial);
+    }
+
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+
+    /// Maps displayed coordinates to the stored pixel shown there
+    type SourceOf = fn(u32, u32) -> (u32, u32);
+
+    /// Distinct color for each stored pixel
+    fn stored_pixel(x: u32, y: u32) -> [u8; 4] {
+        [(x * 80) as u8, (y * 120) as u8, 7, 255]
+    }
+
+    /// A 3x2 PNG carrying an eXIf chunk with the given orientation
+    fn oriented_png(orientation: u16) -> Vec<u8> {
+        let img = RgbaImage::from_fn(3, 2, |x, y| image::Rgba(stored_pixel(x, y)));
+        let mut png = encode_png_fixture(img);
+
+        let mut tiff = b"MM\0\x2A\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        tiff.extend_from_slice(&orientation.to_be_bytes());
+        tiff.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+
+        let mut chunk = (tiff.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(b"eXIf");
+        chunk.extend_from_slice(&tiff);
+        chunk.extend_from_slice(&crc32fast::hash(&chunk[4..]).to_be_bytes());
+
+        let ihdr_end = 8 + 8 + 13 + 4;
+        png.splice(ihdr_end..ihdr_end, chunk);
+        png
+    }
+
+    #[test]
+    fn orient_applies_every_exif_orientation() {
+        const W: u32 = 3;
+        const H: u32 = 2;
+        // Stored coordinates shown at displayed (x, y) for each orientation
+        let source: [SourceOf; 8] = [
+            |x, y| (x, y),
+            |x, y| (W - 1 - x, y),
+            |x, y| (W - 1 - x, H - 1 - y),
+            |x, y| (x, H - 1 - y),
+            |x, y| (y, x),
+            |x, y| (y, H - 1 - x),
+            |x, y| (W - 1 - y, H - 1 - x),
+            |x, y| (W - 1 - y, x),
+        ];
+
+        for (orientation, source) in (1..=8).zip(source) {
+            let raw = decode_to_rgba(&oriented_png(orientation), true).unwrap();
+            let expected_size = if orientation >= 5 { (H, W) } else { (W, H) };
+            assert_eq!((raw.width, raw.height), expected_size, "orientation {}", orientation);
+            assert_eq!(raw.pixels.len(), (raw.width * raw.height * 4) as usize);
+
+            for y in 0..raw.height {
+                for x in 0..raw.width {
+                    let offset = ((y * raw.width + x) * 4) as usize;
+                    let (sx, sy) = source(x, y);
+                    assert_eq!(raw.pixels[offset..offset + 4], stored_pixel(sx, sy), "orientation {} at ({}, {})", orientation, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn without_orient_pixels_stay_as_stored() {
+        let raw = decode_to_rgba(&oriented_png(6), false).unwrap();
+        assert_eq!((raw.width, raw.height), (3, 2));
+        assert_eq!(raw.pixels[4..8], stored_pixel(1, 0));
+    }
+}
@@ -0,0 +1,238 @@
+use wasm_bindgen::prelude::*;
+use image::ImageFormat;
+use exif::{In, Tag, Value};
+use std::io::Cursor;
+
+use crate::common::decode_image;
+use crate::encode::{encode_to, parse_format};
+
+/// Meters per inch, for converting PNG pHYs pixels-per-meter
+const METERS_PER_INCH: f64 = 0.0254;
+
+/// Centimeters per inch, for JFIF densities given in dots per cm
+const CM_PER_INCH: f32 = 2.54;
+
+/// Horizontal and vertical resolution in dots per inch
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Resolution {
+    pub x_dpi: f32,
+    pub y_dpi: f32,
+}
+
+/// Whether the JPEG starts with a JFIF APP0 segment (holding the density fields)
+fn jfif_header(data: &[u8]) -> bool {
+    data.len() >= 18 && data[..4] == [0xFF, 0xD8, 0xFF, 0xE0] && &data[6..11] == b"JFIF\0"
+}
+
+fn read_jpeg_dpi(data: &[u8]) -> Option<(Resolution, &'static str)> {
+    if !jfif_header(data) {
+        return None;
+    }
+    let x = u16::from_be_bytes([data[14], data[15]]) as f32;
+    let y = u16::from_be_bytes([data[16], data[17]]) as f32;
+    if x == 0.0 || y == 0.0 {
+        return None;
+    }
+    match data[13] {
+        1 => Some((Resolution { x_dpi: x, y_dpi: y }, "inch")),
+        2 => Some((Resolution { x_dpi: x * CM_PER_INCH, y_dpi: y * CM_PER_INCH }, "cm")),
+        // Unit 0 only gives the pixel aspect ratio
+        _ => None,
+    }
+}
+
+fn read_png_dpi(data: &[u8]) -> Option<(Resolution, &'static str)> {
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        match &data[pos + 4..pos + 8] {
+            b"pHYs" => {
+                let chunk = data.get(pos + 8..pos + 17)?;
+                let x = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as f64;
+                let y = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as f64;
+                // Unit 0 only gives the pixel aspect ratio
+                if chunk[8] != 1 || x == 0.0 || y == 0.0 {
+                    return None;
+                }
+                let dpi = Resolution {
+                    x_dpi: (x * METERS_PER_INCH) as f32,
+                    y_dpi: (y * METERS_PER_INCH) as f32,
+                };
+                return Some((dpi, "meter"));
+            }
+            b"IDAT" | b"IEND" => return None,
+            // The length is untrusted and would wrap a 32-bit usize
+            _ => pos = length.checked_add(12).and_then(|n| pos.checked_add(n)).filter(|&p| p <= data.len())?,
+        }
+    }
+    None
+}
+
+/// Store the resolution in the JFIF APP0 density fields, replacing an existing JFIF
+/// header or inserting one right after SOI
+fn write_jpeg_dpi(data: &[u8], x_dpi: u16, y_dpi: u16) -> Result<Vec<u8>, String> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err("Not a JPEG file".to_string());
+    }
+
+    let mut output = data.to_vec();
+    if jfif_header(data) {
+        output[13] = 1; // units: dots per inch
+        output[14..16].copy_from_slice(&x_dpi.to_be_bytes());
+        output[16..18].copy_from_slice(&y_dpi.to_be_bytes());
+    } else {
+        let mut app0 = vec![0xFF, 0xE0, 0x00, 0x10];
+        app0.extend_from_slice(b"JFIF\0");
+        app0.extend_from_slice(&[1, 1, 1]);
+        app0.extend_from_slice(&x_dpi.to_be_bytes());
+        app0.extend_from_slice(&y_dpi.to_be_bytes());
+        app0.extend_from_slice(&[0, 0]);
+        output.splice(2..2, app0);
+    }
+
+    Ok(output)
+}
+
+/// Store the resolution in a pHYs chunk right after IHDR, dropping any existing pHYs chunk
+fn write_png_dpi(data: &[u8], x_dpi: f32, y_dpi: f32) -> Result<Vec<u8>, String> {
+    const SIGNATURE_LEN: usize = 8;
+    const IHDR_END: usize = SIGNATURE_LEN + 8 + 13 + 4;
+    if data.len() < IHDR_END || &data[12..16] != b"IHDR" {
+        return Err("Not a PNG file".to_string());
+    }
+
+    let to_ppm = |dpi: f32| (dpi as f64 / METERS_PER_INCH).round() as u32;
+    let mut chunk_data = b"pHYs".to_vec();
+    chunk_data.extend_from_slice(&to_ppm(x_dpi).to_be_bytes());
+    chunk_data.extend_from_slice(&to_ppm(y_dpi).to_be_bytes());
+    chunk_data.push(1); // unit: meter
+
+    let mut output = data[..IHDR_END].to_vec();
+    output.extend_from_slice(&9u32.to_be_bytes());
+    output.extend_from_slice(&chunk_data);
+    output.extend_from_slice(&crc32fast::hash(&chunk_data).to_be_bytes());
+
+    let mut pos = IHDR_END;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let end = length.checked_add(12).and_then(|n| pos.checked_add(n)).map_or(data.len(), |end| end.min(data.len()));
+        if &data[pos + 4..pos + 8] != b"pHYs" {
+            output.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+
+    Ok(output)
+}
+
+/// Record the resolution in the tags of an encoded image without re-encoding it.
+/// Formats without a supported resolution field are returned unchanged.
+pub(crate) fn embed_dpi(data: Vec<u8>, format: ImageFormat, x_dpi: f32, y_dpi: f32) -> Result<Vec<u8>, String> {
+    if !(x_dpi.is_finite() && y_dpi.is_finite() && x_dpi > 0.0 && y_dpi > 0.0) {
+        return Err(format!("Invalid resolution {}x{} DPI", x_dpi, y_dpi));
+    }
+
+    match format {
+        ImageFormat::Jpeg => {
+            let to_density = |dpi: f32| {
+                u16::try_from(dpi.round() as u32).map_err(|_| format!("DPI {} is too large to store", dpi))
+            };
+            write_jpeg_dpi(&data, to_density(x_dpi)?.max(1), to_density(y_dpi)?.max(1))
+        }
+        ImageFormat::Png => write_png_dpi(&data, x_dpi, y_dpi),
+        _ => Ok(data),
+    }
+}
+
+/// EXIF/TIFF XResolution and YResolution, as written by cameras (often without a
+/// JFIF header) and by TIFF and WebP encoders
+fn read_exif_dpi(data: &[u8]) -> Option<(Resolution, &'static str)> {
+    let exif = exif::Reader::new().read_from_container(&mut Cursor::new(data)).ok()?;
+    let rational = |tag: Tag| match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Rational(values)) => values.first().map(|v| v.to_f64() as f32)
+            .filter(|v| v.is_finite() && *v > 0.0),
+        _ => None,
+    };
+    let (x, y) = (rational(Tag::XResolution)?, rational(Tag::YResolution)?);
+
+    // ResolutionUnit defaults to inches; 1 means no absolute unit
+    let unit = exif.get_field(Tag::ResolutionUnit, In::PRIMARY).and_then(|f| f.value.get_uint(0));
+    match unit.unwrap_or(2) {
+        2 => Some((Resolution { x_dpi: x, y_dpi: y }, "inch")),
+        3 => Some((Resolution { x_dpi: x * CM_PER_INCH, y_dpi: y * CM_PER_INCH }, "cm")),
+        _ => None,
+    }
+}
+
+/// Resolution converted to DPI, with the unit the file declares it in ("inch", "cm"
+/// or "meter"). JPEG JFIF density and PNG pHYs take precedence over EXIF. `None` when
+/// the file has no resolution or only a pixel aspect ratio.
+pub(crate) fn detect_resolution(data: &[u8]) -> Option<(Resolution, &'static str)> {
+    let tagged = if data.starts_with(&[0xFF, 0xD8]) {
+        read_jpeg_dpi(data)
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        read_png_dpi(data)
+    } else {
+        None
+    };
+    tagged.or_else(|| read_exif_dpi(data))
+}
+
+/// Read the DPI from JPEG (JFIF density), PNG (pHYs) or EXIF resolution tags
+#[wasm_bindgen]
+pub fn read_dpi(data: &[u8]) -> Option<Resolution> {
+    detect_resolution(data).map(|(dpi, _)| dpi)
+}
+
+/// Write the DPI into the resolution tags. With an empty `format` the source bytes are
+/// patched without re-encoding; otherwise the image is converted to `format` first.
+/// Only JPEG and PNG store a resolution; other formats are returned without it.
+#[wasm_bindgen]
+pub fn set_dpi(data: &[u8], x_dpi: f32, y_dpi: f32, format: &str) -> Result<Vec<u8>, String> {
+    let decoded = decode_image(data)?;
+
+    if format.is_empty() {
+        return embed_dpi(data.to_vec(), decoded.format, x_dpi, y_dpi);
+    }
+
+    let format = parse_format(format)?;
+    let output = encode_to(&decoded.img, format, 90)?;
+    embed_dpi(output, format, x_dpi, y_dpi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+    use image::RgbImage;
+
+    fn png() -> Vec<u8> {
+        encode_png_fixture(RgbImage::new(4, 4))
+    }
+
+    /// `png` with a chunk declaring `length` bytes inserted after IHDR
+    fn with_chunk_length(png: &[u8], length: u32) -> Vec<u8> {
+        let mut output = png[..33].to_vec();
+        output.extend_from_slice(&length.to_be_bytes());
+        output.extend_from_slice(b"tEXt");
+        output.extend_from_slice(&png[33..]);
+        output
+    }
+
+    #[test]
+    fn png_dpi_round_trips() {
+        let tagged = set_dpi(&png(), 300.0, 150.0, "").unwrap();
+        let dpi = read_dpi(&tagged).unwrap();
+        assert!((dpi.x_dpi - 300.0).abs() < 0.5 && (dpi.y_dpi - 150.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn oversized_png_chunk_lengths_stop_the_scan() {
+        for length in [u32::MAX, 0xFFFF_FFF4, 1 << 20] {
+            let data = with_chunk_length(&png(), length);
+            assert!(read_dpi(&data).is_none());
+            assert!(write_png_dpi(&data, 72.0, 72.0).is_ok());
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use wasm_bindgen::prelude::*;
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont, point};
+use image::{DynamicImage, Rgba};
+use std::io::Cursor;
+
+use crate::alpha::composite_over;
+use crate::common::{decode_image, parse_color};
+
+/// Largest font size accepted by `draw_text`, in pixels
+const MAX_TEXT_SIZE: f32 = 2000.0;
+
+/// Largest outline accepted by `draw_text`, in pixels. `dilate` visits every offset
+/// in the stroke disc for every pixel around the text, so its cost grows with the
+/// square of the width.
+const MAX_STROKE_WIDTH: f32 = 50.0;
+
+/// Horizontal placement of each line relative to the anchor x
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "" | "left" => Ok(Align::Left),
+            "center" => Ok(Align::Center),
+            "right" => Ok(Align::Right),
+            _ => Err(format!("Invalid text alignment: {}", name)),
+        }
+    }
+}
+
+/// Glyph coverage (0..1) of the text over the whole image, and the bounding box of
+/// the covered pixels as (min_x, min_y, max_x, max_y)
+struct Coverage {
+    values: Vec<f32>,
+    bounds: Option<(u32, u32, u32, u32)>,
+}
+
+/// Lay out `text` (lines split on '\n') and rasterize it into a `width`x`height`
+/// coverage map. (`x`, `y`) is the anchor of the first line's top edge.
+#[allow(clippy::too_many_arguments)]
+fn rasterize(font: &FontRef, text: &str, x: i32, y: i32, size: f32, align: Align, width: u32, height: u32) -> Coverage {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let line_height = scaled.ascent() - scaled.descent() + scaled.line_gap();
+
+    let mut coverage = Coverage { values: vec![0.0; (width * height) as usize], bounds: None };
+    for (row, line) in text.lines().enumerate() {
+        let mut caret = 0.0;
+        let mut glyphs = Vec::new();
+        let mut previous = None;
+        for c in line.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, id);
+            }
+            glyphs.push((id, caret));
+            caret += scaled.h_advance(id);
+            previous = Some(id);
+        }
+
+        let start = match align {
+            Align::Left => x as f32,
+            Align::Center => x as f32 - caret / 2.0,
+            Align::Right => x as f32 - caret,
+        };
+        let baseline = y as f32 + scaled.ascent() + row as f32 * line_height;
+
+        for (id, offset) in glyphs {
+            let glyph = id.with_scale_and_position(size, point(start + offset, baseline));
+            let Some(outline) = scaled.outline_glyph(glyph) else { continue };
+            let bounds = outline.px_bounds();
+            outline.draw(|gx, gy, c| {
+                let (px, py) = (bounds.min.x as i64 + gx as i64, bounds.min.y as i64 + gy as i64);
+                if px < 0 || py < 0 || px >= width as i64 || py >= height as i64 {
+                    return;
+                }
+                let (px, py) = (px as u32, py as u32);
+                let value = &mut coverage.values[(py * width + px) as usize];
+                *value = value.max(c.min(1.0));
+                coverage.bounds = Some(match coverage.bounds {
+                    None => (px, py, px, py),
+                    Some((x0, y0, x1, y1)) => (x0.min(px), y0.min(py), x1.max(px), y1.max(py)),
+                });
+            });
+        }
+    }
+    coverage
+}
+
+/// Grow the coverage by `radius` pixels with an anti-aliased disc, producing the
+/// outline drawn behind the text
+fn dilate(coverage: &Coverage, radius: f32, width: u32, height: u32) -> Vec<f32> {
+    let mut outline = vec![0.0; coverage.values.len()];
+    let Some((x0, y0, x1, y1)) = coverage.bounds else { return outline };
+
+    let reach = radius.ceil() as i64;
+    let offsets: Vec<(i64, i64, f32)> = (-reach..=reach)
+        .flat_map(|dy| (-reach..=reach).map(move |dx| (dx, dy)))
+        .filter_map(|(dx, dy)| {
+            let weight = (radius + 0.5 - ((dx * dx + dy * dy) as f32).sqrt()).clamp(0.0, 1.0);
+            (weight > 0.0).then_some((dx, dy, weight))
+        })
+        .collect();
+
+    let clamp_x = |v: i64| v.clamp(0, width as i64 - 1) as u32;
+    let clamp_y = |v: i64| v.clamp(0, height as i64 - 1) as u32;
+    for py in clamp_y(y0 as i64 - reach)..=clamp_y(y1 as i64 + reach) {
+        for px in clamp_x(x0 as i64 - reach)..=clamp_x(x1 as i64 + reach) {
+            let mut value: f32 = 0.0;
+            for &(dx, dy, weight) in &offsets {
+                let (sx, sy) = (px as i64 + dx, py as i64 + dy);
+                if sx >= 0 && sy >= 0 && sx < width as i64 && sy < height as i64 {
+                    value = value.max(coverage.values[(sy as u32 * width + sx as u32) as usize] * weight);
+                }
+            }
+            outline[(py * width + px) as usize] = value;
+        }
+    }
+    outline
+}
+
+/// Draw `text` with a TrueType/OpenType font supplied as `font` bytes (WASM has no
+/// system fonts). (`x`, `y`) anchors the top of the first line; `align` ("left",
+/// "center" or "right") places each line relative to `x`, and '\n' starts a new line.
+/// `size` is the font size in pixels. A `stroke_width` above 0 draws an outline of
+/// that many pixels in `stroke_color` behind the text, e.g. white text with a black
+/// outline for memes. The stroke is limited to `MAX_STROKE_WIDTH` and to half the
+/// font size; it costs O(text area × stroke_width²). Missing glyphs are skipped.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn draw_text(
+    data: &[u8],
+    text: &str,
+    x: i32,
+    y: i32,
+    size: f32,
+    color: &str,
+    font: &[u8],
+    align: &str,
+    stroke_width: f32,
+    stroke_color: &str,
+) -> Result<Vec<u8>, String> {
+    if !size.is_finite() || size <= 0.0 || size > MAX_TEXT_SIZE {
+        return Err(format!("Font size must be between 0 and {} (got {})", MAX_TEXT_SIZE, size));
+    }
+    if !stroke_width.is_finite() || !(0.0..=MAX_STROKE_WIDTH.min(size / 2.0)).contains(&stroke_width) {
+        return Err(format!(
+            "Stroke width must be between 0 and {} and at most half the font size (got {})",
+            MAX_STROKE_WIDTH, stroke_width
+        ));
+    }
+    let align = Align::parse(align)?;
+    let fill = parse_color(color)?;
+    let stroke = if stroke_width > 0.0 { Some(parse_color(stroke_color)?) } else { None };
+    let font = FontRef::try_from_slice(font).map_err(|e| format!("Failed to load font: {}", e))?;
+
+    let decoded = decode_image(data)?;
+    let mut rgba = decoded.img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+
+    let coverage = rasterize(&font, text, x, y, size, align, width, height);
+    let outline = stroke.map(|stroke| (stroke, dilate(&coverage, stroke_width, width, height)));
+
+    let with_alpha = |color: Rgba<u8>, amount: f32| {
+        let mut color = color;
+        color.0[3] = (color.0[3] as f32 * amount).round() as u8;
+        color
+    };
+    for (i, pixel) in rgba.pixels_mut().enumerate() {
+        if let Some((stroke, outline)) = &outline
+            && outline[i] > 0.0
+        {
+            *pixel = composite_over(with_alpha(*stroke, outline[i]), *pixel, false);
+        }
+        if coverage.values[i] > 0.0 {
+            *pixel = composite_over(with_alpha(fill, coverage.values[i]), *pixel, false);
+        }
+    }
+
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(rgba).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode image with text: {}", e))?;
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+    use image::RgbaImage;
+
+    /// Minimal TrueType font whose printable ASCII glyphs are all a solid box
+    /// (x 100..500, y 0..700 on a 1000 unit em, advance 600)
+    const BOX_FONT: &[u8] = include_bytes!("../tests/fixtures/box.ttf");
+
+    fn canvas() -> Vec<u8> {
+        encode_png_fixture(RgbaImage::from_pixel(200, 120, Rgba([255, 255, 255, 255])))
+    }
+
+    fn draw(text: &str, align: &str, stroke_width: f32) -> Result<Vec<u8>, String> {
+        draw_text(&canvas(), text, 100, 10, 40.0, "#000000", BOX_FONT, align, stroke_width, "#ff0000")
+    }
+
+    /// Bounding box (min_x, min_y, max_x, max_y) of every non-white pixel
+    fn ink_bounds(png: &[u8]) -> (u32, u32, u32, u32) {
+        let img = image::load_from_memory(png).unwrap().to_rgba8();
+        img.enumerate_pixels()
+            .filter(|(_, _, p)| p.0 != [255, 255, 255, 255])
+            .fold((u32::MAX, u32::MAX, 0, 0), |(x0, y0, x1, y1), (x, y, _)| {
+                (x0.min(x), y0.min(y), x1.max(x), y1.max(y))
+            })
+    }
+
+    #[test]
+    fn alignment_moves_the_text_relative_to_the_anchor() {
+        // "II" at 40 px advances 48 px, with ink in columns 4..44 of that run
+        let left = ink_bounds(&draw("II", "left", 0.0).unwrap());
+        let center = ink_bounds(&draw("II", "center", 0.0).unwrap());
+        let right = ink_bounds(&draw("II", "right", 0.0).unwrap());
+
+        assert_eq!((left.0, left.2), (104, 143));
+        assert_eq!((center.0, center.2), (80, 119));
+        assert_eq!((right.0, right.2), (56, 95));
+        assert_eq!(left.1, center.1);
+        assert_eq!(left.3, right.3);
+    }
+
+    #[test]
+    fn newlines_stack_lines_one_line_height_apart() {
+        let single = ink_bounds(&draw("I", "left", 0.0).unwrap());
+        let double = ink_bounds(&draw("I\nI", "left", 0.0).unwrap());
+
+        // Ascent 800 minus descent -200 on a 1000 unit em is 40 px per line
+        assert_eq!(single.1, double.1);
+        assert_eq!(double.3, single.3 + 40);
+        assert_eq!((single.0, single.2), (double.0, double.2));
+    }
+
+    #[test]
+    fn stroke_widens_the_ink_on_every_side() {
+        let plain = ink_bounds(&draw("I", "left", 0.0).unwrap());
+        let stroked = ink_bounds(&draw("I", "left", 4.0).unwrap());
+
+        assert_eq!(stroked.0, plain.0 - 4);
+        assert_eq!(stroked.1, plain.1 - 4);
+        assert_eq!(stroked.2, plain.2 + 4);
+        assert_eq!(stroked.3, plain.3 + 4);
+    }
+
+    #[test]
+    fn rejects_invalid_size_stroke_and_alignment() {
+        let png = canvas();
+        assert!(draw_text(&png, "I", 0, 0, 0.0, "#000", BOX_FONT, "", 0.0, "").is_err());
+        assert!(draw_text(&png, "I", 0, 0, 40.0, "#000", BOX_FONT, "", 21.0, "#fff").is_err());
+        assert!(draw_text(&png, "I", 0, 0, 200.0, "#000", BOX_FONT, "", 60.0, "#fff").is_err());
+        assert!(draw_text(&png, "I", 0, 0, 40.0, "#000", BOX_FONT, "justify", 0.0, "").is_err());
+        assert!(draw_text(&png, "I", 0, 0, 40.0, "#000", b"not a font", "", 0.0, "").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+//! Per-stage timing for profiling, compiled in with the `timing` feature.
+//! When enabled, each instrumented operation logs a JSON line such as
+//! `{"op":"resize","decode_ms":4.10,"process_ms":12.30,"encode_ms":8.00,"total_ms":24.40}`
+//! via `console.log`. Without the feature, and in native builds such as `cargo test`
+//! where there is no `performance` or `console`, every call is a no-op.
+
+#[cfg(all(feature = "timing", target_arch = "wasm32"))]
+pub(crate) struct StageTimer {
+    op: &'static str,
+    start: f64,
+    last: f64,
+    stages: Vec<(&'static str, f64)>,
+}
+
+#[cfg(all(feature = "timing", target_arch = "wasm32"))]
+fn now() -> f64 {
+    use wasm_bindgen::JsCast;
+
+    // Works in both window and worker scopes
+    js_sys::Reflect::get(&js_sys::global(), &"performance".into())
+        .ok()
+        .and_then(|p| p.dyn_into::<web_sys::Performance>().ok())
+        .map(|p| p.now())
+        .unwrap_or(0.0)
+}
+
+#[cfg(all(feature = "timing", target_arch = "wasm32"))]
+impl StageTimer {
+    pub fn start(op: &'static str) -> Self {
+        let start = now();
+        StageTimer { op, start, last: start, stages: Vec::new() }
+    }
+
+    /// Record the time elapsed since the previous stage (or the start)
+    pub fn stage(&mut self, name: &'static str) {
+        let t = now();
+        self.stages.push((name, t - self.last));
+        self.last = t;
+    }
+
+    pub fn finish(self) {
+        let mut json = format!("{{\"op\":\"{}\"", self.op);
+        for (name, ms) in &self.stages {
+            json.push_str(&format!(",\"{}_ms\":{:.2}", name, ms));
+        }
+        json.push_str(&format!(",\"total_ms\":{:.2}}}", self.last - self.start));
+        web_sys::console::log_1(&json.into());
+    }
+}
+
+#[cfg(not(all(feature = "timing", target_arch = "wasm32")))]
+pub(crate) struct StageTimer;
+
+#[cfg(not(all(feature = "timing", target_arch = "wasm32")))]
+impl StageTimer {
+    #[inline(always)]
+    pub fn start(_op: &'static str) -> Self {
+        StageTimer
+    }
+
+    #[inline(always)]
+    pub fn stage(&mut self, _name: &'static str) {}
+
+    #[inline(always)]
+    pub fn finish(self) {}
+}
@@ -1,11 +1,208 @@
 use wasm_bindgen::prelude::*;
+use image::{DynamicImage, ImageFormat, Rgba, Rgba32FImage, RgbaImage};
+use image::imageops::FilterType;
+use serde::Deserialize;
 use std::io::Cursor;
 
-use crate::common::decode_image;
+use crate::common::{check_dimensions, check_region, convert_color, decode_for_size, decode_image, parse_color, sobel_energy, supports_alpha};
+use crate::encode::parse_format;
+use crate::metadata::read_xmp_roll;
+use crate::resolution::embed_dpi;
+use crate::timing::StageTimer;
+
+/// Encoded image together with its final pixel dimensions
+#[wasm_bindgen(getter_with_clone)]
+pub struct TransformResult {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Encoded crop together with the rectangle that was used
+#[wasm_bindgen(getter_with_clone)]
+pub struct CropResult {
+    pub data: Vec<u8>,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// One encoded tile of `split_tiles` and its position in the source image
+#[wasm_bindgen(getter_with_clone)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// A crop rectangle in source pixels, as accepted by `crop_image`
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// One result of `crop_multiple`: the encoded crop, or why that rectangle failed
+#[wasm_bindgen(getter_with_clone)]
+pub struct CropOutcome {
+    pub data: Vec<u8>,
+    pub error: Option<String>,
+}
+
+/// Crop rectangle as given in `crop_multiple`'s JSON
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RectSpec {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Largest `width`x`height`-bounded size with the aspect ratio of `target_w`:`target_h`
+fn fit_aspect(width: u32, height: u32, target_w: u32, target_h: u32) -> (u32, u32) {
+    let target_aspect = target_w as f64 / target_h as f64;
+    if width as f64 / height as f64 > target_aspect {
+        let w = ((height as f64 * target_aspect).round() as u32).clamp(1, width);
+        (w, height)
+    } else {
+        let h = ((width as f64 / target_aspect).round() as u32).clamp(1, height);
+        (width, h)
+    }
+}
+
+pub(crate) fn parse_filter(filter: &str) -> FilterType {
+    match filter {
+        "nearest" => FilterType::Nearest,
+        "triangle" => FilterType::Triangle,
+        "catmull_rom" => FilterType::CatmullRom,
+        "gaussian" => FilterType::Gaussian,
+        _ => FilterType::Lanczos3,
+    }
+}
+
+/// For each output index along an axis, the first source index it covers and the
+/// weights (fractional coverage) of the source indices from there on
+fn box_weights(src_len: u32, dst_len: u32) -> Vec<(usize, Vec<f32>)> {
+    let scale = src_len as f64 / dst_len as f64;
+    (0..dst_len)
+        .map(|i| {
+            let start = i as f64 * scale;
+            let end = (i + 1) as f64 * scale;
+            let first = start.floor() as usize;
+            let last = (end.ceil() as usize).min(src_len as usize);
+            let weights = (first..last)
+                .map(|s| ((end.min(s as f64 + 1.0) - start.max(s as f64)) / scale) as f32)
+                .collect();
+            (first, weights)
+        })
+        .collect()
+}
+
+/// Area-averaging resize: every output pixel is the mean of the source area it covers.
+/// Unlike the point-sampled kernels this never rings, which suits tiny thumbnails.
+/// Averaging is done on premultiplied alpha so transparent pixels do not bleed color.
+fn resize_box(img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let mut src = img.to_rgba32f();
+    for pixel in src.pixels_mut() {
+        let a = pixel.0[3];
+        for c in 0..3 {
+            pixel.0[c] *= a;
+        }
+    }
+
+    let accumulate = |out: &mut [f32; 4], p: &Rgba<f32>, weight: f32| {
+        for (o, v) in out.iter_mut().zip(p.0) {
+            *o += v * weight;
+        }
+    };
+
+    let columns = box_weights(src.width(), width);
+    let horizontal = Rgba32FImage::from_fn(width, src.height(), |x, y| {
+        let (first, weights) = &columns[x as usize];
+        let mut out = [0.0; 4];
+        for (i, &weight) in weights.iter().enumerate() {
+            accumulate(&mut out, src.get_pixel((first + i) as u32, y), weight);
+        }
+        Rgba(out)
+    });
+
+    let rows = box_weights(src.height(), height);
+    let mut output = Rgba32FImage::from_fn(width, height, |x, y| {
+        let (first, weights) = &rows[y as usize];
+        let mut out = [0.0; 4];
+        for (i, &weight) in weights.iter().enumerate() {
+            accumulate(&mut out, horizontal.get_pixel(x, (first + i) as u32), weight);
+        }
+        Rgba(out)
+    });
+
+    for pixel in output.pixels_mut() {
+        let a = pixel.0[3];
+        for c in 0..3 {
+            pixel.0[c] = if a > 0.0 { pixel.0[c] / a } else { 0.0 };
+        }
+    }
+
+    convert_color(&DynamicImage::ImageRgba32F(output), img.color())
+}
+
+/// Reduction ratio from which `resize_high_quality` box-filters first
+const TWO_PASS_MIN_RATIO: f64 = 4.0;
+
+/// Resize, and for reductions of `TWO_PASS_MIN_RATIO` (4x) or more on either axis, first
+/// area-average down to twice the target before the final filter. A single kernel pass
+/// at such ratios skips source pixels and aliases; the box prefilter averages all of
+/// them, and the final pass runs on a much smaller image.
+fn resize_high_quality(img: &DynamicImage, width: u32, height: u32, filter: &str) -> DynamicImage {
+    let ratio = (img.width() as f64 / width as f64).max(img.height() as f64 / height as f64);
+    if filter == "box" || ratio < TWO_PASS_MIN_RATIO {
+        return resize_with_filter(img, width, height, filter);
+    }
+
+    let prefiltered = resize_box(img, width.saturating_mul(2).min(img.width()), height.saturating_mul(2).min(img.height()));
+    resize_with_filter(&prefiltered, width, height, filter)
+}
+
+/// Round a target size down to even numbers (as video codecs require), picking the
+/// even size whose aspect ratio is closest to `aspect`. Never exceeds the given size,
+/// so a side of 1 pixel is an error rather than being grown to 2.
+fn snap_even(width: u32, height: u32, aspect: f64) -> Result<(u32, u32), String> {
+    if width < 2 || height < 2 {
+        return Err(format!("Size {}x{} is too small to round down to even dimensions", width, height));
+    }
+    let even_w = width & !1;
+    let even_h = height & !1;
+    let h_from_w = (((even_w as f64 / aspect) / 2.0).round() as u32 * 2).clamp(2, even_h);
+    let w_from_h = (((even_h as f64 * aspect) / 2.0).round() as u32 * 2).clamp(2, even_w);
+
+    let error = |(w, h): (u32, u32)| (w as f64 / h as f64 - aspect).abs();
+    Ok([(even_w, even_h), (even_w, h_from_w), (w_from_h, even_h)]
+        .into_iter()
+        .min_by(|a, b| error(*a).total_cmp(&error(*b)))
+        .unwrap_or((even_w, even_h)))
+}
+
+/// Resize to exactly `width`x`height` with a named filter (see `parse_filter`), or
+/// "box" for area averaging
+pub(crate) fn resize_with_filter(img: &DynamicImage, width: u32, height: u32, filter: &str) -> DynamicImage {
+    match filter {
+        "box" => resize_box(img, width, height),
+        _ => img.resize_exact(width, height, parse_filter(filter)),
+    }
+}
 
 #[wasm_bindgen]
 pub fn crop_image(data: &[u8], x: u32, y: u32, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let mut timer = StageTimer::start("crop");
     let decoded = decode_image(data)?;
+    timer.stage("decode");
 
     if x + width > decoded.img.width() || y + height > decoded.img.height() {
         return Err(format!(
@@ -15,53 +212,999 @@ pub fn crop_image(data: &[u8], x: u32, y: u32, width: u32, height: u32) -> Resul
     }
 
     let cropped = decoded.img.crop_imm(x, y, width, height);
+    timer.stage("process");
+
+    let mut output = Vec::new();
+    cropped.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode cropped image: {}", e))?;
+    timer.stage("encode");
+    timer.finish();
+
+    Ok(output)
+}
+
+/// Cut several rectangles out of one decode, e.g. face tags:
+/// `[{"x": 10, "y": 20, "width": 64, "height": 64}, ...]`. Returns one entry per
+/// rectangle in order; a rectangle outside the image (or failing to encode) gets an
+/// empty `data` and its `error` set without affecting the others. `format` is the
+/// output format name, empty for the source format.
+#[wasm_bindgen]
+pub fn crop_multiple(data: &[u8], rects_json: &str, format: &str) -> Result<Vec<CropOutcome>, String> {
+    let rects: Vec<RectSpec> = serde_json::from_str(rects_json)
+        .map_err(|e| format!("Invalid crop rectangles: {}", e))?;
+
+    let decoded = decode_image(data)?;
+    let format = if format.is_empty() { decoded.format } else { parse_format(format)? };
+    let (width, height) = (decoded.img.width(), decoded.img.height());
+
+    Ok(rects.iter().map(|rect| {
+        let encoded = check_region(rect.x, rect.y, rect.width, rect.height, width, height).and_then(|()| {
+            let mut output = Vec::new();
+            decoded.img.crop_imm(rect.x, rect.y, rect.width, rect.height)
+                .write_to(&mut Cursor::new(&mut output), format)
+                .map_err(|e| format!("Failed to encode cropped image: {}", e))?;
+            Ok(output)
+        });
+        match encoded {
+            Ok(data) => CropOutcome { data, error: None },
+            Err(error) => CropOutcome { data: Vec::new(), error: Some(error) },
+        }
+    }).collect())
+}
+
+/// Crop the part of the requested rectangle that lies inside the image, for interactive
+/// crop boxes that may be dragged past the edges (`x`/`y` may be negative). Returns the
+/// rectangle actually used; errors only when it does not overlap the image at all.
+#[wasm_bindgen]
+pub fn crop_clamped(data: &[u8], x: i32, y: i32, width: u32, height: u32) -> Result<CropResult, String> {
+    let decoded = decode_image(data)?;
+    let (img_w, img_h) = (decoded.img.width() as i64, decoded.img.height() as i64);
+
+    let left = (x as i64).clamp(0, img_w);
+    let top = (y as i64).clamp(0, img_h);
+    let right = (x as i64 + width as i64).clamp(0, img_w);
+    let bottom = (y as i64 + height as i64).clamp(0, img_h);
+    if right <= left || bottom <= top {
+        return Err(format!(
+            "Crop region ({},{} {}x{}) does not overlap the image ({}x{})",
+            x, y, width, height, img_w, img_h
+        ));
+    }
+
+    let (x, y) = (left as u32, top as u32);
+    let (width, height) = ((right - left) as u32, (bottom - top) as u32);
+    let cropped = decoded.img.crop_imm(x, y, width, height);
+
+    let mut output = Vec::new();
+    cropped.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode cropped image: {}", e))?;
+
+    Ok(CropResult { data: output, x, y, width, height })
+}
+
+/// Crop to the aspect ratio of `target_w`:`target_h`, positioning the largest such
+/// window where it contains the most Sobel edge energy. This keeps off-center
+/// subjects in frame without any ML-based saliency detection.
+#[wasm_bindgen]
+pub fn smart_crop(data: &[u8], target_w: u32, target_h: u32) -> Result<CropResult, String> {
+    if target_w == 0 || target_h == 0 {
+        return Err(format!("Invalid target size {}x{}", target_w, target_h));
+    }
+
+    let decoded = decode_image(data)?;
+    let (width, height) = (decoded.img.width(), decoded.img.height());
+    let (crop_w, crop_h) = fit_aspect(width, height, target_w, target_h);
+
+    // The window can only slide along one axis, so project the energy onto it
+    let energy = sobel_energy(&decoded.img.to_luma8());
+    let horizontal = crop_w < width;
+    let profile: Vec<f64> = if horizontal {
+        (0..width as usize)
+            .map(|x| (0..height as usize).map(|y| energy[y * width as usize + x] as f64).sum())
+            .collect()
+    } else {
+        energy.chunks(width as usize)
+            .map(|row| row.iter().map(|&e| e as f64).sum())
+            .collect()
+    };
+
+    let window = if horizontal { crop_w } else { crop_h } as usize;
+    let mut best_offset = 0;
+    let mut window_sum: f64 = profile[..window].iter().sum();
+    let mut best_sum = window_sum;
+    for offset in 1..=(profile.len() - window) {
+        window_sum += profile[offset + window - 1] - profile[offset - 1];
+        if window_sum > best_sum {
+            best_sum = window_sum;
+            best_offset = offset;
+        }
+    }
+
+    let (x, y) = if horizontal { (best_offset as u32, 0) } else { (0, best_offset as u32) };
+    let cropped = decoded.img.crop_imm(x, y, crop_w, crop_h);
 
     let mut output = Vec::new();
     cropped.write_to(&mut Cursor::new(&mut output), decoded.format)
         .map_err(|e| format!("Failed to encode cropped image: {}", e))?;
 
+    Ok(CropResult { data: output, x, y, width: crop_w, height: crop_h })
+}
+
+/// The largest `target_w`:`target_h` rectangle inside a `width`x`height` image, placed
+/// so the normalized focal point (`focal_x`, `focal_y` in 0..1) is as close to its
+/// center as the image bounds allow. Pure math with no decoding, so a frontend can
+/// preview the crop overlay and pass the result to `crop_image`.
+#[wasm_bindgen]
+pub fn compute_crop_rect(
+    width: u32,
+    height: u32,
+    target_w: u32,
+    target_h: u32,
+    focal_x: f32,
+    focal_y: f32
+) -> Result<CropRect, String> {
+    if width == 0 || height == 0 || target_w == 0 || target_h == 0 {
+        return Err(format!("Invalid crop of {}x{} to {}x{}", width, height, target_w, target_h));
+    }
+    if !(0.0..=1.0).contains(&focal_x) || !(0.0..=1.0).contains(&focal_y) {
+        return Err(format!("Focal point ({}, {}) must be within 0..1", focal_x, focal_y));
+    }
+
+    let (crop_w, crop_h) = fit_aspect(width, height, target_w, target_h);
+    let place = |focal: f32, len: u32, window: u32| {
+        let start = (focal as f64 * len as f64 - window as f64 / 2.0).round();
+        start.clamp(0.0, (len - window) as f64) as u32
+    };
+
+    Ok(CropRect {
+        x: place(focal_x, width, crop_w),
+        y: place(focal_y, height, crop_h),
+        width: crop_w,
+        height: crop_h,
+    })
+}
+
+/// Cover `target_w`x`target_h` (scale to fill, then crop the overflow), keeping the
+/// normalized focal point (`focal_x`, `focal_y` in 0..1) as close to the center of the
+/// result as the image bounds allow. Use this instead of a center crop when the
+/// subject's position is known; `compute_crop_rect` returns the rectangle used.
+#[wasm_bindgen]
+pub fn resize_cover_focal(data: &[u8], target_w: u32, target_h: u32, focal_x: f32, focal_y: f32) -> Result<Vec<u8>, String> {
+    check_dimensions(target_w, target_h)?;
+
+    let decoded = decode_image(data)?;
+    let rect = compute_crop_rect(decoded.img.width(), decoded.img.height(), target_w, target_h, focal_x, focal_y)?;
+
+    let cropped = decoded.img.crop_imm(rect.x, rect.y, rect.width, rect.height);
+    let resized = resize_with_filter(&cropped, target_w, target_h, "lanczos3");
+
+    let mut output = Vec::new();
+    resized.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode resized image: {}", e))?;
+
     Ok(output)
 }
 
+/// How `resize` maps the image onto the requested box
+#[derive(Clone, Copy, PartialEq)]
+enum ResizeMode {
+    /// Stretch to exactly the box, ignoring aspect ratio
+    Exact,
+    /// Scale to fit inside the box, keeping aspect ratio; the result may be smaller
+    Fit,
+    /// Scale to fill the box, keeping aspect ratio, and center-crop the overflow
+    Cover,
+    /// Like `Fit`, then letterbox to exactly the box size
+    Contain,
+}
+
+impl ResizeMode {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "" | "exact" => Ok(ResizeMode::Exact),
+            "fit" => Ok(ResizeMode::Fit),
+            "cover" => Ok(ResizeMode::Cover),
+            "contain" => Ok(ResizeMode::Contain),
+            _ => Err(format!("Invalid resize mode: {}", name)),
+        }
+    }
+}
+
+/// Resize into a `width`x`height` box with one of the modes "exact" (stretch),
+/// "fit" (fit inside, keeping aspect ratio; may upscale), "cover" (fill and
+/// center-crop) or "contain" (fit inside, then pad to the box with transparency,
+/// or white for formats without alpha). The result carries the final dimensions.
+/// With `even_dimensions` the output size is rounded down to even numbers.
+#[wasm_bindgen]
+pub fn resize(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    filter: &str,
+    mode: &str,
+    even_dimensions: bool
+) -> Result<TransformResult, String> {
+    resize_with_mode(data, width, height, filter, ResizeMode::parse(mode)?, false, even_dimensions)
+}
+
+fn resize_with_mode(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    filter: &str,
+    mode: ResizeMode,
+    high_quality: bool,
+    even_dimensions: bool
+) -> Result<TransformResult, String> {
+    check_dimensions(width, height)?;
+    // Fit picks its size from the source aspect ratio, the other modes fill the box
+    let (width, height) = if even_dimensions && mode != ResizeMode::Fit {
+        snap_even(width, height, width as f64 / height as f64)?
+    } else {
+        (width, height)
+    };
+
+    let mut timer = StageTimer::start("resize");
+    let decoded = decode_for_size(data, width, height)?;
+    timer.stage("decode");
+
+    let scale = |img: &DynamicImage, w: u32, h: u32| if high_quality {
+        resize_high_quality(img, w, h, filter)
+    } else {
+        resize_with_filter(img, w, h, filter)
+    };
+
+    let (src_w, src_h) = (decoded.img.width(), decoded.img.height());
+    let fit_size = || {
+        let ratio = (width as f64 / src_w as f64).min(height as f64 / src_h as f64);
+        (
+            ((src_w as f64 * ratio).round() as u32).clamp(1, width),
+            ((src_h as f64 * ratio).round() as u32).clamp(1, height),
+        )
+    };
+    let resized = match mode {
+        ResizeMode::Exact => scale(&decoded.img, width, height),
+        ResizeMode::Fit => {
+            let (w, h) = fit_size();
+            let (w, h) = if even_dimensions { snap_even(w, h, src_w as f64 / src_h as f64)? } else { (w, h) };
+            scale(&decoded.img, w, h)
+        }
+        ResizeMode::Cover => {
+            let rect = compute_crop_rect(src_w, src_h, width, height, 0.5, 0.5)?;
+            scale(&decoded.img.crop_imm(rect.x, rect.y, rect.width, rect.height), width, height)
+        }
+        ResizeMode::Contain => {
+            let (w, h) = fit_size();
+            let fitted = scale(&decoded.img, w, h);
+            let transparent = supports_alpha(decoded.format);
+            let fill = if transparent { Rgba([0, 0, 0, 0]) } else { Rgba([255, 255, 255, 255]) };
+            let mut canvas = RgbaImage::from_pixel(width, height, fill);
+            image::imageops::overlay(&mut canvas, &fitted.to_rgba8(), ((width - w) / 2) as i64, ((height - h) / 2) as i64);
+            let canvas = DynamicImage::ImageRgba8(canvas);
+            if transparent { canvas } else { convert_color(&canvas, decoded.img.color()) }
+        }
+    };
+    timer.stage("process");
+
+    let (final_w, final_h) = (resized.width(), resized.height());
+    let mut output = Vec::new();
+    resized.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode resized image: {}", e))?;
+    timer.stage("encode");
+    timer.finish();
+
+    Ok(TransformResult { data: output, width: final_w, height: final_h })
+}
+
+/// Resize to exactly `new_width`x`new_height`; `resize` with mode "exact".
+/// `high_quality` enables a box-filter prefilter pass for large reductions (see
+/// `resize_high_quality`). Large JPEGs are decoded at reduced scale when the target
+/// allows it (see `decode_for_size`). With `even_dimensions` the target is first
+/// rounded down to even numbers.
 #[wasm_bindgen]
 pub fn resize_image(
     data: &[u8],
     new_width: u32,
     new_height: u32,
+    filter: &str,
+    high_quality: bool,
+    even_dimensions: bool
+) -> Result<Vec<u8>, String> {
+    resize_with_mode(data, new_width, new_height, filter, ResizeMode::Exact, high_quality, even_dimensions)
+        .map(|result| result.data)
+}
+
+/// Crop a region and scale it to `out_width`x`out_height` with a single decode, e.g. to
+/// cut map tiles
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn crop_resize(
+    data: &[u8],
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    out_width: u32,
+    out_height: u32,
     filter: &str
 ) -> Result<Vec<u8>, String> {
+    check_dimensions(out_width, out_height)?;
+
+    let mut timer = StageTimer::start("crop_resize");
     let decoded = decode_image(data)?;
+    timer.stage("decode");
 
-    let filter_type = match filter {
-        "nearest" => image::imageops::FilterType::Nearest,
-        "triangle" => image::imageops::FilterType::Triangle,
-        "catmull_rom" => image::imageops::FilterType::CatmullRom,
-        "gaussian" => image::imageops::FilterType::Gaussian,
-        _ => image::imageops::FilterType::Lanczos3,
+    check_region(x, y, width, height, decoded.img.width(), decoded.img.height())?;
+    let cropped = decoded.img.crop_imm(x, y, width, height);
+    let resized = resize_with_filter(&cropped, out_width, out_height, filter);
+    timer.stage("process");
+
+    let mut output = Vec::new();
+    resized.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode tile: {}", e))?;
+    timer.stage("encode");
+    timer.finish();
+
+    Ok(output)
+}
+
+/// Slice the image into a grid of `tile_w`x`tile_h` tiles for large-image viewers,
+/// decoding once. Tiles are ordered row-major (left to right, then top to bottom);
+/// tiles on the right and bottom edges are smaller when the size does not divide
+/// evenly. An empty `format` keeps the source format.
+#[wasm_bindgen]
+pub fn split_tiles(data: &[u8], tile_w: u32, tile_h: u32, format: &str) -> Result<Vec<Tile>, String> {
+    if tile_w == 0 || tile_h == 0 {
+        return Err(format!("Invalid tile size {}x{}", tile_w, tile_h));
+    }
+
+    let decoded = decode_image(data)?;
+    let format = if format.is_empty() { decoded.format } else { parse_format(format)? };
+    let (width, height) = (decoded.img.width(), decoded.img.height());
+
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(tile_h as usize) {
+        for x in (0..width).step_by(tile_w as usize) {
+            let (w, h) = (tile_w.min(width - x), tile_h.min(height - y));
+            let mut bytes = Vec::new();
+            decoded.img.crop_imm(x, y, w, h).write_to(&mut Cursor::new(&mut bytes), format)
+                .map_err(|e| format!("Failed to encode tile at ({}, {}): {}", x, y, e))?;
+            tiles.push(Tile { x, y, width: w, height: h, bytes });
+        }
+    }
+
+    Ok(tiles)
+}
+
+/// Scale down to the largest size that fits inside both `max_width` and `max_height`,
+/// preserving aspect ratio. Images that already fit are never upscaled. With
+/// `even_dimensions` both sides are rounded down to even numbers for video encoders.
+#[wasm_bindgen]
+pub fn resize_within(
+    data: &[u8],
+    max_width: u32,
+    max_height: u32,
+    filter: &str,
+    even_dimensions: bool
+) -> Result<TransformResult, String> {
+    if max_width == 0 || max_height == 0 {
+        return Err(format!("Invalid size constraint {}x{}", max_width, max_height));
+    }
+
+    let mut timer = StageTimer::start("resize_within");
+    let decoded = decode_image(data)?;
+    timer.stage("decode");
+
+    let (width, height) = (decoded.img.width(), decoded.img.height());
+    let scale = (max_width as f64 / width as f64)
+        .min(max_height as f64 / height as f64)
+        .min(1.0);
+
+    let mut new_width = ((width as f64 * scale).round() as u32).clamp(1, max_width);
+    let mut new_height = ((height as f64 * scale).round() as u32).clamp(1, max_height);
+    if even_dimensions {
+        (new_width, new_height) = snap_even(new_width, new_height, width as f64 / height as f64)?;
+    }
+
+    let resized = if (new_width, new_height) == (width, height) {
+        decoded.img
+    } else {
+        resize_with_filter(&decoded.img, new_width, new_height, filter)
     };
+    timer.stage("process");
 
-    let resized = decoded.img.resize_exact(new_width, new_height, filter_type);
+    let mut output = Vec::new();
+    resized.write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode resized image: {}", e))?;
+    timer.stage("encode");
+    timer.finish();
+
+    Ok(TransformResult { data: output, width: new_width, height: new_height })
+}
+
+/// Resize to a print size given in inches at `dpi` (e.g. 6x4 in at 300 DPI is
+/// 1800x1200 px). JPEG and PNG output also record the DPI in their resolution tags.
+/// With `even_dimensions` both sides are rounded down to even numbers.
+#[wasm_bindgen]
+pub fn resize_for_print(
+    data: &[u8],
+    width_in: f32,
+    height_in: f32,
+    dpi: u32,
+    filter: &str,
+    even_dimensions: bool
+) -> Result<TransformResult, String> {
+    if !(width_in.is_finite() && height_in.is_finite() && width_in > 0.0 && height_in > 0.0) || dpi == 0 {
+        return Err(format!("Invalid print size {}x{} in at {} DPI", width_in, height_in, dpi));
+    }
+
+    let mut new_width = ((width_in as f64 * dpi as f64).round() as u32).max(1);
+    let mut new_height = ((height_in as f64 * dpi as f64).round() as u32).max(1);
+    if even_dimensions {
+        (new_width, new_height) = snap_even(new_width, new_height, width_in as f64 / height_in as f64)?;
+    }
+    check_dimensions(new_width, new_height)?;
+
+    let decoded = decode_image(data)?;
+    let resized = resize_with_filter(&decoded.img, new_width, new_height, filter);
 
     let mut output = Vec::new();
     resized.write_to(&mut Cursor::new(&mut output), decoded.format)
         .map_err(|e| format!("Failed to encode resized image: {}", e))?;
+    let output = embed_dpi(output, decoded.format, dpi as f32, dpi as f32)?;
 
-    Ok(output)
+    Ok(TransformResult { data: output, width: new_width, height: new_height })
+}
+
+/// Resampling used for arbitrary-angle rotation. Nearest is fastest and keeps exact
+/// source colors (pixel art); bilinear reads 4 pixels and is the default; bicubic reads
+/// 16 and keeps photos sharper at roughly 3-4x the cost of bilinear.
+#[derive(Clone, Copy)]
+pub(crate) enum Interpolation {
+    Nearest,
+    Bilinear,
+    Bicubic,
+}
+
+impl Interpolation {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "nearest" => Ok(Interpolation::Nearest),
+            "" | "bilinear" => Ok(Interpolation::Bilinear),
+            "bicubic" => Ok(Interpolation::Bicubic),
+            _ => Err(format!("Invalid interpolation: {}", name)),
+        }
+    }
+
+    /// Sample an RGBA image at fractional pixel coordinates (pixel centers at integers),
+    /// treating everything outside the image as `bg`. Colors are blended premultiplied by
+    /// alpha, so transparent neighbors (e.g. a transparent `bg`) do not darken edges.
+    fn sample(self, img: &RgbaImage, x: f32, y: f32, bg: Rgba<u8>) -> Rgba<u8> {
+        let (width, height) = img.dimensions();
+        let premultiply = |p: Rgba<u8>| -> [f32; 4] {
+            let a = p.0[3] as f32 / 255.0;
+            [p.0[0] as f32 * a, p.0[1] as f32 * a, p.0[2] as f32 * a, p.0[3] as f32]
+        };
+        let fetch = |px: f32, py: f32| -> [f32; 4] {
+            if px < 0.0 || py < 0.0 || px >= width as f32 || py >= height as f32 {
+                return premultiply(bg);
+            }
+            premultiply(*img.get_pixel(px as u32, py as u32))
+        };
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let value = match self {
+            Interpolation::Nearest => fetch(x.round(), y.round()),
+            Interpolation::Bilinear => {
+                let p00 = fetch(x0, y0);
+                let p10 = fetch(x0 + 1.0, y0);
+                let p01 = fetch(x0, y0 + 1.0);
+                let p11 = fetch(x0 + 1.0, y0 + 1.0);
+
+                let mut out = [0.0; 4];
+                for c in 0..4 {
+                    let top = p00[c] + (p10[c] - p00[c]) * fx;
+                    let bottom = p01[c] + (p11[c] - p01[c]) * fx;
+                    out[c] = top + (bottom - top) * fy;
+                }
+                out
+            }
+            Interpolation::Bicubic => {
+                let wx = [-1.0, 0.0, 1.0, 2.0].map(|i: f32| cubic_weight(i - fx));
+                let wy = [-1.0, 0.0, 1.0, 2.0].map(|i: f32| cubic_weight(i - fy));
+
+                let mut out = [0.0; 4];
+                for (j, weight_y) in wy.iter().enumerate() {
+                    for (i, weight_x) in wx.iter().enumerate() {
+                        let p = fetch(x0 + i as f32 - 1.0, y0 + j as f32 - 1.0);
+                        for c in 0..4 {
+                            out[c] += p[c] * weight_x * weight_y;
+                        }
+                    }
+                }
+                out
+            }
+        };
+
+        let alpha = value[3].clamp(0.0, 255.0);
+        let unpremultiply = |c: f32| if alpha > 0.0 { c * 255.0 / alpha } else { 0.0 };
+        Rgba([
+            unpremultiply(value[0]),
+            unpremultiply(value[1]),
+            unpremultiply(value[2]),
+            alpha,
+        ].map(|c| c.round().clamp(0.0, 255.0) as u8))
+    }
+}
+
+/// Catmull-Rom cubic convolution kernel (a = -0.5)
+fn cubic_weight(t: f32) -> f32 {
+    let t = t.abs();
+    if t < 1.0 {
+        1.5 * t * t * t - 2.5 * t * t + 1.0
+    } else if t < 2.0 {
+        -0.5 * t * t * t + 2.5 * t * t - 4.0 * t + 2.0
+    } else {
+        0.0
+    }
 }
 
+/// Size of the canvas that fits a `width`x`height` image rotated by `degrees`
+fn rotated_size(width: u32, height: u32, degrees: f32) -> (u32, u32) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let new_width = (width as f32 * cos.abs() + height as f32 * sin.abs()).ceil().max(1.0) as u32;
+    let new_height = (width as f32 * sin.abs() + height as f32 * cos.abs()).ceil().max(1.0) as u32;
+    (new_width, new_height)
+}
+
+/// Rotate by an arbitrary angle (clockwise), expanding the canvas to fit and filling
+/// the uncovered corners with `bg`
+fn rotate_affine(img: &DynamicImage, degrees: f32, bg: Rgba<u8>, interpolation: Interpolation) -> DynamicImage {
+    let src = img.to_rgba8();
+    let (width, height) = src.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (new_width, new_height) = rotated_size(width, height, degrees);
+
+    let src_cx = width as f32 / 2.0;
+    let src_cy = height as f32 / 2.0;
+    let dst_cx = new_width as f32 / 2.0;
+    let dst_cy = new_height as f32 / 2.0;
+
+    let mut output = RgbaImage::new(new_width, new_height);
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        // Map the output pixel center back into the source (inverse rotation)
+        let dx = x as f32 + 0.5 - dst_cx;
+        let dy = y as f32 + 0.5 - dst_cy;
+        let sx = dx * cos + dy * sin + src_cx - 0.5;
+        let sy = -dx * sin + dy * cos + src_cy - 0.5;
+        *pixel = interpolation.sample(&src, sx, sy, bg);
+    }
+
+    DynamicImage::ImageRgba8(output)
+}
+
+/// Rotate clockwise by `degrees`: multiples of 90 move pixels losslessly, other
+/// angles are resampled with `interpolation` and `bg` filling the uncovered corners.
+/// When `format` cannot store alpha, a translucent `bg` is composited over white (so
+/// "transparent" fills white, as `resize`'s contain mode does). The result keeps the
+/// source color type unless transparent corners need an alpha channel.
+pub(crate) fn rotate_dynamic(
+    img: &DynamicImage,
+    degrees: f32,
+    bg: &str,
+    interpolation: &str,
+    format: ImageFormat
+) -> Result<DynamicImage, String> {
+    if !degrees.is_finite() {
+        return Err(format!("Invalid rotation angle: {}", degrees));
+    }
+    let interpolation = Interpolation::parse(interpolation)?;
+
+    let normalized = degrees.rem_euclid(360.0);
+    let quarter_turns = (normalized / 90.0).round();
+
+    if (normalized - quarter_turns * 90.0).abs() < 0.001 {
+        return Ok(match quarter_turns as u32 % 4 {
+            1 => img.rotate90(),
+            2 => img.rotate180(),
+            3 => img.rotate270(),
+            _ => img.clone(),
+        });
+    }
+
+    let (new_width, new_height) = rotated_size(img.width(), img.height(), normalized);
+    check_dimensions(new_width, new_height)?;
+
+    let fill = fill_for_format(parse_color(bg)?, format);
+    let rotated = rotate_affine(img, normalized, fill, interpolation);
+    Ok(restore_color(rotated, img, fill))
+}
+
+/// The color that fills uncovered areas when writing `format`: a translucent `fill`
+/// is composited over white for formats that cannot store alpha
+fn fill_for_format(mut fill: Rgba<u8>, format: ImageFormat) -> Rgba<u8> {
+    if !supports_alpha(format) {
+        let alpha = fill.0[3] as u32;
+        for c in &mut fill.0[..3] {
+            *c = ((*c as u32 * alpha + 255 * (255 - alpha) + 127) / 255) as u8;
+        }
+        fill.0[3] = 255;
+    }
+    fill
+}
+
+/// Convert a resampled RGBA result back to the source's color type, unless
+/// transparent fill needs the alpha channel the source lacks
+fn restore_color(result: DynamicImage, source: &DynamicImage, fill: Rgba<u8>) -> DynamicImage {
+    if fill.0[3] == 255 || source.color().has_alpha() {
+        convert_color(&result, source.color())
+    } else {
+        result
+    }
+}
+
+/// Rotate clockwise by `degrees`. Exact multiples of 90 are lossless pixel moves;
+/// any other angle is resampled onto a larger canvas filled with `bg`, using
+/// `interpolation` ("nearest", "bilinear" or "bicubic"; empty means bilinear).
+/// Formats without alpha (e.g. JPEG) get a translucent `bg` composited over white.
 #[wasm_bindgen]
-pub fn rotate_image(data: &[u8], direction: &str) -> Result<Vec<u8>, String> {
+pub fn rotate(data: &[u8], degrees: f32, bg: &str, interpolation: &str) -> Result<Vec<u8>, String> {
+    let mut timer = StageTimer::start("rotate");
     let decoded = decode_image(data)?;
+    timer.stage("decode");
 
-    let rotated = match direction {
-        "left" => decoded.img.rotate270(),   // 270° = 90° counter-clockwise
-        "right" => decoded.img.rotate90(),   // 90° = 90° clockwise
-        _ => return Err("Invalid rotation direction".to_string()),
-    };
+    let rotated = rotate_dynamic(&decoded.img, degrees, bg, interpolation, decoded.format)?;
+    timer.stage("process");
 
     let mut output = Vec::new();
     rotated.write_to(&mut Cursor::new(&mut output), decoded.format)
         .map_err(|e| format!("Failed to encode rotated image: {}", e))?;
+    timer.stage("encode");
+    timer.finish();
 
     Ok(output)
 }
+
+/// Largest axis-aligned rectangle inside a `width`x`height` image rotated by `degrees`
+fn inscribed_size(width: u32, height: u32, degrees: f32) -> (u32, u32) {
+    let (w, h) = (width as f64, height as f64);
+    let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+    let (sin, cos) = (sin.abs(), cos.abs());
+    let (long, short) = if w >= h { (w, h) } else { (h, w) };
+
+    let (crop_w, crop_h) = if short <= 2.0 * sin * cos * long || (sin - cos).abs() < 1e-10 {
+        // Half-constrained: two crop corners touch the longer side
+        let x = 0.5 * short;
+        if w >= h { (x / sin, x / cos) } else { (x / cos, x / sin) }
+    } else {
+        let cos_2a = cos * cos - sin * sin;
+        ((w * cos - h * sin) / cos_2a, (h * cos - w * sin) / cos_2a)
+    };
+
+    ((crop_w.floor() as u32).clamp(1, width), (crop_h.floor() as u32).clamp(1, height))
+}
+
+/// Level the horizon from the camera roll recorded in XMP (`GPano:PoseRollDegrees`,
+/// positive when the camera was rolled clockwise, which tilts the horizon
+/// counterclockwise in the picture): the image is rotated clockwise by that angle to
+/// cancel the tilt and cropped to the largest rectangle without filled corners. Images without
+/// roll metadata are returned unchanged. `interpolation` is as for `rotate` ("bicubic"
+/// suits photos; empty means bilinear).
+#[wasm_bindgen]
+pub fn auto_straighten_from_exif(data: &[u8], interpolation: &str) -> Result<Vec<u8>, String> {
+    Interpolation::parse(interpolation)?;
+    let Some(roll) = read_xmp_roll(data).filter(|roll| roll.abs() >= 0.01) else {
+        return Ok(data.to_vec());
+    };
+
+    let decoded = decode_image(data)?;
+    let (width, height) = (decoded.img.width(), decoded.img.height());
+    let rotated = rotate_dynamic(&decoded.img, roll, "transparent", interpolation, decoded.format)?;
+
+    // Stay a pixel inside the rotated edges, which are blended with the fill color
+    let (crop_w, crop_h) = inscribed_size(width, height, roll);
+    let (crop_w, crop_h) = (crop_w.saturating_sub(2).max(1), crop_h.saturating_sub(2).max(1));
+    let x = (rotated.width() - crop_w.min(rotated.width())) / 2;
+    let y = (rotated.height() - crop_h.min(rotated.height())) / 2;
+    let cropped = rotated.crop_imm(x, y, crop_w, crop_h);
+
+    let mut output = Vec::new();
+    convert_color(&cropped, decoded.img.color()).write_to(&mut Cursor::new(&mut output), decoded.format)
+        .map_err(|e| format!("Failed to encode straightened image: {}", e))?;
+
+    Ok(output)
+}
+
+/// Rotate by a quarter or half turn: "left", "right", or "180" / "flip" for
+/// upside-down scans. Pixels are moved losslessly, keeping the color type.
+#[wasm_bindgen]
+pub fn rotate_image(data: &[u8], direction: &str) -> Result<Vec<u8>, String> {
+    match direction {
+        "left" => rotate(data, 270.0, "transparent", ""),   // 270° = 90° counter-clockwise
+        "right" => rotate(data, 90.0, "transparent", ""),   // 90° = 90° clockwise
+        "180" | "flip" => rotate(data, 180.0, "transparent", ""),
+        _ => Err("Invalid rotation direction".to_string()),
+    }
+}
+
+/// Solve the 8 coefficients of the homography mapping each `from` point onto the
+/// matching `to` point (Gaussian elimination with partial pivoting)
+fn solve_homography(from: [(f64, f64); 4], to: [(f64, f64); 4]) -> Option<[f64; 8]> {
+    let mut rows = [[0.0f64; 9]; 8];
+    for (i, (&(u, v), &(x, y))) in from.iter().zip(&to).enumerate() {
+        rows[2 * i] = [u, v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x, x];
+        rows[2 * i + 1] = [0.0, 0.0, 0.0, u, v, 1.0, -u * y, -v * y, y];
+    }
+
+    for col in 0..8 {
+        let pivot = (col..8).max_by(|&a, &b| rows[a][col].abs().total_cmp(&rows[b][col].abs()))?;
+        if rows[pivot][col].abs() < 1e-10 {
+            return None;
+        }
+        rows.swap(col, pivot);
+        for row in 0..8 {
+            if row != col {
+                let factor = rows[row][col] / rows[col][col];
+                let pivot_row = rows[col];
+                for (value, &p) in rows[row].iter_mut().zip(&pivot_row).skip(col) {
+                    *value -= factor * p;
+                }
+            }
+        }
+    }
+
+    Some(std::array::from_fn(|i| rows[i][8] / rows[i][i]))
+}
+
+/// Four-point perspective correction, e.g. to flatten a photographed document.
+/// `corners` holds the source quadrilateral as x,y pairs in the order top-left,
+/// top-right, bottom-right, bottom-left; it is mapped onto an `out_width`x`out_height`
+/// rectangle with bilinear sampling, and areas outside the source are filled with `bg`
+/// (composited over white for formats without alpha, as in `rotate`).
+#[wasm_bindgen]
+pub fn perspective_transform(data: &[u8], corners: Vec<f32>, out_width: u32, out_height: u32, bg: &str) -> Result<Vec<u8>, String> {
+    if corners.len() != 8 || corners.iter().any(|c| !c.is_finite()) {
+        return Err("Corners must be 8 finite numbers (x,y for TL, TR, BR, BL)".to_string());
+    }
+    check_dimensions(out_width, out_height)?;
+    let bg = parse_color(bg)?;
+
+    let (w, h) = (out_width as f64, out_height as f64);
+    let source: [(f64, f64); 4] = std::array::from_fn(|i| (corners[2 * i] as f64, corners[2 * i + 1] as f64));
+    let h_coeffs = solve_homography([(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)], source)
+        .ok_or("Corners are degenerate (three or more are collinear)")?;
+
+    let decoded = decode_image(data)?;
+    let src = decoded.img.to_rgba8();
+    let fill = fill_for_format(bg, decoded.format);
+
+    let output = RgbaImage::from_fn(out_width, out_height, |x, y| {
+        let (u, v) = (x as f64 + 0.5, y as f64 + 0.5);
+        let denominator = h_coeffs[6] * u + h_coeffs[7] * v + 1.0;
+        let sx = (h_coeffs[0] * u + h_coeffs[1] * v + h_coeffs[2]) / denominator;
+        let sy = (h_coeffs[3] * u + h_coeffs[4] * v + h_coeffs[5]) / denominator;
+        Interpolation::Bilinear.sample(&src, (sx - 0.5) as f32, (sy - 0.5) as f32, fill)
+    });
+
+    let mut output_bytes = Vec::new();
+    restore_color(DynamicImage::ImageRgba8(output), &decoded.img, fill)
+        .write_to(&mut Cursor::new(&mut output_bytes), decoded.format)
+        .map_err(|e| format!("Failed to encode transformed image: {}", e))?;
+
+    Ok(output_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::encode_png_fixture;
+    use image::{Rgb, RgbImage};
+
+    fn encode(img: DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), format).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn rotate_fills_jpeg_corners_with_white_for_transparent_bg() {
+        let white = encode(DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 48, Rgb([255, 255, 255]))), ImageFormat::Jpeg);
+        let rotated = image::load_from_memory(&rotate(&white, 30.0, "transparent", "bilinear").unwrap()).unwrap();
+
+        assert_eq!(rotated.color(), image::ColorType::Rgb8);
+        let corner = rotated.to_rgb8().get_pixel(0, 0).0;
+        assert!(corner.iter().all(|&c| c >= 250), "corner {:?}", corner);
+    }
+
+    #[test]
+    fn rotate_rejects_canvas_expanded_past_the_pixel_limit() {
+        // A 100000x1 strip is tiny, but at 45 degrees its bounding square is ~70711 px a side
+        let strip = DynamicImage::ImageRgb8(RgbImage::new(100_000, 1));
+        assert_eq!(rotated_size(100_000, 1, 45.0), (70712, 70712));
+
+        let err = rotate_dynamic(&strip, 45.0, "white", "bilinear", ImageFormat::Png).unwrap_err();
+        assert!(err.contains("exceed the limit"), "{}", err);
+        assert!(rotate_dynamic(&strip, 90.0, "white", "bilinear", ImageFormat::Png).is_ok());
+    }
+
+    #[test]
+    fn perspective_fills_jpeg_outside_with_white_for_transparent_bg() {
+        let white = encode(DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 48, Rgb([255, 255, 255]))), ImageFormat::Jpeg);
+        // A quad reaching past the left edge of the source
+        let corners = vec![-32.0, 0.0, 64.0, 0.0, 64.0, 48.0, -32.0, 48.0];
+        let output = perspective_transform(&white, corners, 48, 24, "transparent").unwrap();
+        let output = image::load_from_memory(&output).unwrap();
+
+        assert_eq!(output.color(), image::ColorType::Rgb8);
+        let outside = output.to_rgb8().get_pixel(2, 12).0;
+        assert!(outside.iter().all(|&c| c >= 250), "outside {:?}", outside);
+    }
+
+    #[test]
+    fn nearest_keeps_exact_source_colors() {
+        let src = RgbaImage::from_fn(5, 4, |x, y| Rgba([(x * 50) as u8, (y * 60) as u8, 17, 200]));
+        for y in 0..4 {
+            for x in 0..5 {
+                let sampled = Interpolation::Nearest.sample(&src, x as f32, y as f32, Rgba([0, 0, 0, 0]));
+                assert_eq!(sampled, *src.get_pixel(x, y));
+            }
+        }
+
+        let rotated = rotate_affine(&DynamicImage::ImageRgba8(src.clone()), 30.0, Rgba([0, 0, 0, 0]), Interpolation::Nearest);
+        let source_colors: Vec<_> = src.pixels().collect();
+        assert!(rotated.to_rgba8().pixels().all(|p| p.0[3] == 0 || source_colors.contains(&p)));
+    }
+
+    #[test]
+    fn bilinear_edges_against_transparent_fill_keep_their_color() {
+        let white = DynamicImage::ImageRgba8(RgbaImage::from_pixel(40, 40, Rgba([255, 255, 255, 255])));
+        for interpolation in [Interpolation::Bilinear, Interpolation::Bicubic] {
+            let rotated = rotate_affine(&white, 30.0, Rgba([0, 0, 0, 0]), interpolation).to_rgba8();
+            for pixel in rotated.pixels().filter(|p| p.0[3] > 0) {
+                assert!(pixel.0[..3].iter().all(|&c| c >= 254), "{:?}", pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn box_downscale_of_checker_is_mid_gray() {
+        let checker = RgbaImage::from_fn(2, 2, |x, y| {
+            if (x + y) % 2 == 0 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) }
+        });
+        let resized = resize_box(&DynamicImage::ImageRgba8(checker), 1, 1).to_rgba8();
+        assert_eq!(resized.get_pixel(0, 0), &Rgba([128, 128, 128, 255]));
+    }
+
+    #[test]
+    fn snap_even_never_exceeds_the_requested_size() {
+        assert_eq!(snap_even(101, 57, 101.0 / 57.0).unwrap(), (100, 56));
+        assert_eq!(snap_even(2, 2, 1.0).unwrap(), (2, 2));
+        assert!(snap_even(1, 40, 0.025).is_err());
+    }
+
+    #[test]
+    fn even_resize_respects_bounds_and_source_size() {
+        let data = encode_png_fixture(RgbaImage::from_pixel(33, 21, Rgba([1, 2, 3, 255])));
+        let within = resize_within(&data, 17, 17, "triangle", true).unwrap();
+        assert_eq!((within.width % 2, within.height % 2), (0, 0));
+        assert!(within.width <= 17 && within.height <= 17);
+        assert!(resize_within(&data, 1, 40, "triangle", true).is_err());
+
+        let tiny = encode_png_fixture(RgbaImage::from_pixel(1, 1, Rgba([1, 2, 3, 255])));
+        assert!(resize_within(&tiny, 10, 10, "triangle", true).is_err());
+
+        let fit = resize(&data, 15, 15, "triangle", "fit", true).unwrap();
+        assert_eq!((fit.width, fit.height), (14, 10));
+        let exact = image::load_from_memory(&resize_image(&data, 15, 9, "triangle", false, true).unwrap()).unwrap();
+        assert_eq!((exact.width(), exact.height()), (14, 8));
+    }
+
+    #[test]
+    fn high_quality_downscale_reduces_aliasing() {
+        // One-pixel stripes: any correct reduction is a flat gray at the mean (85)
+        let stripes = DynamicImage::ImageRgba8(RgbaImage::from_fn(300, 300, |x, _| {
+            if x % 3 == 0 { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) }
+        }));
+        let aliasing = |img: DynamicImage| -> f64 {
+            let gray = img.to_luma8();
+            gray.pixels().map(|p| (p.0[0] as f64 - 85.0).abs()).sum::<f64>() / gray.len() as f64
+        };
+
+        let single = aliasing(resize_with_filter(&stripes, 20, 20, "nearest"));
+        let two_pass = aliasing(resize_high_quality(&stripes, 20, 20, "nearest"));
+        assert!(single > 50.0, "single pass {}", single);
+        assert!(two_pass < single / 4.0, "single pass {}, two pass {}", single, two_pass);
+    }
+
+    #[test]
+    fn resize_rejects_zero_and_oversized_targets() {
+        let data = encode_png_fixture(RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255])));
+        assert!(resize_image(&data, 0, 10, "triangle", false, false).unwrap_err().contains("Invalid dimensions"));
+        assert!(resize_image(&data, 10, 0, "triangle", false, false).is_err());
+        assert!(resize_image(&data, 100_000, 100_000, "triangle", false, false).unwrap_err().contains("exceed"));
+        assert!(resize_image(&data, 8, 8, "triangle", false, false).is_ok());
+    }
+
+    fn rect(r: CropRect) -> (u32, u32, u32, u32) {
+        (r.x, r.y, r.width, r.height)
+    }
+
+    #[test]
+    fn crop_rect_centers_the_largest_window() {
+        assert_eq!(rect(compute_crop_rect(1600, 900, 4, 5, 0.5, 0.5).unwrap()), (440, 0, 720, 900));
+        assert_eq!(rect(compute_crop_rect(900, 1600, 16, 9, 0.5, 0.5).unwrap()), (0, 547, 900, 506));
+        assert_eq!(rect(compute_crop_rect(800, 600, 4, 3, 0.2, 0.9).unwrap()), (0, 0, 800, 600));
+    }
+
+    #[test]
+    fn crop_rect_follows_and_clamps_the_focal_point() {
+        assert_eq!(rect(compute_crop_rect(1600, 900, 1, 1, 0.25, 0.5).unwrap()), (0, 0, 900, 900));
+        assert_eq!(rect(compute_crop_rect(1600, 900, 1, 1, 0.6, 0.5).unwrap()), (510, 0, 900, 900));
+        assert_eq!(rect(compute_crop_rect(1600, 900, 1, 1, 1.0, 0.0).unwrap()), (700, 0, 900, 900));
+        assert_eq!(rect(compute_crop_rect(900, 1600, 1, 1, 0.5, 1.0).unwrap()), (0, 700, 900, 900));
+    }
+
+    #[test]
+    fn crop_rect_rejects_invalid_input() {
+        assert!(compute_crop_rect(0, 900, 1, 1, 0.5, 0.5).is_err());
+        assert!(compute_crop_rect(1600, 900, 0, 1, 0.5, 0.5).is_err());
+        assert!(compute_crop_rect(1600, 900, 1, 1, 1.5, 0.5).is_err());
+        assert!(compute_crop_rect(1600, 900, 1, 1, 0.5, -0.1).is_err());
+        assert!(compute_crop_rect(1600, 900, 1, 1, f32::NAN, 0.5).is_err());
+    }
+
+    /// PNG with an iTXt XMP packet recording the camera roll
+    fn with_xmp_roll(png: &[u8], roll: f32) -> Vec<u8> {
+        let xmp = format!(
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+             <rdf:Description xmlns:GPano=\"http://ns.google.com/photos/1.0/panorama/\" \
+             GPano:PoseRollDegrees=\"{}\"/></rdf:RDF></x:xmpmeta>",
+            roll
+        );
+        let mut chunk = b"iTXtXML:com.adobe.xmp\0\0\0\0\0".to_vec();
+        chunk.extend_from_slice(xmp.as_bytes());
+
+        let mut output = png[..33].to_vec();
+        output.extend_from_slice(&((chunk.len() - 4) as u32).to_be_bytes());
+        output.extend_from_slice(&chunk);
+        output.extend_from_slice(&crc32fast::hash(&chunk).to_be_bytes());
+        output.extend_from_slice(&png[33..]);
+        output
+    }
+
+    /// Row of the darkest pixel in each column of the middle third
+    fn line_rows(img: &DynamicImage) -> Vec<u32> {
+        let gray = img.to_luma8();
+        (gray.width() / 3..gray.width() * 2 / 3)
+            .map(|x| (0..gray.height()).min_by_key(|&y| gray.get_pixel(x, y).0[0]).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn auto_straighten_levels_a_rolled_horizon() {
+        let level = DynamicImage::ImageRgb8(RgbImage::from_fn(240, 160, |_, y| {
+            if (79..=81).contains(&y) { Rgb([0, 0, 0]) } else { Rgb([255, 255, 255]) }
+        }));
+        // A camera rolled 8 degrees clockwise sees the horizon 8 degrees counterclockwise
+        let tilted = rotate_dynamic(&level, -8.0, "white", "bicubic", ImageFormat::Png).unwrap();
+        let rows = line_rows(&tilted);
+        assert!(rows.iter().max().unwrap() - rows.iter().min().unwrap() > 5);
+
+        let data = with_xmp_roll(&encode(tilted, ImageFormat::Png), 8.0);
+        let straightened = image::load_from_memory(&auto_straighten_from_exif(&data, "bicubic").unwrap()).unwrap();
+        let rows = line_rows(&straightened);
+        let spread = rows.iter().max().unwrap() - rows.iter().min().unwrap();
+        assert!(spread <= 1, "rows {:?}", rows);
+    }
+
+    #[test]
+    fn auto_straighten_without_roll_is_a_no_op() {
+        let data = encode(DynamicImage::ImageRgb8(RgbImage::new(8, 8)), ImageFormat::Png);
+        assert_eq!(auto_straighten_from_exif(&data, "").unwrap(), data);
+        assert!(auto_straighten_from_exif(&data, "lanczos").is_err());
+    }
+}